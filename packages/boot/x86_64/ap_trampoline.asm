@@ -0,0 +1,103 @@
+global ap_trampoline_start
+global ap_trampoline_end
+global ap_trampoline_page_table
+global ap_trampoline_stack
+global ap_trampoline_entry
+global ap_trampoline_argument
+global ap_trampoline_efer
+
+; Physical address smp/mod.rs copies the trampoline to. Application processors start in real
+; mode at the page given by the startup IPI, so it has to be below 1 MiB and page aligned.
+TRAMPOLINE equ 0x8000
+; Address of a trampoline label once copied
+%define RELOCATE(label) (TRAMPOLINE + (label) - ap_trampoline_start)
+
+; Takes an application processor from real mode to long mode with the kernel's page table, then
+; calls the entry point with the argument on the stack filled in by smp/mod.rs. Copied to
+; TRAMPOLINE before use, so every address goes through RELOCATE.
+section .rodata
+bits 16
+ap_trampoline_start:
+	cli
+	cld
+	xor ax, ax
+	mov ds, ax
+
+	lgdt [RELOCATE(gdt.pointer)]
+	mov eax, cr0
+	or eax, 1 ; protection enable
+	mov cr0, eax
+	jmp dword gdt.code32:RELOCATE(protected_mode)
+
+bits 32
+protected_mode:
+	mov ax, gdt.data
+	mov ds, ax
+	mov es, ax
+	mov ss, ax
+
+	; enable PAE-flag in cr4 (Physical Address Extension)
+	mov eax, cr4
+	or eax, 1 << 5
+	mov cr4, eax
+
+	; load the kernel's P4 table
+	mov eax, [RELOCATE(ap_trampoline_page_table)]
+	mov cr3, eax
+
+	; set the EFER MSR bits the bootstrap processor runs with, such as long mode, no-execute
+	; where supported and syscall
+	mov ecx, 0xC0000080
+	rdmsr
+	or eax, [RELOCATE(ap_trampoline_efer)]
+	wrmsr
+
+	; enable paging and write protection in the cr0 register
+	mov eax, cr0
+	or eax, (1 << 31) | (1 << 16)
+	mov cr0, eax
+
+	jmp gdt.code64:RELOCATE(long_mode)
+
+bits 64
+long_mode:
+	; load 0 into all data segment registers
+	xor ax, ax
+	mov ss, ax
+	mov ds, ax
+	mov es, ax
+
+	mov rsp, [RELOCATE(ap_trampoline_stack)]
+	mov rdi, [RELOCATE(ap_trampoline_argument)]
+	mov rax, [RELOCATE(ap_trampoline_entry)]
+	call rax
+.hang:
+	hlt
+	jmp .hang
+
+align 8
+gdt:
+	dq 0 ; zero entry
+.code32: equ $ - gdt
+	dq 0x00cf9a000000ffff ; 32 bit code segment covering 4 GiB
+.data: equ $ - gdt
+	dq 0x00cf92000000ffff ; data segment covering 4 GiB
+.code64: equ $ - gdt
+	dq (1<<43) | (1<<44) | (1<<47) | (1<<53) ; 64 bit code segment
+.pointer:
+	dw .pointer - gdt - 1
+	dd RELOCATE(gdt)
+
+; Filled in by smp/mod.rs before each startup IPI
+align 8
+ap_trampoline_page_table:
+	dq 0 ; physical address of the P4 table, below 4 GiB
+ap_trampoline_stack:
+	dq 0 ; stack top
+ap_trampoline_entry:
+	dq 0 ; extern "C" fn(usize) -> !
+ap_trampoline_argument:
+	dq 0 ; argument of the entry point
+ap_trampoline_efer:
+	dq 0 ; EFER bits to set, only the low 32 are used
+ap_trampoline_end:
@@ -0,0 +1,26 @@
+global switch_context
+
+section .text
+bits 64
+; Save the callee-saved registers of the current thread on its stack, store its stack pointer
+; in [rdi], then load the stack pointer in rsi and restore the registers saved there.
+; The System V ABI lets us clobber every other register across the call.
+switch_context:
+	pushfq
+	push rbp
+	push rbx
+	push r12
+	push r13
+	push r14
+	push r15
+	mov [rdi], rsp
+
+	mov rsp, rsi
+	pop r15
+	pop r14
+	pop r13
+	pop r12
+	pop rbx
+	pop rbp
+	popfq
+	ret
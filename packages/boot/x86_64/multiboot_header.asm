@@ -8,6 +8,11 @@ header_start:
 
     ; insert optional multiboot tags here
 
+    ; module alignment tag: load boot modules at page aligned addresses
+    dw 6    ; type
+    dw 0    ; flags
+    dd 8    ; size
+
     ; required end tag
     dw 0    ; type
     dw 0    ; flags
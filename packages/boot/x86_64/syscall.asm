@@ -0,0 +1,113 @@
+global syscall_entry
+global syscall_interrupt_entry
+extern syscall_dispatch
+
+; Offsets of the syscall fields of PerCpu in percpu.rs
+%define PERCPU_SYSCALL_KERNEL_STACK 8
+%define PERCPU_SYSCALL_USER_STACK 16
+; Model specific registers holding the GS base and the copy of it swapgs switches to
+%define IA32_GS_BASE 0xc0000101
+%define IA32_KERNEL_GS_BASE 0xc0000102
+
+section .text
+bits 64
+; Entered by the syscall instruction, with the user return address in rcx, the user flags in
+; r11 and interrupts masked. Switches to the kernel stack of the current thread, saves the
+; user registers as a SyscallFrame (see syscall.rs) and hands it to syscall_dispatch.
+syscall_entry:
+	; User mode may have changed the GS base, but IA32_KERNEL_GS_BASE still leads to the per-CPU
+	; data
+	swapgs
+	mov [gs:PERCPU_SYSCALL_USER_STACK], rsp
+	mov rsp, [gs:PERCPU_SYSCALL_KERNEL_STACK]
+
+	push qword [gs:PERCPU_SYSCALL_USER_STACK]
+	push rcx
+	push r11
+	push r15
+	push r14
+	push r13
+	push r12
+	push rbp
+	push rbx
+	push r9
+	push r8
+	push r10
+	push rdx
+	push rsi
+	push rdi
+	push rax
+
+	; The registers are saved, so the per-CPU data can go back into IA32_KERNEL_GS_BASE, where
+	; the kernel keeps its copy
+	mov ecx, IA32_GS_BASE
+	rdmsr
+	mov ecx, IA32_KERNEL_GS_BASE
+	wrmsr
+
+	mov rdi, rsp
+	call syscall_dispatch
+
+	pop rax
+	pop rdi
+	pop rsi
+	pop rdx
+	pop r10
+	pop r8
+	pop r9
+	pop rbx
+	pop rbp
+	pop r12
+	pop r13
+	pop r14
+	pop r15
+	pop r11
+	pop rcx
+	pop rsp
+	o64 sysret
+
+; Entered by int 0x80, with the CPU having switched to the kernel stack from the TSS and
+; pushed ss, rsp, rflags, cs and rip. Builds the same SyscallFrame as syscall_entry.
+syscall_interrupt_entry:
+	push qword [rsp + 24] ; user rsp
+	push qword [rsp + 8] ; user rip
+	push qword [rsp + 32] ; user rflags
+	push r15
+	push r14
+	push r13
+	push r12
+	push rbp
+	push rbx
+	push r9
+	push r8
+	push r10
+	push rdx
+	push rsi
+	push rdi
+	push rax
+
+	; The interrupt frame leaves the stack 8 bytes off the alignment the ABI requires
+	mov rdi, rsp
+	sub rsp, 8
+	call syscall_dispatch
+	add rsp, 8
+
+	pop rax
+	pop rdi
+	pop rsi
+	pop rdx
+	pop r10
+	pop r8
+	pop r9
+	pop rbx
+	pop rbp
+	pop r12
+	pop r13
+	pop r14
+	pop r15
+	; Copy rflags, rip and rsp back into the interrupt frame. The destination of pop is
+	; addressed after rsp has moved past the popped value.
+	pop qword [rsp + 32]
+	pop qword [rsp + 8]
+	pop qword [rsp + 24]
+	iretq
@@ -0,0 +1,32 @@
+global user_demo_start
+global user_demo_end
+
+; Position independent user mode program, copied into user space by usermode.rs. Uses the
+; system call numbers from syscall.rs.
+section .rodata
+bits 64
+user_demo_start:
+	; write(1, via_syscall, via_syscall_length)
+	mov eax, 1
+	mov edi, 1
+	lea rsi, [rel via_syscall]
+	mov edx, via_syscall_length
+	syscall
+
+	; write(1, via_interrupt, via_interrupt_length)
+	mov eax, 1
+	mov edi, 1
+	lea rsi, [rel via_interrupt]
+	mov edx, via_interrupt_length
+	int 0x80
+
+	; exit(0)
+	mov eax, 0
+	xor edi, edi
+	syscall
+
+via_syscall: db "Hello from user mode via syscall", 10
+via_syscall_length: equ $ - via_syscall
+via_interrupt: db "Hello from user mode via int 0x80", 10
+via_interrupt_length: equ $ - via_interrupt
+user_demo_end:
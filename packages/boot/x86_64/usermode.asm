@@ -0,0 +1,65 @@
+global enter_user_mode
+
+section .text
+bits 64
+; Drop to ring 3, continuing at rdi with the stack pointer in rsi. rdx holds the user code
+; segment selector and rcx the user data segment selector. Builds the frame iretq expects:
+; ss, rsp, rflags, cs, rip.
+enter_user_mode:
+	mov ds, cx
+	mov es, cx
+	push rcx
+	push rsi
+	push 0x202 ; interrupts enabled
+	push rdx
+	push rdi
+
+	; Don't leak kernel values to user mode
+	xor eax, eax
+	xor ebx, ebx
+	xor ecx, ecx
+	xor edx, edx
+	xor esi, esi
+	xor edi, edi
+	xor ebp, ebp
+	xor r8, r8
+	xor r9, r9
+	xor r10, r10
+	xor r11, r11
+	xor r12, r12
+	xor r13, r13
+	xor r14, r14
+	xor r15, r15
+	iretq
+
+global return_to_user_mode
+
+; Return to ring 3 with the user registers saved in the SyscallFrame at rdi, as if the system
+; call it describes had just returned. rsi holds the user code segment selector and rdx the user
+; data segment selector. The frame's offsets match SyscallFrame in syscall.rs.
+return_to_user_mode:
+	mov ds, dx
+	mov es, dx
+	push rdx
+	push qword [rdi + 120] ; rsp
+	push qword [rdi + 104] ; rflags
+	push rsi
+	push qword [rdi + 112] ; rip
+
+	; sysret leaves the return address in rcx and the flags in r11
+	mov rcx, [rdi + 112]
+	mov r11, [rdi + 104]
+	mov rax, [rdi]
+	mov rsi, [rdi + 16]
+	mov rdx, [rdi + 24]
+	mov r10, [rdi + 32]
+	mov r8, [rdi + 40]
+	mov r9, [rdi + 48]
+	mov rbx, [rdi + 56]
+	mov rbp, [rdi + 64]
+	mov r12, [rdi + 72]
+	mov r13, [rdi + 80]
+	mov r14, [rdi + 88]
+	mov r15, [rdi + 96]
+	mov rdi, [rdi + 8]
+	iretq
@@ -0,0 +1,120 @@
+//! Fixed ACPI description table, describing power management hardware
+use super::{read, Sdt};
+
+/// Signature of the FADT
+pub const SIGNATURE: &str = "FACP";
+/// Offset of the 32 bit address of the DSDT
+const DSDT: usize = 40;
+/// Offset of the interrupt used for ACPI events
+const SCI_INTERRUPT: usize = 46;
+/// Offset of the port ACPI enable and disable commands are written to
+const SMI_COMMAND: usize = 48;
+/// Offset of the command enabling ACPI mode
+const ACPI_ENABLE: usize = 52;
+/// Offset of the port of the PM1a control block
+const PM1A_CONTROL_BLOCK: usize = 64;
+/// Offset of the port of the PM1b control block
+const PM1B_CONTROL_BLOCK: usize = 68;
+/// Offset of the feature flags
+const FLAGS: usize = 112;
+/// Offset of the reset register
+const RESET_REGISTER: usize = 116;
+/// Offset of the value written to the reset register
+const RESET_VALUE: usize = 128;
+/// Offset of the 64 bit address of the DSDT, from ACPI 2.0 on
+const X_DSDT: usize = 140;
+/// Flag set if the reset register is supported
+const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+
+#[derive(Debug, Clone, Copy)]
+/// Typed view of the FADT
+pub struct Fadt {
+    /// The whole table, header included
+    data: &'static [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Location of a register in one of the ACPI address spaces
+pub struct GenericAddress {
+    /// Address space the register is in: 0 for memory, 1 for I/O ports, 2 for PCI configuration
+    pub address_space: u8,
+    /// Size of the register, in bits
+    pub bit_width: u8,
+    /// Offset of the register within the address, in bits
+    pub bit_offset: u8,
+    /// Access size: 1 for bytes up to 4 for quad words, 0 if undefined
+    pub access_size: u8,
+    /// Address of the register in its address space
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Read a generic address structure at offset
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    pub fn read(data: &[u8], offset: usize) -> Self {
+        Self {
+            address_space: read(data, offset, 1) as u8,
+            bit_width: read(data, offset + 1, 1) as u8,
+            bit_offset: read(data, offset + 2, 1) as u8,
+            access_size: read(data, offset + 3, 1) as u8,
+            address: read(data, offset + 4, 8),
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+impl Fadt {
+    /// Fadt constructor. Fields missing from short tables of old revisions read as zero.
+    pub fn new(table: &Sdt) -> Self {
+        Self { data: table.data }
+    }
+
+    /// Get the physical address of the DSDT, preferring the 64 bit field
+    pub fn dsdt_address(&self) -> u64 {
+        match read(self.data, X_DSDT, 8) {
+            0 => read(self.data, DSDT, 4),
+            address => address,
+        }
+    }
+
+    /// Get the interrupt used for ACPI events, in the 8259 numbering
+    pub fn sci_interrupt(&self) -> u16 {
+        read(self.data, SCI_INTERRUPT, 2) as u16
+    }
+
+    /// Get the port ACPI enable commands are written to, 0 if the system is always in ACPI mode
+    pub fn smi_command(&self) -> u32 {
+        read(self.data, SMI_COMMAND, 4) as u32
+    }
+
+    /// Get the command enabling ACPI mode
+    pub fn acpi_enable(&self) -> u8 {
+        read(self.data, ACPI_ENABLE, 1) as u8
+    }
+
+    /// Get the port of the PM1a control block
+    pub fn pm1a_control_block(&self) -> u32 {
+        read(self.data, PM1A_CONTROL_BLOCK, 4) as u32
+    }
+
+    /// Get the port of the PM1b control block, 0 if there is none
+    pub fn pm1b_control_block(&self) -> u32 {
+        read(self.data, PM1B_CONTROL_BLOCK, 4) as u32
+    }
+
+    /// Get the feature flags
+    pub fn flags(&self) -> u32 {
+        read(self.data, FLAGS, 4) as u32
+    }
+
+    /// Get the register resetting the system and the value to write to it, if supported
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        if self.flags() & RESET_REGISTER_SUPPORTED == 0 {
+            return None;
+        }
+        Some((
+            GenericAddress::read(self.data, RESET_REGISTER),
+            read(self.data, RESET_VALUE, 1) as u8,
+        ))
+    }
+}
@@ -0,0 +1,82 @@
+//! High precision event timer description table
+use super::{read, GenericAddress, Sdt};
+
+/// Signature of the HPET table
+pub const SIGNATURE: &str = "HPET";
+/// Offset of the event timer block id
+const EVENT_TIMER_BLOCK_ID: usize = 36;
+/// Offset of the base address
+const BASE_ADDRESS: usize = 40;
+/// Offset of the HPET number
+const HPET_NUMBER: usize = 52;
+/// Offset of the minimum clock tick in periodic mode
+const MINIMUM_TICK: usize = 53;
+/// Size of the table
+const SIZE: usize = 56;
+/// Block id bit set if the main counter is 64 bits wide
+const COUNTER_64_BIT: u32 = 1 << 13;
+/// Block id bit set if the timer can replace the PIT and RTC interrupts
+const LEGACY_REPLACEMENT: u32 = 1 << 15;
+
+#[derive(Debug, Clone, Copy)]
+/// Typed view of the HPET table
+pub struct Hpet {
+    /// The whole table, header included
+    data: &'static [u8],
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+impl Hpet {
+    /// Hpet constructor, None if the table is too short
+    pub fn new(table: &Sdt) -> Option<Self> {
+        if table.data.len() < SIZE {
+            return None;
+        }
+        Some(Self { data: table.data })
+    }
+
+    /// Get the event timer block id
+    fn block_id(&self) -> u32 {
+        read(self.data, EVENT_TIMER_BLOCK_ID, 4) as u32
+    }
+
+    /// Get the location of the timer's registers
+    pub fn base_address(&self) -> GenericAddress {
+        GenericAddress::read(self.data, BASE_ADDRESS)
+    }
+
+    /// Get the hardware revision of the timer
+    pub fn hardware_revision(&self) -> u8 {
+        self.block_id() as u8
+    }
+
+    /// Get the number of comparators of the timer
+    pub fn comparator_count(&self) -> u8 {
+        ((self.block_id() >> 8) & 0x1f) as u8 + 1
+    }
+
+    /// Check if the main counter is 64 bits wide
+    pub fn has_64_bit_counter(&self) -> bool {
+        self.block_id() & COUNTER_64_BIT != 0
+    }
+
+    /// Check if the timer can replace the PIT and RTC interrupts
+    pub fn supports_legacy_replacement(&self) -> bool {
+        self.block_id() & LEGACY_REPLACEMENT != 0
+    }
+
+    /// Get the PCI vendor id of the timer
+    pub fn vendor_id(&self) -> u16 {
+        (self.block_id() >> 16) as u16
+    }
+
+    /// Get the sequence number of this timer block
+    pub fn hpet_number(&self) -> u8 {
+        self.data[HPET_NUMBER]
+    }
+
+    /// Get the minimum number of ticks between periodic interrupts
+    pub fn minimum_tick(&self) -> u16 {
+        read(self.data, MINIMUM_TICK, 2) as u16
+    }
+}
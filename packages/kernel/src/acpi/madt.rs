@@ -0,0 +1,202 @@
+//! Multiple APIC description table, listing processors and interrupt controllers
+use super::{read, Sdt};
+
+/// Signature of the MADT
+pub const SIGNATURE: &str = "APIC";
+/// Offset of the local APIC address
+const LOCAL_APIC_ADDRESS: usize = 36;
+/// Offset of the flags
+const FLAGS: usize = 40;
+/// Offset of the first entry
+const ENTRIES: usize = 44;
+/// Flag set if the system also has 8259 PICs, which need to be masked before using the APICs
+const PCAT_COMPAT: u32 = 1;
+/// Flag set in the entries of processors that are usable
+const PROCESSOR_ENABLED: u32 = 1;
+
+/// Entry type of a processor's local APIC
+const TYPE_LOCAL_APIC: u8 = 0;
+/// Entry type of an I/O APIC
+const TYPE_IO_APIC: u8 = 1;
+/// Entry type of an interrupt source override
+const TYPE_INTERRUPT_OVERRIDE: u8 = 2;
+/// Entry type of a local APIC NMI pin
+const TYPE_LOCAL_APIC_NMI: u8 = 4;
+/// Entry type of a local APIC address override
+const TYPE_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+/// Entry type of a processor's local x2APIC
+const TYPE_LOCAL_X2APIC: u8 = 9;
+
+#[derive(Debug, Clone, Copy)]
+/// Typed view of the MADT
+pub struct Madt {
+    /// The whole table, header included
+    data: &'static [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An entry of the MADT
+pub enum MadtEntry {
+    /// A processor and its local APIC
+    LocalApic {
+        /// ACPI id of the processor
+        processor_id: u8,
+        /// Id of its local APIC
+        apic_id: u8,
+        /// Whether the processor can be started
+        enabled: bool,
+    },
+    /// An I/O APIC
+    IoApic {
+        /// Id of the I/O APIC
+        id: u8,
+        /// Physical address of its registers
+        address: u32,
+        /// First global system interrupt it handles
+        gsi_base: u32,
+    },
+    /// An ISA interrupt wired to a different global system interrupt
+    InterruptOverride {
+        /// Bus of the source, 0 for ISA
+        bus: u8,
+        /// ISA interrupt number
+        source: u8,
+        /// Global system interrupt it is wired to
+        gsi: u32,
+        /// Polarity and trigger mode
+        flags: u16,
+    },
+    /// A local APIC interrupt pin wired to the non-maskable interrupt
+    LocalApicNmi {
+        /// ACPI id of the processor, 0xff for all processors
+        processor_id: u8,
+        /// Polarity and trigger mode
+        flags: u16,
+        /// Local interrupt pin, 0 or 1
+        lint: u8,
+    },
+    /// 64 bit address of the local APICs, replacing the one in the header
+    LocalApicAddressOverride {
+        /// Physical address of the local APIC registers
+        address: u64,
+    },
+    /// A processor whose local APIC is in x2APIC mode
+    LocalX2Apic {
+        /// Id of its local x2APIC
+        x2apic_id: u32,
+        /// ACPI id of the processor
+        uid: u32,
+        /// Whether the processor can be started
+        enabled: bool,
+    },
+    /// An entry of a type that isn't decoded
+    Other {
+        /// Entry type
+        kind: u8,
+        /// Length of the entry, in bytes
+        length: u8,
+    },
+}
+
+/// Iterator over the entries of the MADT
+pub struct MadtEntries {
+    /// The whole table, header included
+    data: &'static [u8],
+    /// Offset of the next entry
+    offset: usize,
+}
+
+impl Madt {
+    /// Madt constructor, None if the table is too short to hold the fixed fields
+    pub fn new(table: &Sdt) -> Option<Self> {
+        if table.data.len() < ENTRIES {
+            return None;
+        }
+        Some(Self { data: table.data })
+    }
+
+    /// Get the physical address of the local APIC registers
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .filter_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_else(|| read(self.data, LOCAL_APIC_ADDRESS, 4))
+    }
+
+    /// Get the flags of the table
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    pub fn flags(&self) -> u32 {
+        read(self.data, FLAGS, 4) as u32
+    }
+
+    /// Check if the system also has 8259 PICs
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags() & PCAT_COMPAT != 0
+    }
+
+    /// Get an iterator over the entries of the table
+    pub fn entries(&self) -> MadtEntries {
+        MadtEntries {
+            data: self.data,
+            offset: ENTRIES,
+        }
+    }
+}
+
+impl Iterator for MadtEntries {
+    type Item = MadtEntry;
+
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn next(&mut self) -> Option<MadtEntry> {
+        if self.offset + 2 > self.data.len() {
+            return None;
+        }
+        let kind = self.data[self.offset];
+        let length = self.data[self.offset + 1];
+        // A zero length entry would repeat forever, so it ends the table like a truncated one
+        if length < 2 || self.offset + usize::from(length) > self.data.len() {
+            return None;
+        }
+        let entry = &self.data[self.offset..self.offset + usize::from(length)];
+        self.offset += usize::from(length);
+
+        Some(match (kind, length) {
+            (TYPE_LOCAL_APIC, 8) => MadtEntry::LocalApic {
+                processor_id: entry[2],
+                apic_id: entry[3],
+                enabled: read(entry, 4, 4) as u32 & PROCESSOR_ENABLED != 0,
+            },
+            (TYPE_IO_APIC, 12) => MadtEntry::IoApic {
+                id: entry[2],
+                address: read(entry, 4, 4) as u32,
+                gsi_base: read(entry, 8, 4) as u32,
+            },
+            (TYPE_INTERRUPT_OVERRIDE, 10) => MadtEntry::InterruptOverride {
+                bus: entry[2],
+                source: entry[3],
+                gsi: read(entry, 4, 4) as u32,
+                flags: read(entry, 8, 2) as u16,
+            },
+            (TYPE_LOCAL_APIC_NMI, 6) => MadtEntry::LocalApicNmi {
+                processor_id: entry[2],
+                flags: read(entry, 3, 2) as u16,
+                lint: entry[5],
+            },
+            (TYPE_LOCAL_APIC_ADDRESS_OVERRIDE, 12) => MadtEntry::LocalApicAddressOverride {
+                address: read(entry, 4, 8),
+            },
+            (TYPE_LOCAL_X2APIC, 16) => MadtEntry::LocalX2Apic {
+                x2apic_id: read(entry, 4, 4) as u32,
+                uid: read(entry, 12, 4) as u32,
+                enabled: read(entry, 8, 4) as u32 & PROCESSOR_ENABLED != 0,
+            },
+            _ => MadtEntry::Other {
+                kind: kind,
+                length: length,
+            },
+        })
+    }
+}
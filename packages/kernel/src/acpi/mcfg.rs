@@ -0,0 +1,72 @@
+//! PCI express memory mapped configuration table, locating the ECAM areas
+use super::{read, Sdt};
+
+/// Signature of the MCFG
+pub const SIGNATURE: &str = "MCFG";
+/// Offset of the first entry, after 8 reserved bytes
+const ENTRIES: usize = 44;
+/// Size of an entry
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+/// Typed view of the MCFG
+pub struct Mcfg {
+    /// The whole table, header included
+    data: &'static [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An ECAM area described by the MCFG
+pub struct McfgEntry {
+    /// Physical address of the configuration space of bus 0 of the segment group, even if
+    /// start_bus is higher
+    pub base_address: u64,
+    /// PCI segment group
+    pub segment: u16,
+    /// First bus covered
+    pub start_bus: u8,
+    /// Last bus (inclusive) covered
+    pub end_bus: u8,
+}
+
+/// Iterator over the entries of the MCFG
+pub struct McfgEntries {
+    /// The whole table, header included
+    data: &'static [u8],
+    /// Offset of the next entry
+    offset: usize,
+}
+
+impl Mcfg {
+    /// Mcfg constructor
+    pub fn new(table: &Sdt) -> Self {
+        Self { data: table.data }
+    }
+
+    /// Get an iterator over the entries of the table
+    pub fn entries(&self) -> McfgEntries {
+        McfgEntries {
+            data: self.data,
+            offset: ENTRIES,
+        }
+    }
+}
+
+impl Iterator for McfgEntries {
+    type Item = McfgEntry;
+
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn next(&mut self) -> Option<McfgEntry> {
+        if self.offset + ENTRY_SIZE > self.data.len() {
+            return None;
+        }
+        let offset = self.offset;
+        self.offset += ENTRY_SIZE;
+        Some(McfgEntry {
+            base_address: read(self.data, offset, 8),
+            segment: read(self.data, offset + 8, 2) as u16,
+            start_bus: self.data[offset + 10],
+            end_bus: self.data[offset + 11],
+        })
+    }
+}
@@ -0,0 +1,185 @@
+//! ACPI tables: finding the RSDP, mapping and validating the tables it leads to, and typed views
+//! of the common ones
+pub use self::fadt::{Fadt, GenericAddress};
+pub use self::hpet::Hpet;
+pub use self::madt::{Madt, MadtEntry};
+pub use self::mcfg::{Mcfg, McfgEntry};
+use alloc::vec::Vec;
+use core::{slice, str};
+use memory::{self, EntryFlags, PhysicalAddress};
+use multiboot2::BootInformation;
+use spin::Once;
+
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod rsdp;
+
+/// Size of the header every system description table starts with
+const HEADER_SIZE: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons ACPI tables can't be used
+pub enum Error {
+    /// Neither the boot loader nor the BIOS area provide an RSDP
+    NoRsdp,
+    /// A table is shorter than its header or its contents require
+    Truncated,
+    /// The bytes of a structure don't sum to zero
+    BadChecksum,
+    /// No virtual memory was left to map a table
+    OutOfMemory,
+}
+
+/// A system description table whose checksum has been verified, mapped read-only
+pub struct Sdt {
+    /// The whole table, header included
+    data: &'static [u8],
+}
+
+/// Tables listed by the RSDT or XSDT, and the DSDT, available once acpi::init has been called
+static TABLES: Once<Vec<Sdt>> = Once::new();
+
+/// Read a little endian integer of size bytes at offset. Fields past the end of the data read as
+/// zero, as they do for tables from older ACPI revisions.
+fn read(data: &[u8], offset: usize, size: usize) -> u64 {
+    match data.get(offset..offset + size) {
+        Some(bytes) => bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| (value << 8) | u64::from(byte)),
+        None => 0,
+    }
+}
+
+/// Check that the bytes of a structure sum to zero
+fn checksum_valid(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Map physical memory read-only, returning it as a slice
+fn map(physical_address: PhysicalAddress, length: usize) -> Result<&'static [u8], Error> {
+    let address = memory::controller()
+        .map_physical(physical_address, length, EntryFlags::NO_EXECUTE, "acpi")
+        .ok_or(Error::OutOfMemory)?;
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length) })
+}
+
+/// Remove a mapping made by map
+fn unmap(data: &'static [u8]) {
+    memory::controller().unmap_physical(data.as_ptr() as usize);
+}
+
+impl Sdt {
+    /// Map the table at a physical address and verify its checksum
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn map(physical_address: PhysicalAddress) -> Result<Self, Error> {
+        // The length is in the header, so the header gets mapped on its own first
+        let header = map(physical_address, HEADER_SIZE)?;
+        let length = read(header, 4, 4) as usize;
+        unmap(header);
+        if length < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        let data = map(physical_address, length)?;
+        if !checksum_valid(data) {
+            unmap(data);
+            return Err(Error::BadChecksum);
+        }
+        Ok(Self { data: data })
+    }
+
+    /// Get the four character signature identifying the kind of table
+    pub fn signature(&self) -> &'static str {
+        str::from_utf8(&self.data[0..4]).unwrap_or("????")
+    }
+
+    /// Get the whole table, header included, such as the AML code of the DSDT
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+
+    /// Get the id of the firmware vendor
+    pub fn oem_id(&self) -> &'static str {
+        str::from_utf8(&self.data[10..16]).unwrap_or("??????")
+    }
+}
+
+/// Find the RSDP and map every table it leads to. Tables with a bad checksum are skipped. Needs
+/// the heap and the memory controller.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn init(boot_info: &BootInformation) -> Result<(), Error> {
+    assert_has_not_been_called!("acpi::init must be called only once");
+    let rsdp = rsdp::find(boot_info)?;
+
+    // The XSDT holds 64 bit pointers and supersedes the RSDT when present
+    let (root, entry_size) = match rsdp.xsdt_address {
+        Some(address) => (Sdt::map(address as usize)?, 8),
+        None => (Sdt::map(rsdp.rsdt_address as usize)?, 4),
+    };
+    let mut tables = Vec::new();
+    let entry_count = (root.data.len() - HEADER_SIZE) / entry_size;
+    for index in 0..entry_count {
+        let address = read(root.data, HEADER_SIZE + index * entry_size, entry_size) as usize;
+        match Sdt::map(address) {
+            Ok(table) => tables.push(table),
+            Err(error) => println!("acpi: skipping table at {:#x}: {:?}", address, error),
+        }
+    }
+
+    let dsdt_address = tables
+        .iter()
+        .find(|table| table.signature() == fadt::SIGNATURE)
+        .map(|table| Fadt::new(table).dsdt_address());
+    if let Some(address) = dsdt_address {
+        match Sdt::map(address as usize) {
+            Ok(dsdt) => tables.push(dsdt),
+            Err(error) => println!("acpi: skipping DSDT at {:#x}: {:?}", address, error),
+        }
+    }
+
+    print!("acpi: revision {} from {}, tables", rsdp.revision, root.oem_id());
+    for table in &tables {
+        print!(" {}", table.signature());
+    }
+    println!();
+
+    tables.push(root);
+    TABLES.call_once(|| tables);
+    Ok(())
+}
+
+/// Find a table by signature, such as "APIC" for the MADT. Returns None if acpi::init failed.
+pub fn find(signature: &str) -> Option<&'static Sdt> {
+    TABLES
+        .try()
+        .and_then(|tables| tables.iter().find(|table| table.signature() == signature))
+}
+
+/// Get the differentiated system description table, whose AML code describes the devices and
+/// sleep states
+pub fn dsdt() -> Option<&'static Sdt> {
+    find("DSDT")
+}
+
+/// Get the multiple APIC description table, which lists processors and interrupt controllers
+pub fn madt() -> Option<Madt> {
+    find(madt::SIGNATURE).and_then(Madt::new)
+}
+
+/// Get the fixed ACPI description table, which describes power management hardware
+pub fn fadt() -> Option<Fadt> {
+    find(fadt::SIGNATURE).map(Fadt::new)
+}
+
+/// Get the high precision event timer description table
+pub fn hpet() -> Option<Hpet> {
+    find(hpet::SIGNATURE).and_then(Hpet::new)
+}
+
+/// Get the PCI express memory mapped configuration table
+pub fn mcfg() -> Option<Mcfg> {
+    find(mcfg::SIGNATURE).map(Mcfg::new)
+}
@@ -0,0 +1,130 @@
+//! Finding the root system description pointer, through the boot loader or the BIOS area
+use core::slice;
+use multiboot2::BootInformation;
+use super::{checksum_valid, map, read, unmap, Error};
+
+/// Signature the RSDP starts with
+const SIGNATURE: &[u8] = b"RSD PTR ";
+/// Size of the ACPI 1.0 RSDP, covered by its first checksum
+const V1_SIZE: usize = 20;
+/// Size of the ACPI 2.0 RSDP, covered by its extended checksum
+const V2_SIZE: usize = 36;
+/// Multiboot tag holding a copy of the ACPI 1.0 RSDP
+const TAG_ACPI_OLD: u32 = 14;
+/// Multiboot tag holding a copy of the ACPI 2.0 RSDP
+const TAG_ACPI_NEW: u32 = 15;
+/// Multiboot tag ending the tag list
+const TAG_END: u32 = 0;
+/// Physical address of the BIOS data area word holding the EBDA segment
+const EBDA_POINTER: usize = 0x40e;
+/// Part of the EBDA searched for the RSDP
+const EBDA_SEARCH_SIZE: usize = 1024;
+/// Start of the BIOS read-only memory area searched for the RSDP
+const BIOS_AREA_START: usize = 0xe_0000;
+/// End (exclusive) of the BIOS read-only memory area searched for the RSDP
+const BIOS_AREA_END: usize = 0x10_0000;
+
+#[derive(Debug, Clone, Copy)]
+/// Root system description pointer, leading to the other tables
+pub struct Rsdp {
+    /// ACPI revision, 0 for ACPI 1.0 and 2 for later ones
+    pub revision: u8,
+    /// Physical address of the RSDT
+    pub rsdt_address: u32,
+    /// Physical address of the XSDT, from ACPI 2.0 on
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    /// Validate an RSDP at the start of data
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < V1_SIZE || &data[0..8] != SIGNATURE || !checksum_valid(&data[..V1_SIZE]) {
+            return None;
+        }
+        let revision = data[15];
+        let xsdt_address = if revision >= 2 {
+            let length = read(data, 20, 4) as usize;
+            if length < V2_SIZE || length > data.len() || !checksum_valid(&data[..length]) {
+                return None;
+            }
+            match read(data, 24, 8) {
+                0 => None,
+                address => Some(address),
+            }
+        } else {
+            None
+        };
+        Some(Self {
+            revision: revision,
+            rsdt_address: read(data, 16, 4) as u32,
+            xsdt_address: xsdt_address,
+        })
+    }
+}
+
+/// Look for the RSDP copies GRUB passes as multiboot tags, preferring the ACPI 2.0 one
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+fn find_in_multiboot(boot_info: &BootInformation) -> Option<Rsdp> {
+    let start = boot_info.start_address();
+    let total_size = unsafe { *(start as *const u32) } as usize;
+    let info = unsafe { slice::from_raw_parts(start as *const u8, total_size) };
+
+    let mut old = None;
+    let mut new = None;
+    // Tags follow the 8 byte header, each starting on an 8 byte boundary
+    let mut offset = 8;
+    while offset + 8 <= info.len() {
+        let tag_type = read(info, offset, 4) as u32;
+        let tag_size = read(info, offset + 4, 4) as usize;
+        if tag_type == TAG_END || tag_size < 8 || offset + tag_size > info.len() {
+            break;
+        }
+        let contents = &info[offset + 8..offset + tag_size];
+        match tag_type {
+            TAG_ACPI_OLD => old = Rsdp::parse(contents),
+            TAG_ACPI_NEW => new = Rsdp::parse(contents),
+            _ => {}
+        }
+        offset += (tag_size + 7) & !7;
+    }
+    new.or(old)
+}
+
+/// Scan physical memory on 16 byte boundaries for a valid RSDP
+fn scan(start: usize, end: usize) -> Option<Rsdp> {
+    let area = match map(start, end - start) {
+        Ok(area) => area,
+        Err(_) => return None,
+    };
+    let found = (0..area.len() / 16)
+        .filter_map(|index| Rsdp::parse(&area[index * 16..]))
+        .next();
+    unmap(area);
+    found
+}
+
+/// Look for the RSDP in the first KiB of the EBDA, then in the BIOS read-only memory area
+fn find_in_bios_area() -> Option<Rsdp> {
+    let pointer = match map(EBDA_POINTER, 2) {
+        Ok(pointer) => pointer,
+        Err(_) => return None,
+    };
+    let ebda = (read(pointer, 0, 2) as usize) << 4;
+    unmap(pointer);
+
+    let in_ebda = if ebda != 0 && ebda < BIOS_AREA_START {
+        scan(ebda, ebda + EBDA_SEARCH_SIZE)
+    } else {
+        None
+    };
+    in_ebda.or_else(|| scan(BIOS_AREA_START, BIOS_AREA_END))
+}
+
+/// Find the RSDP
+pub fn find(boot_info: &BootInformation) -> Result<Rsdp, Error> {
+    // The multiboot information is identity mapped, while the BIOS area has to be mapped
+    find_in_multiboot(boot_info)
+        .or_else(find_in_bios_area)
+        .ok_or(Error::NoRsdp)
+}
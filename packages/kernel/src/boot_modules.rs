@@ -0,0 +1,59 @@
+//! Files loaded next to the kernel by GRUB's module2 command, such as the initial ramdisk
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::slice;
+use multiboot2::BootInformation;
+use spin::Once;
+
+/// Boot modules, available once boot_modules::init has been called
+static MODULES: Once<Vec<BootModule>> = Once::new();
+
+#[derive(Debug)]
+/// A file GRUB loaded into memory. memory::init keeps its frames from being allocated and
+/// identity maps it read-only.
+pub struct BootModule {
+    /// Name of the module, the first word of its command line in grub.cfg
+    name: String,
+    /// Physical (and virtual) start address of the module
+    start: usize,
+    /// Physical (and virtual) end address (exclusive) of the module
+    end: usize,
+}
+
+impl BootModule {
+    /// Get the name of the module
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the contents of the module
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { slice::from_raw_parts(self.start as *const u8, self.end - self.start) }
+    }
+}
+
+/// Record the modules listed in the multiboot information. Needs the heap.
+pub fn init(boot_info: &BootInformation) {
+    assert_has_not_been_called!("boot_modules::init must be called only once");
+    MODULES.call_once(|| {
+        boot_info
+            .module_tags()
+            .filter(|module| module.end_address() > module.start_address())
+            .map(|module| BootModule {
+                name: String::from(module.name().split_whitespace().next().unwrap_or("")),
+                start: module.start_address() as usize,
+                end: module.end_address() as usize,
+            })
+            .collect()
+    });
+}
+
+/// Get all boot modules
+pub fn all() -> &'static [BootModule] {
+    MODULES.try().expect("boot modules are not initialized")
+}
+
+/// Find a boot module by name
+pub fn find(name: &str) -> Option<&'static BootModule> {
+    all().iter().find(|module| module.name() == name)
+}
@@ -0,0 +1,205 @@
+//! Identification and features of the processor, as CPUID reports them
+//!
+//! boot.asm only makes sure CPUID and long mode exist. Everything else the kernel relies on is
+//! looked up here first. All processors are assumed to match the bootstrap processor.
+use core::str;
+use x86_64::registers::msr::{rdmsr, wrmsr, IA32_EFER};
+
+/// CPUID leaf with the vendor string and the highest basic leaf
+const LEAF_VENDOR: u32 = 0;
+/// CPUID leaf with the family, model and basic feature flags
+const LEAF_FEATURES: u32 = 1;
+/// CPUID leaf with the structured extended feature flags
+const LEAF_EXTENDED_FEATURES: u32 = 7;
+/// CPUID leaf with the highest extended leaf
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+/// CPUID leaf with the extended feature flags
+const LEAF_EXTENDED_INFO: u32 = 0x8000_0001;
+/// Family whose model numbers are extended by the extended model field, besides family 15
+const FAMILY_6: u32 = 6;
+/// Family whose numbers are extended by the extended family field
+const FAMILY_15: u32 = 15;
+/// EFER bit allowing the NO_EXECUTE bit to be set on pages
+const EFER_NXE: u64 = 1 << 11;
+
+bitflags! {
+    /// Processor features the kernel may make use of
+    pub struct Features: u32 {
+        #[cfg_attr(feature = "cargo-clippy", allow(identity_op))]
+        /// monitor and mwait instructions
+        const MONITOR = 1 << 0;
+        /// Process context identifiers in CR3
+        const PCID = 1 << 1;
+        /// Local APIC x2APIC mode
+        const X2APIC = 1 << 2;
+        /// Local APIC timer TSC-deadline mode
+        const TSC_DEADLINE = 1 << 3;
+        /// xsave family of instructions and XCR0
+        const XSAVE = 1 << 4;
+        /// rdrand instruction
+        const RDRAND = 1 << 5;
+        /// Global pages, which survive address space switches
+        const PGE = 1 << 6;
+        /// Supervisor mode execution prevention
+        const SMEP = 1 << 7;
+        /// Supervisor mode access prevention
+        const SMAP = 1 << 8;
+        /// NO_EXECUTE bit of page table entries
+        const NX = 1 << 9;
+        /// 1 GiB pages
+        const HUGE_PAGES_1GIB = 1 << 10;
+    }
+}
+
+/// Features reported by leaf 1 ECX, as the bit of the register and the feature
+const LEAF_1_ECX_FEATURES: &[(u32, Features)] = &[
+    (3, Features::MONITOR),
+    (17, Features::PCID),
+    (21, Features::X2APIC),
+    (24, Features::TSC_DEADLINE),
+    (26, Features::XSAVE),
+    (30, Features::RDRAND),
+];
+/// Features reported by leaf 1 EDX
+const LEAF_1_EDX_FEATURES: &[(u32, Features)] = &[(13, Features::PGE)];
+/// Features reported by leaf 7 EBX
+const LEAF_7_EBX_FEATURES: &[(u32, Features)] = &[(7, Features::SMEP), (20, Features::SMAP)];
+/// Features reported by leaf 0x80000001 EDX
+const LEAF_80000001_EDX_FEATURES: &[(u32, Features)] =
+    &[(20, Features::NX), (26, Features::HUGE_PAGES_1GIB)];
+
+#[derive(Debug, Clone, Copy)]
+/// Identification and features of the processor
+pub struct Cpu {
+    /// Vendor string, such as GenuineIntel or AuthenticAMD
+    vendor: [u8; 12],
+    /// Family, including the extended family
+    family: u32,
+    /// Model, including the extended model
+    model: u32,
+    /// Stepping
+    stepping: u32,
+    /// Supported features
+    features: Features,
+}
+
+lazy_static! {
+    /// The processor the kernel runs on
+    static ref CPU: Cpu = Cpu::detect();
+}
+
+/// Run CPUID for a leaf and subleaf, returning EAX, EBX, ECX and EDX
+fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        asm!("cpuid"
+             : "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx), "={edx}"(edx)
+             : "{eax}"(leaf), "{ecx}"(subleaf));
+    }
+    (eax, ebx, ecx, edx)
+}
+
+/// Collect the features whose bits are set in register
+fn features_in(register: u32, bits: &[(u32, Features)]) -> Features {
+    bits.iter()
+        .filter(|&&(bit, _)| register & (1 << bit) != 0)
+        .fold(Features::empty(), |features, &(_, feature)| features | feature)
+}
+
+impl Cpu {
+    /// Query the processor
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn detect() -> Self {
+        let (max_leaf, vendor_ebx, vendor_ecx, vendor_edx) = cpuid(LEAF_VENDOR, 0);
+        let mut vendor = [0; 12];
+        for (index, &register) in [vendor_ebx, vendor_edx, vendor_ecx].iter().enumerate() {
+            for byte in 0..4 {
+                vendor[index * 4 + byte] = (register >> (byte * 8)) as u8;
+            }
+        }
+
+        let (signature, _, ecx, edx) = cpuid(LEAF_FEATURES, 0);
+        let mut family = (signature >> 8) & 0xf;
+        let mut model = (signature >> 4) & 0xf;
+        if family == FAMILY_6 || family == FAMILY_15 {
+            model += ((signature >> 16) & 0xf) << 4;
+        }
+        if family == FAMILY_15 {
+            family += (signature >> 20) & 0xff;
+        }
+
+        let mut features =
+            features_in(ecx, LEAF_1_ECX_FEATURES) | features_in(edx, LEAF_1_EDX_FEATURES);
+        if max_leaf >= LEAF_EXTENDED_FEATURES {
+            let ebx = cpuid(LEAF_EXTENDED_FEATURES, 0).1;
+            features |= features_in(ebx, LEAF_7_EBX_FEATURES);
+        }
+        if cpuid(LEAF_EXTENDED_MAX, 0).0 >= LEAF_EXTENDED_INFO {
+            let edx = cpuid(LEAF_EXTENDED_INFO, 0).3;
+            features |= features_in(edx, LEAF_80000001_EDX_FEATURES);
+        }
+
+        Self {
+            vendor: vendor,
+            family: family,
+            model: model,
+            stepping: signature & 0xf,
+            features: features,
+        }
+    }
+
+    /// Get the vendor string
+    pub fn vendor(&self) -> &str {
+        str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    /// Get the family
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    /// Get the model
+    pub fn model(&self) -> u32 {
+        self.model
+    }
+
+    /// Get the stepping
+    pub fn stepping(&self) -> u32 {
+        self.stepping
+    }
+
+    /// Get the supported features
+    pub fn features(&self) -> Features {
+        self.features
+    }
+}
+
+/// Get the identification and features of the processor
+pub fn info() -> &'static Cpu {
+    &CPU
+}
+
+/// Check whether the processor supports all of features
+pub fn has(features: Features) -> bool {
+    CPU.features.contains(features)
+}
+
+/// Report the processor and turn on the features the kernel uses that it supports
+pub fn init() {
+    #![cfg_attr(feature = "cargo-clippy", allow(use_debug))]
+    let cpu = info();
+    println!(
+        "cpu: {} family {:#x} model {:#x} stepping {}",
+        cpu.vendor(),
+        cpu.family(),
+        cpu.model(),
+        cpu.stepping()
+    );
+    println!("    {:?}", cpu.features());
+
+    if has(Features::NX) {
+        unsafe { wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE) };
+    } else {
+        println!("cpu: no NX support, pages stay executable");
+    }
+}
@@ -0,0 +1,190 @@
+//! Parse ELF64 executables, as far as needed to load them
+use core::ops::Range;
+
+/// Program header type of a loadable segment
+pub const PT_LOAD: u32 = 1;
+/// Segment permission: execute
+pub const PF_X: u32 = 1 << 0;
+/// Segment permission: write
+pub const PF_W: u32 = 1 << 1;
+/// Segment permission: read
+pub const PF_R: u32 = 1 << 2;
+
+/// Size of the ELF64 file header
+const HEADER_SIZE: usize = 64;
+/// Size of an ELF64 program header
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// ELF type of executables
+const ET_EXEC: u16 = 2;
+/// ELF machine of x86_64
+const EM_X86_64: u16 = 0x3e;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons an image can't be used as an executable
+pub enum Error {
+    /// The image is too short to hold its headers
+    Truncated,
+    /// The image does not start with the ELF magic number
+    BadMagic,
+    /// The image is not a 64-bit little endian ELF file
+    UnsupportedFormat,
+    /// The image is not an x86_64 executable
+    NotExecutable,
+    /// A program header describes a segment outside the image, or with a file size larger than
+    /// its memory size
+    BadSegment,
+}
+
+/// A validated ELF64 executable
+pub struct ElfFile<'a> {
+    /// The whole image
+    data: &'a [u8],
+    /// Virtual address execution starts at
+    entry_point: u64,
+    /// Offset of the program header table
+    program_header_offset: usize,
+    /// Size of each entry of the program header table
+    program_header_size: usize,
+    /// Number of entries in the program header table
+    program_header_count: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Entry of the program header table
+pub struct ProgramHeader {
+    /// Segment type, such as PT_LOAD
+    pub kind: u32,
+    /// Segment permissions, made of PF_R, PF_W and PF_X
+    pub flags: u32,
+    /// Offset of the segment's contents in the image
+    pub offset: u64,
+    /// Virtual address the segment is loaded at
+    pub virtual_address: u64,
+    /// Size of the segment's contents in the image
+    pub file_size: u64,
+    /// Size of the segment in memory, with the part past file_size zeroed
+    pub memory_size: u64,
+}
+
+/// Iterator over the program headers of an ELF file
+pub struct ProgramHeaderIter<'a> {
+    /// The whole image
+    data: &'a [u8],
+    /// Offset of the next header
+    offset: usize,
+    /// Size of each header
+    size: usize,
+    /// Number of headers left
+    remaining: usize,
+}
+
+/// Read a little endian integer of size bytes at offset
+fn read(data: &[u8], offset: usize, size: usize) -> u64 {
+    data[offset..offset + size]
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+}
+
+impl<'a> ElfFile<'a> {
+    /// Check that data holds an x86_64 executable with sane program headers
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+        if data[0..4] != [0x7f, b'E', b'L', b'F'] {
+            return Err(Error::BadMagic);
+        }
+        // 64-bit class, little endian, version 1
+        if data[4] != 2 || data[5] != 1 || data[6] != 1 {
+            return Err(Error::UnsupportedFormat);
+        }
+        if read(data, 16, 2) as u16 != ET_EXEC || read(data, 18, 2) as u16 != EM_X86_64 {
+            return Err(Error::NotExecutable);
+        }
+
+        let file = ElfFile {
+            data: data,
+            entry_point: read(data, 24, 8),
+            program_header_offset: read(data, 32, 8) as usize,
+            program_header_size: read(data, 54, 2) as usize,
+            program_header_count: read(data, 56, 2) as usize,
+        };
+        let table_size = file.program_header_size
+            .checked_mul(file.program_header_count)
+            .ok_or(Error::Truncated)?;
+        let table_end = file.program_header_offset
+            .checked_add(table_size)
+            .ok_or(Error::Truncated)?;
+        if file.program_header_size < PROGRAM_HEADER_SIZE || table_end > data.len() {
+            return Err(Error::Truncated);
+        }
+
+        for header in file.program_headers() {
+            let in_image = match header.file_range() {
+                Some(range) => range.end <= data.len(),
+                None => false,
+            };
+            if !in_image || header.file_size > header.memory_size {
+                return Err(Error::BadSegment);
+            }
+        }
+        Ok(file)
+    }
+
+    /// Get the virtual address execution starts at
+    pub fn entry_point(&self) -> u64 {
+        self.entry_point
+    }
+
+    /// Get an iterator over the program headers
+    pub fn program_headers(&self) -> ProgramHeaderIter<'a> {
+        ProgramHeaderIter {
+            data: self.data,
+            offset: self.program_header_offset,
+            size: self.program_header_size,
+            remaining: self.program_header_count,
+        }
+    }
+
+    /// Get the contents of a segment stored in the image
+    pub fn segment_data(&self, header: &ProgramHeader) -> &'a [u8] {
+        let range = header.file_range().expect("segment was validated by parse");
+        &self.data[range]
+    }
+}
+
+impl ProgramHeader {
+    /// Get the byte range of the segment's contents in the image, if it doesn't overflow
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn file_range(&self) -> Option<Range<usize>> {
+        let start = self.offset as usize;
+        let end = start.checked_add(self.file_size as usize)?;
+        Some(start..end)
+    }
+}
+
+impl<'a> Iterator for ProgramHeaderIter<'a> {
+    type Item = ProgramHeader;
+
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    fn next(&mut self) -> Option<ProgramHeader> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let data = self.data;
+        let start = self.offset;
+        self.offset += self.size;
+        self.remaining -= 1;
+
+        Some(ProgramHeader {
+            kind: read(data, start, 4) as u32,
+            flags: read(data, start + 4, 4) as u32,
+            offset: read(data, start + 8, 8),
+            virtual_address: read(data, start + 16, 8),
+            file_size: read(data, start + 32, 8),
+            memory_size: read(data, start + 40, 8),
+        })
+    }
+}
@@ -0,0 +1,63 @@
+//! Emergency console, the output path of panics and fatal exceptions
+//!
+//! It draws straight to VGA memory instead of going through vga_buffer::WRITER, and copies
+//! everything to the first serial port, so reports get out even while the writer's lock is
+//! held, including by the code that panicked. Output of a processor printing at the same time
+//! may end up mixed with the report.
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use idle;
+use power;
+use serial::{SerialPort, COM1};
+use vga_buffer::{self, Writer};
+
+/// Number of panics and fatal exceptions that started being reported
+static PANICS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Writer to the screen and the first serial port that takes no locks
+struct Console {
+    /// Writer drawing to VGA memory, independent of WRITER
+    vga: Writer,
+    /// The first serial port
+    serial: SerialPort,
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.vga.write_str(s)?;
+        self.serial.write_str(s)
+    }
+}
+
+/// Get the writer to the screen and the first serial port that takes no locks
+fn console() -> Console {
+    Console {
+        vga: vga_buffer::emergency_writer(),
+        serial: SerialPort::new(COM1),
+    }
+}
+
+/// Print a message without taking locks, for code that runs with interrupts disabled for good
+pub fn print(args: fmt::Arguments) {
+    let _ = console().write_fmt(args);
+}
+
+/// Report a panic or fatal exception, then carry out the panic policy. A panic while another
+/// one is being reported, such as a fault while formatting the first report, only gets a fixed
+/// message before the processor halts.
+pub fn panic(args: fmt::Arguments) -> ! {
+    unsafe { ::x86_64::instructions::interrupts::disable() };
+    let mut console = console();
+    match PANICS.fetch_add(1, Ordering::SeqCst) {
+        0 => {
+            // Nothing is left to report a failure to
+            let _ = console.write_fmt(args);
+            power::on_panic()
+        }
+        1 => {
+            let _ = console.write_str("\nPANIC while reporting a panic, halting\n");
+            idle::halt_forever()
+        }
+        _ => idle::halt_forever(),
+    }
+}
@@ -0,0 +1,338 @@
+//! Virtual filesystem: filesystems mounted into one tree of paths, and open files
+use alloc::arc::Arc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use sync::RwLock;
+
+pub mod tar;
+pub mod tmp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons a filesystem operation can fail
+pub enum Error {
+    /// No file or directory exists at the path
+    NotFound,
+    /// A directory was expected
+    NotADirectory,
+    /// A regular file was expected
+    IsADirectory,
+    /// The filesystem can't be modified
+    ReadOnly,
+    /// Something already exists at the path
+    AlreadyExists,
+    /// The directory still has entries
+    NotEmpty,
+    /// The paths lie on different filesystems
+    CrossDevice,
+    /// The file would grow larger than the filesystem can hold
+    NoSpace,
+    /// The path is not absolute, or can't be used for the operation
+    InvalidPath,
+    /// The filesystem image is damaged
+    Corrupt,
+}
+
+/// Result of a filesystem operation
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of an inode
+pub enum FileType {
+    /// Regular file
+    File,
+    /// Directory
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Information about an inode
+pub struct Stat {
+    /// Kind of the inode
+    pub file_type: FileType,
+    /// Size in bytes, or number of entries for directories
+    pub size: usize,
+}
+
+#[derive(Debug, Clone)]
+/// Entry of a directory listing
+pub struct DirEntry {
+    /// Name of the entry within the directory
+    pub name: String,
+    /// Kind of the entry
+    pub file_type: FileType,
+}
+
+/// A file or directory of some filesystem
+pub trait Inode: Send + Sync {
+    /// Get information about the inode
+    fn stat(&self) -> Stat;
+
+    /// Read from a file at offset into buffer, returning the number of bytes read
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Find an entry of a directory by name
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>>;
+
+    /// List the entries of a directory
+    fn readdir(&self) -> Result<Vec<DirEntry>>;
+
+    /// Write data to a file at offset, growing it if needed, and return the number of bytes
+    /// written
+    fn write_at(&self, _offset: usize, _data: &[u8]) -> Result<usize> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Shrink or grow a file to size bytes, filling new space with zeros
+    fn truncate(&self, _size: usize) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Create a new, empty entry in a directory
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<Inode>> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Add an existing inode of the same filesystem to a directory
+    fn link(&self, _name: &str, _inode: Arc<Inode>) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Remove an entry from a directory. Directories must be empty.
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+}
+
+/// A mountable filesystem
+pub trait FileSystem: Send + Sync {
+    /// Get the root directory of the filesystem
+    fn root(&self) -> Arc<Inode>;
+}
+
+/// A filesystem attached to the tree of paths
+struct Mount {
+    /// Path components of the directory the filesystem is mounted on
+    path: Vec<String>,
+    /// The mounted filesystem
+    file_system: Arc<FileSystem>,
+}
+
+/// An open file, reading and writing sequentially from where the last access stopped
+pub struct File {
+    /// Inode of the file
+    inode: Arc<Inode>,
+    /// Offset of the next read or write
+    offset: usize,
+}
+
+lazy_static! {
+    /// Mounted filesystems, in the order they were mounted
+    static ref MOUNTS: RwLock<Vec<Mount>> = RwLock::new("mounts", Vec::new());
+}
+
+impl File {
+    /// Read into buffer, returning the number of bytes read, or 0 at the end of the file
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let read = self.inode.read_at(self.offset, buffer)?;
+        self.offset += read;
+        Ok(read)
+    }
+
+    /// Write data, returning the number of bytes written
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let written = self.inode.write_at(self.offset, data)?;
+        self.offset += written;
+        Ok(written)
+    }
+
+    /// Set the offset of the next read or write
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    /// Read everything from the current offset to the end of the file
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let remaining = self.inode.stat().size.saturating_sub(self.offset);
+        let mut contents = vec![0; remaining];
+        let mut filled = 0;
+        while filled < contents.len() {
+            match self.read(&mut contents[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        contents.truncate(filled);
+        Ok(contents)
+    }
+}
+
+/// Split an absolute path into its components, resolving "." and ".."
+fn components(path: &str) -> Result<Vec<&str>> {
+    if !path.starts_with('/') {
+        return Err(Error::InvalidPath);
+    }
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    Ok(components)
+}
+
+/// Find the inode at the given path components, along with the index of the mount it is on
+fn resolve(components: &[&str]) -> Result<(Arc<Inode>, usize)> {
+    // The mount point deepest in the tree wins, so that mounts can shadow parts of others
+    let (mut inode, depth, mount_index) = {
+        let mounts = MOUNTS.read();
+        let (index, mount) = mounts
+            .iter()
+            .enumerate()
+            .filter(|&(_, mount)| {
+                mount.path.len() <= components.len()
+                    && mount
+                        .path
+                        .iter()
+                        .zip(components.iter())
+                        .all(|(mounted, component)| mounted.as_str() == *component)
+            })
+            .max_by_key(|&(_, mount)| mount.path.len())
+            .ok_or(Error::NotFound)?;
+        (mount.file_system.root(), mount.path.len(), index)
+    };
+
+    for component in &components[depth..] {
+        if inode.stat().file_type != FileType::Directory {
+            return Err(Error::NotADirectory);
+        }
+        inode = inode.lookup(component)?;
+    }
+    Ok((inode, mount_index))
+}
+
+/// Find the directory containing the last component of a path, returning it with the name of
+/// the last component and the index of the mount the directory is on
+fn resolve_parent<'a>(components: &[&'a str]) -> Result<(Arc<Inode>, &'a str, usize)> {
+    let (name, parent) = components.split_last().ok_or(Error::InvalidPath)?;
+    let (directory, mount_index) = resolve(parent)?;
+    if directory.stat().file_type != FileType::Directory {
+        return Err(Error::NotADirectory);
+    }
+    Ok((directory, *name, mount_index))
+}
+
+/// Find the inode at an absolute path
+pub fn lookup(path: &str) -> Result<Arc<Inode>> {
+    resolve(&components(path)?).map(|(inode, _)| inode)
+}
+
+/// Attach a filesystem at an absolute path
+pub fn mount(path: &str, file_system: Arc<FileSystem>) -> Result<()> {
+    let components = components(path)?;
+    if !components.is_empty() && lookup(path)?.stat().file_type != FileType::Directory {
+        return Err(Error::NotADirectory);
+    }
+    MOUNTS.write().push(Mount {
+        path: components.into_iter().map(String::from).collect(),
+        file_system: file_system,
+    });
+    Ok(())
+}
+
+/// Open the file at an absolute path
+pub fn open(path: &str) -> Result<File> {
+    let inode = lookup(path)?;
+    if inode.stat().file_type == FileType::Directory {
+        return Err(Error::IsADirectory);
+    }
+    Ok(File {
+        inode: inode,
+        offset: 0,
+    })
+}
+
+/// Open the file at an absolute path for writing, creating it if it doesn't exist and
+/// truncating it if it does
+pub fn create(path: &str) -> Result<File> {
+    let components = components(path)?;
+    let (directory, name, _) = resolve_parent(&components)?;
+    let inode = match directory.lookup(name) {
+        Ok(inode) => {
+            inode.truncate(0)?;
+            inode
+        }
+        Err(Error::NotFound) => directory.create(name, FileType::File)?,
+        Err(error) => return Err(error),
+    };
+    Ok(File {
+        inode: inode,
+        offset: 0,
+    })
+}
+
+/// Create a directory at an absolute path
+pub fn mkdir(path: &str) -> Result<()> {
+    let components = components(path)?;
+    let (directory, name, _) = resolve_parent(&components)?;
+    directory.create(name, FileType::Directory).map(|_| ())
+}
+
+/// Shrink or grow the file at an absolute path to size bytes
+pub fn truncate(path: &str, size: usize) -> Result<()> {
+    lookup(path)?.truncate(size)
+}
+
+/// Remove the file or empty directory at an absolute path
+pub fn unlink(path: &str) -> Result<()> {
+    let components = components(path)?;
+    let (directory, name, _) = resolve_parent(&components)?;
+    directory.unlink(name)
+}
+
+/// Move a file or directory to a new path on the same filesystem, replacing a file already at
+/// the new path
+pub fn rename(old_path: &str, new_path: &str) -> Result<()> {
+    let old_components = components(old_path)?;
+    let new_components = components(new_path)?;
+    if old_components == new_components {
+        return Ok(());
+    }
+    if new_components.starts_with(&old_components) {
+        // A directory can't be moved into itself
+        return Err(Error::InvalidPath);
+    }
+
+    let (old_directory, old_name, old_mount) = resolve_parent(&old_components)?;
+    let (new_directory, new_name, new_mount) = resolve_parent(&new_components)?;
+    if old_mount != new_mount {
+        return Err(Error::CrossDevice);
+    }
+    let inode = old_directory.lookup(old_name)?;
+
+    match new_directory.lookup(new_name) {
+        Ok(existing) => {
+            if existing.stat().file_type == FileType::Directory {
+                return Err(Error::AlreadyExists);
+            }
+            new_directory.unlink(new_name)?;
+        }
+        Err(Error::NotFound) => {}
+        Err(error) => return Err(error),
+    }
+    new_directory.link(new_name, inode)?;
+    old_directory.unlink(old_name)
+}
+
+/// Get information about the inode at an absolute path
+pub fn stat(path: &str) -> Result<Stat> {
+    lookup(path).map(|inode| inode.stat())
+}
+
+/// List the directory at an absolute path
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>> {
+    lookup(path)?.readdir()
+}
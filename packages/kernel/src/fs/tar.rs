@@ -0,0 +1,212 @@
+//! Read-only filesystem backed by a ustar archive in memory, such as the initrd boot module
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{cmp, str};
+use super::{DirEntry, Error, FileSystem, FileType, Inode, Result, Stat};
+
+/// Size of archive blocks, and of each header
+const BLOCK_SIZE: usize = 512;
+/// Type flag of regular files
+const TYPE_FILE: u8 = b'0';
+/// Type flag of regular files in old archives
+const TYPE_FILE_OLD: u8 = 0;
+/// Type flag of directories
+const TYPE_DIRECTORY: u8 = b'5';
+
+/// Filesystem of a ustar archive
+pub struct TarFileSystem {
+    /// Root directory of the archive
+    root: Arc<TarInode>,
+}
+
+/// File or directory of a ustar archive
+enum TarInode {
+    /// Regular file, with its contents in the archive
+    File(&'static [u8]),
+    /// Directory, with its entries by name
+    Directory(BTreeMap<String, Arc<TarInode>>),
+}
+
+/// Directory tree being built from the archive, before it is frozen into inodes
+enum Node {
+    /// Regular file, with its contents in the archive
+    File(&'static [u8]),
+    /// Directory, with its entries by name
+    Directory(BTreeMap<String, Node>),
+}
+
+/// Parse a NUL or space terminated octal number
+fn parse_octal(field: &[u8]) -> Result<usize> {
+    let mut value: usize = 0;
+    for &byte in field.iter().skip_while(|&&byte| byte == b' ') {
+        match byte {
+            b'0'...b'7' => {
+                value = value
+                    .checked_mul(8)
+                    .and_then(|value| value.checked_add(usize::from(byte - b'0')))
+                    .ok_or(Error::Corrupt)?;
+            }
+            0 | b' ' => break,
+            _ => return Err(Error::Corrupt),
+        }
+    }
+    Ok(value)
+}
+
+/// Get a NUL terminated string field
+fn parse_string(field: &[u8]) -> Result<&str> {
+    let length = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..length]).map_err(|_| Error::Corrupt)
+}
+
+impl Node {
+    /// Add a file or directory at the given path components, creating missing directories
+    fn insert(&mut self, path: &[&str], node: Self) -> Result<()> {
+        let entries = match *self {
+            Node::Directory(ref mut entries) => entries,
+            Node::File(_) => return Err(Error::Corrupt),
+        };
+        match path.split_first() {
+            None => Err(Error::Corrupt),
+            Some((name, rest)) if rest.is_empty() => {
+                // Directories may be listed after files in them, so don't replace them
+                let is_directory = match entries.get(*name) {
+                    Some(&Node::Directory(_)) => true,
+                    _ => false,
+                };
+                if !is_directory {
+                    entries.insert(String::from(*name), node);
+                }
+                Ok(())
+            }
+            Some((name, rest)) => entries
+                .entry(String::from(*name))
+                .or_insert_with(|| Node::Directory(BTreeMap::new()))
+                .insert(rest, node),
+        }
+    }
+
+    /// Turn the tree into inodes
+    fn freeze(self) -> Arc<TarInode> {
+        match self {
+            Node::File(data) => Arc::new(TarInode::File(data)),
+            Node::Directory(entries) => Arc::new(TarInode::Directory(
+                entries
+                    .into_iter()
+                    .map(|(name, node)| (name, node.freeze()))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+impl TarFileSystem {
+    /// Index the files of a ustar archive
+    pub fn new(archive: &'static [u8]) -> Result<Self> {
+        let mut root = Node::Directory(BTreeMap::new());
+        let mut offset = 0;
+        while offset + BLOCK_SIZE <= archive.len() {
+            let header = &archive[offset..offset + BLOCK_SIZE];
+            // The archive ends with zeroed blocks
+            if header[0] == 0 {
+                break;
+            }
+            if &header[257..262] != b"ustar" {
+                return Err(Error::Corrupt);
+            }
+
+            let size = parse_octal(&header[124..136])?;
+            let data_start = offset + BLOCK_SIZE;
+            let data_end = data_start.checked_add(size).ok_or(Error::Corrupt)?;
+            if data_end > archive.len() {
+                return Err(Error::Corrupt);
+            }
+
+            let prefix = parse_string(&header[345..500])?;
+            let name = parse_string(&header[0..100])?;
+            let path: Vec<&str> = prefix
+                .split('/')
+                .chain(name.split('/'))
+                .filter(|component| !component.is_empty() && *component != ".")
+                .collect();
+            if !path.is_empty() {
+                match header[156] {
+                    TYPE_FILE | TYPE_FILE_OLD => {
+                        root.insert(&path, Node::File(&archive[data_start..data_end]))?
+                    }
+                    TYPE_DIRECTORY => root.insert(&path, Node::Directory(BTreeMap::new()))?,
+                    // Links and special files are not supported
+                    _ => {}
+                }
+            }
+
+            let blocks = (size + BLOCK_SIZE - 1) / BLOCK_SIZE;
+            offset = data_start + blocks * BLOCK_SIZE;
+        }
+
+        Ok(Self {
+            root: root.freeze(),
+        })
+    }
+}
+
+impl FileSystem for TarFileSystem {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+}
+
+impl Inode for TarInode {
+    fn stat(&self) -> Stat {
+        match *self {
+            TarInode::File(data) => Stat {
+                file_type: FileType::File,
+                size: data.len(),
+            },
+            TarInode::Directory(ref entries) => Stat {
+                file_type: FileType::Directory,
+                size: entries.len(),
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        match *self {
+            TarInode::File(data) => {
+                let start = cmp::min(offset, data.len());
+                let length = cmp::min(buffer.len(), data.len() - start);
+                buffer[..length].copy_from_slice(&data[start..start + length]);
+                Ok(length)
+            }
+            TarInode::Directory(_) => Err(Error::IsADirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        match *self {
+            TarInode::File(_) => Err(Error::NotADirectory),
+            TarInode::Directory(ref entries) => match entries.get(name) {
+                Some(inode) => {
+                    let inode: Arc<Inode> = inode.clone();
+                    Ok(inode)
+                }
+                None => Err(Error::NotFound),
+            },
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        match *self {
+            TarInode::File(_) => Err(Error::NotADirectory),
+            TarInode::Directory(ref entries) => Ok(entries
+                .iter()
+                .map(|(name, inode)| DirEntry {
+                    name: name.clone(),
+                    file_type: inode.stat().file_type,
+                })
+                .collect()),
+        }
+    }
+}
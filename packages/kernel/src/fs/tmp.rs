@@ -0,0 +1,189 @@
+//! Writable filesystem kept entirely on the kernel heap, for scratch files under /tmp
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::heap::{Alloc, Heap, Layout};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp;
+use spin::Mutex;
+use super::{DirEntry, Error, FileSystem, FileType, Inode, Result, Stat};
+
+/// Largest size a file can grow to, so that a single write can't use up the kernel heap
+const MAX_FILE_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+
+/// Filesystem whose files live on the kernel heap
+pub struct TmpFileSystem {
+    /// Root directory
+    root: Arc<TmpInode>,
+}
+
+/// File or directory of a TmpFileSystem
+enum TmpInode {
+    /// Regular file and its contents
+    File(Mutex<Vec<u8>>),
+    /// Directory and its entries by name
+    Directory(Mutex<BTreeMap<String, Arc<Inode>>>),
+}
+
+impl TmpFileSystem {
+    /// Create an empty filesystem
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(TmpInode::new(FileType::Directory)),
+        }
+    }
+}
+
+impl TmpInode {
+    /// Create an empty file or directory
+    fn new(file_type: FileType) -> Self {
+        match file_type {
+            FileType::File => TmpInode::File(Mutex::new(Vec::new())),
+            FileType::Directory => TmpInode::Directory(Mutex::new(BTreeMap::new())),
+        }
+    }
+}
+
+/// Resize the contents of a file, zero filling new bytes. Fails with Error::NoSpace instead of
+/// aborting if the file would grow beyond MAX_FILE_SIZE or the heap can't hold it.
+fn resize(contents: &mut Vec<u8>, size: usize) -> Result<()> {
+    if size > MAX_FILE_SIZE {
+        return Err(Error::NoSpace);
+    }
+    if size > contents.capacity() {
+        let layout = Layout::from_size_align(size, 1).ok_or(Error::NoSpace)?;
+        let buffer = unsafe { Heap.alloc(layout) }.map_err(|_| Error::NoSpace)?;
+        // The buffer has the layout Vec<u8> allocates with, so the vector can free it
+        let mut grown = unsafe { Vec::from_raw_parts(buffer, 0, size) };
+        grown.extend_from_slice(contents);
+        *contents = grown;
+    }
+    contents.resize(size, 0);
+    Ok(())
+}
+
+impl FileSystem for TmpFileSystem {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+}
+
+impl Inode for TmpInode {
+    fn stat(&self) -> Stat {
+        match *self {
+            TmpInode::File(ref data) => Stat {
+                file_type: FileType::File,
+                size: data.lock().len(),
+            },
+            TmpInode::Directory(ref entries) => Stat {
+                file_type: FileType::Directory,
+                size: entries.lock().len(),
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        match *self {
+            TmpInode::File(ref data) => {
+                let data = data.lock();
+                let start = cmp::min(offset, data.len());
+                let length = cmp::min(buffer.len(), data.len() - start);
+                buffer[..length].copy_from_slice(&data[start..start + length]);
+                Ok(length)
+            }
+            TmpInode::Directory(_) => Err(Error::IsADirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        match *self {
+            TmpInode::File(_) => Err(Error::NotADirectory),
+            TmpInode::Directory(ref entries) => {
+                entries.lock().get(name).cloned().ok_or(Error::NotFound)
+            }
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        match *self {
+            TmpInode::File(_) => Err(Error::NotADirectory),
+            TmpInode::Directory(ref entries) => Ok(entries
+                .lock()
+                .iter()
+                .map(|(name, inode)| DirEntry {
+                    name: name.clone(),
+                    file_type: inode.stat().file_type,
+                })
+                .collect()),
+        }
+    }
+
+    fn write_at(&self, offset: usize, data: &[u8]) -> Result<usize> {
+        match *self {
+            TmpInode::File(ref contents) => {
+                let end = offset.checked_add(data.len()).ok_or(Error::NoSpace)?;
+                let mut contents = contents.lock();
+                if end > contents.len() {
+                    resize(&mut contents, end)?;
+                }
+                contents[offset..end].copy_from_slice(data);
+                Ok(data.len())
+            }
+            TmpInode::Directory(_) => Err(Error::IsADirectory),
+        }
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        match *self {
+            TmpInode::File(ref contents) => {
+                let mut contents = contents.lock();
+                resize(&mut contents, size)?;
+                contents.shrink_to_fit();
+                Ok(())
+            }
+            TmpInode::Directory(_) => Err(Error::IsADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<Inode>> {
+        let inode: Arc<Inode> = Arc::new(TmpInode::new(file_type));
+        self.link(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, inode: Arc<Inode>) -> Result<()> {
+        match *self {
+            TmpInode::File(_) => Err(Error::NotADirectory),
+            TmpInode::Directory(ref entries) => {
+                let mut entries = entries.lock();
+                if entries.contains_key(name) {
+                    return Err(Error::AlreadyExists);
+                }
+                entries.insert(String::from(name), inode);
+                Ok(())
+            }
+        }
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        match *self {
+            TmpInode::File(_) => Err(Error::NotADirectory),
+            TmpInode::Directory(ref entries) => {
+                let mut entries = entries.lock();
+                let removable = match entries.get(name) {
+                    Some(inode) => {
+                        let stat = inode.stat();
+                        stat.file_type == FileType::File || stat.size == 0
+                    }
+                    None => return Err(Error::NotFound),
+                };
+                if !removable {
+                    return Err(Error::NotEmpty);
+                }
+                entries.remove(name);
+                Ok(())
+            }
+        }
+    }
+}
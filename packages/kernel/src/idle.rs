@@ -0,0 +1,60 @@
+//! Idling and halting the processor
+//!
+//! Idle processors wait with mwait where the processor supports it, and with hlt otherwise.
+//! Either way interrupts are enabled by the instruction right before, so that an interrupt
+//! arriving after the caller's last check still ends the wait: sti only takes effect after the
+//! next instruction.
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use cpu::{self, Features};
+use x86_64::instructions::interrupts;
+
+/// Word monitored while waiting for nothing but an interrupt, which is never written
+static NOTHING: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Wait for an interrupt, with interrupts enabled afterwards. The body of idle loops.
+pub fn idle() {
+    unsafe { interrupts::disable() };
+    monitor(&NOTHING);
+    wait();
+}
+
+/// Wait for an interrupt or a write to word, unless word is nonzero already. Interrupts are
+/// enabled afterwards.
+pub fn idle_until(word: &AtomicUsize) {
+    unsafe { interrupts::disable() };
+    // Arming the monitor before the check means a write right after it still ends mwait
+    monitor(word);
+    if word.load(Ordering::SeqCst) == 0 {
+        wait();
+    } else {
+        unsafe { interrupts::enable() };
+    }
+}
+
+/// Stop the processor for good, with interrupts disabled. The end of every fatal path.
+pub fn halt_forever() -> ! {
+    loop {
+        // Non-maskable interrupts still end hlt
+        unsafe { asm!("cli; hlt" :::: "volatile") };
+    }
+}
+
+/// Make the next wait also end on a write to word, if mwait is used
+fn monitor(word: &AtomicUsize) {
+    if cpu::has(Features::MONITOR) {
+        unsafe {
+            asm!("monitor" :: "{rax}"(word as *const AtomicUsize), "{ecx}"(0), "{edx}"(0)
+                 :: "volatile");
+        }
+    }
+}
+
+/// Enable interrupts and wait for one, or for a write to the monitored word if mwait is used.
+/// Called with interrupts disabled.
+fn wait() {
+    if cpu::has(Features::MONITOR) {
+        unsafe { asm!("sti; mwait" :: "{eax}"(0), "{ecx}"(0) : "memory" : "volatile") };
+    } else {
+        unsafe { asm!("sti; hlt" ::: "memory" : "volatile") };
+    }
+}
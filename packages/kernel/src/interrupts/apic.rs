@@ -0,0 +1,122 @@
+//! Local APIC of each processor, used to start the other processors and interrupt them
+use acpi;
+use core::ptr;
+use memory::{EntryFlags, MemoryController};
+use spin::Once;
+
+/// Interrupt vector of spurious interrupts, whose low four bits have to be set
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Model specific register holding the physical address of the local APIC
+const IA32_APIC_BASE: u32 = 0x1b;
+/// Bits of IA32_APIC_BASE holding the physical address
+const APIC_BASE_ADDRESS_MASK: u64 = 0xf_ffff_f000;
+/// Size of the register area
+const REGISTERS_SIZE: usize = 0x1000;
+
+/// Register holding the local APIC id in its top byte
+const ID: usize = 0x20;
+/// Register signalling the end of an interrupt
+const EOI: usize = 0xb0;
+/// Register enabling the local APIC and choosing the spurious interrupt vector
+const SPURIOUS_INTERRUPT: usize = 0xf0;
+/// Low half of the interrupt command register, writing which sends the interrupt
+const ICR_LOW: usize = 0x300;
+/// High half of the interrupt command register, holding the destination
+const ICR_HIGH: usize = 0x310;
+
+/// Spurious interrupt register bit enabling the local APIC
+const APIC_ENABLE: u32 = 1 << 8;
+/// Interrupt command delivery mode: raise the interrupt vector in the low byte
+const DELIVERY_FIXED: u32 = 0b000 << 8;
+/// Interrupt command delivery mode: reset the target into its wait-for-startup state
+const DELIVERY_INIT: u32 = 0b101 << 8;
+/// Interrupt command delivery mode: start the target in real mode at the page in the low byte
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+/// Interrupt command bit set while the interrupt hasn't been accepted yet
+const DELIVERY_PENDING: u32 = 1 << 12;
+/// Interrupt command bit asserting the INIT level, which startup needs
+const LEVEL_ASSERT: u32 = 1 << 14;
+/// Interrupt command destination shorthand: every processor except the sending one
+const ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Virtual address the local APIC registers are mapped at. Every processor's local APIC sits at
+/// the same physical address, so one mapping serves them all.
+static REGISTERS: Once<usize> = Once::new();
+
+/// Read a local APIC register
+fn read(register: usize) -> u32 {
+    let base = REGISTERS.try().expect("local APIC is not initialized");
+    unsafe { ptr::read_volatile((base + register) as *const u32) }
+}
+
+/// Write a local APIC register
+fn write(register: usize, value: u32) {
+    let base = REGISTERS.try().expect("local APIC is not initialized");
+    unsafe { ptr::write_volatile((base + register) as *mut u32, value) };
+}
+
+/// Map the local APIC registers, at the address from the MADT or else the one the processor
+/// reports, and enable the local APIC of the bootstrap processor
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn init(memory_controller: &mut MemoryController) {
+    use x86_64::registers::msr::rdmsr;
+
+    assert_has_not_been_called!("apic::init must be called only once");
+    let physical_address = match acpi::madt() {
+        Some(madt) => madt.local_apic_address(),
+        None => unsafe { rdmsr(IA32_APIC_BASE) } & APIC_BASE_ADDRESS_MASK,
+    };
+    let address = memory_controller
+        .map_physical(
+            physical_address as usize,
+            REGISTERS_SIZE,
+            EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE,
+            "local apic",
+        )
+        .expect("could not map the local APIC");
+    REGISTERS.call_once(|| address);
+    enable();
+}
+
+/// Enable the local APIC of the running processor. Interrupts from the 8259 PICs keep arriving
+/// on the bootstrap processor.
+pub fn enable() {
+    write(
+        SPURIOUS_INTERRUPT,
+        read(SPURIOUS_INTERRUPT) | APIC_ENABLE | u32::from(SPURIOUS_VECTOR),
+    );
+}
+
+/// Get the local APIC id of the running processor
+pub fn id() -> u32 {
+    read(ID) >> 24
+}
+
+/// Signal the local APIC of the running processor that an interrupt it delivered is handled
+pub fn end_of_interrupt() {
+    write(EOI, 0);
+}
+
+/// Send an interrupt command to a processor, waiting until its local APIC accepted it
+fn send(apic_id: u32, command: u32) {
+    write(ICR_HIGH, apic_id << 24);
+    write(ICR_LOW, command);
+    while read(ICR_LOW) & DELIVERY_PENDING != 0 {}
+}
+
+/// Reset a processor into the state where it waits for a startup interrupt
+pub fn send_init(apic_id: u32) {
+    send(apic_id, DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+/// Start a processor waiting after send_init in real mode, at the start of the page with the
+/// given number, which has to be below 256
+pub fn send_startup(apic_id: u32, page_number: u8) {
+    send(apic_id, DELIVERY_STARTUP | LEVEL_ASSERT | u32::from(page_number));
+}
+
+/// Raise the interrupt vector on every processor except the running one
+pub fn broadcast_ipi(vector: u8) {
+    send(0, DELIVERY_FIXED | ALL_EXCLUDING_SELF | u32::from(vector));
+}
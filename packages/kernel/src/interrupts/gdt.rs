@@ -22,9 +22,11 @@ pub enum Descriptor {
 bitflags! {
     ///GDT descriptor flags
     struct DescriptorFlags: u64 {
+        const WRITABLE = 1 << 41;
         const CONFORMING = 1 << 42;
         const EXECUTABLE = 1 << 43;
         const USER_SEGMENT = 1 << 44;
+        const DPL_RING_3 = 3 << 45;
         const PRESENT = 1 << 47;
         const LONG_MODE = 1 << 53;
     }
@@ -39,19 +41,28 @@ impl Gdt {
         }
     }
 
-    /// Add a new descriptor to the GDT and return a segment selector for it
+    /// Add a new descriptor to the GDT and return a segment selector for it, requesting the
+    /// privilege level of the descriptor
     #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
     pub fn add_entry(&mut self, entry: Descriptor) -> SegmentSelector {
-        let index = match entry {
-            Descriptor::UserSegment(value) => self.push(value),
+        let (index, privilege_level) = match entry {
+            Descriptor::UserSegment(value) => {
+                let flags = DescriptorFlags::from_bits_truncate(value);
+                let privilege_level = if flags.contains(DescriptorFlags::DPL_RING_3) {
+                    PrivilegeLevel::Ring3
+                } else {
+                    PrivilegeLevel::Ring0
+                };
+                (self.push(value), privilege_level)
+            }
             Descriptor::SystemSegment(value_low, value_high) => {
                 let index = self.push(value_low);
                 self.push(value_high);
-                index
+                (index, PrivilegeLevel::Ring0)
             }
         };
-        SegmentSelector::new(index as u16, PrivilegeLevel::Ring0)
+        SegmentSelector::new(index as u16, privilege_level)
     }
 
     /// Push a new descriptor into the GDT descriptor table
@@ -89,12 +100,35 @@ impl Descriptor {
         Descriptor::UserSegment(flags.bits())
     }
 
-    /// Create a Task State Segment descriptor
-    pub fn tss_segment(tss: &'static TaskStateSegment) -> Self {
+    /// Create a kernel data segment descriptor
+    pub fn kernel_data_segment() -> Self {
+        let flags =
+            DescriptorFlags::USER_SEGMENT | DescriptorFlags::PRESENT | DescriptorFlags::WRITABLE;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    /// Create a user mode (ring 3) code segment descriptor
+    pub fn user_code_segment() -> Self {
+        let flags = DescriptorFlags::USER_SEGMENT | DescriptorFlags::PRESENT
+            | DescriptorFlags::EXECUTABLE | DescriptorFlags::LONG_MODE
+            | DescriptorFlags::DPL_RING_3;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    /// Create a user mode (ring 3) data segment descriptor
+    pub fn user_data_segment() -> Self {
+        let flags = DescriptorFlags::USER_SEGMENT | DescriptorFlags::PRESENT
+            | DescriptorFlags::WRITABLE | DescriptorFlags::DPL_RING_3;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    /// Create a Task State Segment descriptor. The TSS must stay where it is for as long as the
+    /// descriptor is used.
+    pub fn tss_segment(tss: *const TaskStateSegment) -> Self {
         use core::mem::size_of;
         use bit_field::BitField;
 
-        let ptr = tss as *const _ as u64;
+        let ptr = tss as u64;
 
         let mut low = DescriptorFlags::PRESENT.bits();
 
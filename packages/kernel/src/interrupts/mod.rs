@@ -1,25 +1,98 @@
 //! Interrupt Descriptor Table and corresponding interrupt handlers
 //use x86_64::structures::idt::{ExceptionStackFrame, Idt, IdtEntry};
-use memory::MemoryController;
-use x86_64::structures::idt::{ExceptionStackFrame, Idt};
+use alloc::boxed::Box;
+use memory::{self, MemoryController, StackOwner};
+use x86_64::registers::flags::Flags;
+use x86_64::structures::gdt::SegmentSelector;
+use x86_64::structures::idt::{ExceptionStackFrame, Idt, PageFaultErrorCode};
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::VirtualAddress;
+use x86_64::{PrivilegeLevel, VirtualAddress};
 use spin::Once;
+use emergency;
+use keyboard;
+use process;
+use scheduler;
+use serial;
+use smp;
+use syscall;
+use task;
 
+pub mod apic;
 mod gdt;
+pub mod pic;
+pub mod pit;
 
 /// Double fault stack index in Interrupt Stack Table
 const DOUBLE_FAULT_IST_INDEX: usize = 0;
+/// Interrupt vector of the timer
+const TIMER_VECTOR: u8 = pic::IRQ_OFFSET + pit::IRQ;
+/// Interrupt vector of the keyboard
+const KEYBOARD_VECTOR: u8 = pic::IRQ_OFFSET + keyboard::IRQ;
+/// Interrupt vector of the first serial port
+const SERIAL_VECTOR: u8 = pic::IRQ_OFFSET + serial::IRQ;
+/// Exit code of processes killed by a fault they caused, as shells report a SIGSEGV
+const FAULT_EXIT_CODE: usize = 139;
 
-/// Task State Segment
-static TSS: Once<TaskStateSegment> = Once::new();
-/// Global Descriptor Table
-static GDT: Once<gdt::Gdt> = Once::new();
+/// Segment selectors of the GDT entries, which are the same in the GDT of every processor
+static SELECTORS: Once<Selectors> = Once::new();
+
+#[derive(Debug, Clone, Copy)]
+/// Segment selectors of the GDT entries
+pub struct Selectors {
+    /// Kernel code segment
+    pub kernel_code: SegmentSelector,
+    /// Kernel data segment
+    pub kernel_data: SegmentSelector,
+    /// User mode data segment
+    pub user_data: SegmentSelector,
+    /// User mode code segment
+    pub user_code: SegmentSelector,
+    /// Task State Segment
+    pub tss: SegmentSelector,
+}
+
+/// Descriptor tables of a processor
+pub struct CpuTables {
+    /// Task State Segment, whose stacks are specific to the processor. Kept as a raw pointer
+    /// since set_kernel_stack writes to it.
+    tss: *mut TaskStateSegment,
+    /// Global Descriptor Table, pointing to tss
+    gdt: &'static gdt::Gdt,
+}
 
 lazy_static! {
     static ref IDT: Idt = {
         let mut idt = Idt::new();
-        idt.breakpoint.set_handler_fn(handle_breakpoint);
+        // User mode may trigger breakpoints itself
+        idt.breakpoint
+            .set_handler_fn(handle_breakpoint)
+            .set_privilege_level(PrivilegeLevel::Ring3);
+        idt.divide_by_zero.set_handler_fn(handle_divide_by_zero);
+        idt.debug.set_handler_fn(handle_debug);
+        idt.overflow.set_handler_fn(handle_overflow);
+        idt.bound_range_exceeded.set_handler_fn(handle_bound_range_exceeded);
+        idt.invalid_opcode.set_handler_fn(handle_invalid_opcode);
+        idt.device_not_available.set_handler_fn(handle_device_not_available);
+        idt.invalid_tss.set_handler_fn(handle_invalid_tss);
+        idt.segment_not_present.set_handler_fn(handle_segment_not_present);
+        idt.stack_segment_fault.set_handler_fn(handle_stack_segment_fault);
+        idt.general_protection_fault.set_handler_fn(handle_general_protection_fault);
+        idt.page_fault.set_handler_fn(handle_page_fault);
+        idt.x87_floating_point.set_handler_fn(handle_x87_floating_point);
+        idt.alignment_check.set_handler_fn(handle_alignment_check);
+        idt.machine_check.set_handler_fn(handle_machine_check);
+        idt.simd_floating_point.set_handler_fn(handle_simd_floating_point);
+        idt.virtualization.set_handler_fn(handle_virtualization);
+        idt.security_exception.set_handler_fn(handle_security_exception);
+        idt[usize::from(TIMER_VECTOR)].set_handler_fn(handle_timer);
+        idt[usize::from(KEYBOARD_VECTOR)].set_handler_fn(handle_keyboard);
+        idt[usize::from(SERIAL_VECTOR)].set_handler_fn(handle_serial);
+        idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(handle_spurious);
+        idt[usize::from(smp::tlb::VECTOR)].set_handler_fn(handle_tlb_shootdown);
+        // The system call stub saves registers itself instead of using the interrupt ABI
+        idt[usize::from(syscall::INTERRUPT_VECTOR)]
+            .set_handler_addr(syscall::syscall_interrupt_entry as u64)
+            .set_privilege_level(PrivilegeLevel::Ring3);
         unsafe {
             #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
             idt.double_fault.set_handler_fn(handle_double_fault)
@@ -29,59 +102,361 @@ lazy_static! {
     };
 }
 
-/// Set up Interrupt Descriptor Table and point to interrupt handlers
-pub fn init(memory_controller: &mut MemoryController) {
-    use x86_64::structures::gdt::SegmentSelector;
-    use x86_64::instructions::segmentation::set_cs;
-    use x86_64::instructions::tables::load_tss;
-
-    let double_fault_stack = memory_controller
-        .alloc_stack(1)
-        .expect("could not allocate double fault stack");
+impl CpuTables {
+    /// Create the TSS and GDT of a processor, with a double fault stack of its own
+    fn new(memory_controller: &mut MemoryController) -> Self {
+        let double_fault_stack = memory_controller
+            .alloc_stack(1, StackOwner::DoubleFault)
+            .expect("could not allocate double fault stack");
 
-    let tss = TSS.call_once(|| {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] =
             VirtualAddress(double_fault_stack.top());
-        tss
-    });
+        // The tables live as long as their processor, which is until the machine stops
+        let tss = Box::into_raw(Box::new(tss));
 
-    let mut code_selector = SegmentSelector(0);
-    let mut tss_selector = SegmentSelector(0);
-    let gdt = GDT.call_once(|| {
+        // SYSCALL and SYSRET expect the kernel data segment right after the kernel code segment,
+        // and the user code segment right after the user data segment
         let mut gdt = gdt::Gdt::new();
-        code_selector = gdt.add_entry(gdt::Descriptor::kernel_code_segment());
-        tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(tss));
-        gdt
-    });
-
-    gdt.load();
-
-    unsafe {
-        // Update code segment register
-        set_cs(code_selector);
-        // load TSS
-        load_tss(tss_selector);
+        let selectors = Selectors {
+            kernel_code: gdt.add_entry(gdt::Descriptor::kernel_code_segment()),
+            kernel_data: gdt.add_entry(gdt::Descriptor::kernel_data_segment()),
+            user_data: gdt.add_entry(gdt::Descriptor::user_data_segment()),
+            user_code: gdt.add_entry(gdt::Descriptor::user_code_segment()),
+            tss: gdt.add_entry(gdt::Descriptor::tss_segment(tss)),
+        };
+        SELECTORS.call_once(|| selectors);
+        let gdt: &'static gdt::Gdt = unsafe { &*Box::into_raw(Box::new(gdt)) };
+
+        Self { tss: tss, gdt: gdt }
+    }
+
+    /// Load the tables and the IDT on the running processor
+    fn load(&self) {
+        use x86_64::instructions::segmentation::{load_ds, load_es, load_ss, set_cs};
+        use x86_64::instructions::tables::load_tss;
+
+        let selectors = selectors();
+        self.gdt.load();
+        unsafe {
+            // Update segment registers
+            set_cs(selectors.kernel_code);
+            load_ss(selectors.kernel_data);
+            load_ds(selectors.kernel_data);
+            load_es(selectors.kernel_data);
+            // load TSS
+            load_tss(selectors.tss);
+        }
+        IDT.load();
+    }
+
+    /// Set the stack the processor switches to when an interrupt or exception arrives in user
+    /// mode
+    fn set_kernel_stack(&self, stack_top: usize) {
+        // The processor only reads the TSS when entering the kernel from user mode, which can't
+        // happen while the kernel is changing it
+        unsafe { (*self.tss).privilege_stack_table[0] = VirtualAddress(stack_top) };
+    }
+}
+
+/// Set up the descriptor tables, per-CPU data and local APIC of the bootstrap processor, and
+/// the interrupt controllers and timer
+pub fn init(memory_controller: &mut MemoryController) {
+    let tables = CpuTables::new(memory_controller);
+    tables.load();
+    apic::init(memory_controller);
+    smp::percpu::init(0, apic::id(), tables);
+
+    pic::init();
+    pit::init();
+    pic::unmask(pit::IRQ);
+    keyboard::init();
+    serial::enable_interrupts();
+}
+
+/// Set up the descriptor tables, per-CPU data and local APIC of an application processor
+pub fn init_ap(memory_controller: &mut MemoryController, cpu_id: usize) {
+    let tables = CpuTables::new(memory_controller);
+    tables.load();
+    apic::enable();
+    smp::percpu::init(cpu_id, apic::id(), tables);
+}
+
+/// Get the segment selectors of the GDT
+pub fn selectors() -> &'static Selectors {
+    SELECTORS.try().expect("GDT is not initialized")
+}
+
+/// Set the stack the running processor switches to when an interrupt or exception arrives in
+/// user mode
+pub fn set_kernel_stack(stack_top: usize) {
+    smp::percpu::current().tables().set_kernel_stack(stack_top);
+}
+
+/// Run f with interrupts disabled, restoring the previous interrupt state afterwards
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    use x86_64::instructions::interrupts;
+    use x86_64::registers::flags::{flags, Flags};
+
+    let enabled = flags().contains(Flags::IF);
+    if enabled {
+        unsafe { interrupts::disable() };
+    }
+    let result = f();
+    if enabled {
+        unsafe { interrupts::enable() };
+    }
+    result
+}
+
+/// Point the GS base back to the data of the running processor if the interrupt arrived in user
+/// mode, which may have changed it. Comes first in every handler that uses that data.
+fn restore_gs_base(stack_frame: &ExceptionStackFrame) {
+    if from_user_mode(stack_frame) {
+        smp::percpu::restore_gs_base();
+    }
+}
+
+/// Check whether an interrupt arrived in user mode
+fn from_user_mode(stack_frame: &ExceptionStackFrame) -> bool {
+    // The requested privilege level of the interrupted code segment is the ring it ran in
+    stack_frame.code_segment & 3 == PrivilegeLevel::Ring3 as u64
+}
+
+/// Kill the running process for an exception it caused in user mode. Exceptions caused by the
+/// kernel, or by user code running outside of a process, are fatal.
+#[cfg_attr(feature = "cargo-clippy", allow(use_debug))]
+fn handle_exception(
+    stack_frame: &mut ExceptionStackFrame,
+    name: &str,
+    error_code: Option<u64>,
+) -> ! {
+    use x86_64::instructions::interrupts;
+
+    restore_gs_base(stack_frame);
+    if from_user_mode(stack_frame) && process::current().is_some() {
+        println!(
+            "\nprocess killed by {} at {:#x}",
+            name, stack_frame.instruction_pointer.0
+        );
+        unsafe { interrupts::enable() };
+        process::exit(FAULT_EXIT_CODE);
+    }
+    match error_code {
+        Some(error_code) => emergency::panic(format_args!(
+            "\nException: {} with error code {:#x}\n{:#?}\n",
+            name, error_code, stack_frame
+        )),
+        None => emergency::panic(format_args!("\nException: {}\n{:#?}\n", name, stack_frame)),
     }
+}
+
+/// Handle a division by zero or a quotient that doesn't fit
+extern "x86-interrupt" fn handle_divide_by_zero(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "DIVIDE ERROR", None);
+}
+
+/// Handle a debug exception, such as a single step of code that set the trap flag
+extern "x86-interrupt" fn handle_debug(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "DEBUG", None);
+}
+
+/// Handle an overflow exception
+extern "x86-interrupt" fn handle_overflow(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "OVERFLOW", None);
+}
+
+/// Handle a bound range exceeded exception
+extern "x86-interrupt" fn handle_bound_range_exceeded(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "BOUND RANGE EXCEEDED", None);
+}
+
+/// Handle an invalid opcode, including privileged instructions that don't exist in user mode
+extern "x86-interrupt" fn handle_invalid_opcode(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "INVALID OPCODE", None);
+}
+
+/// Handle an x87 or SIMD instruction while the floating point unit is unavailable
+extern "x86-interrupt" fn handle_device_not_available(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "DEVICE NOT AVAILABLE", None);
+}
 
-    IDT.load();
+/// Handle an invalid TSS
+extern "x86-interrupt" fn handle_invalid_tss(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: u64,
+) {
+    handle_exception(stack_frame, "INVALID TSS", Some(error_code));
+}
+
+/// Handle a segment load of a segment that isn't present
+extern "x86-interrupt" fn handle_segment_not_present(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: u64,
+) {
+    handle_exception(stack_frame, "SEGMENT NOT PRESENT", Some(error_code));
+}
+
+/// Handle a stack segment fault, such as a non-canonical stack address
+extern "x86-interrupt" fn handle_stack_segment_fault(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: u64,
+) {
+    handle_exception(stack_frame, "STACK SEGMENT FAULT", Some(error_code));
+}
+
+/// Handle a general protection fault, such as a privileged instruction like hlt or cli in user
+/// mode
+extern "x86-interrupt" fn handle_general_protection_fault(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: u64,
+) {
+    handle_exception(stack_frame, "GENERAL PROTECTION FAULT", Some(error_code));
+}
+
+/// Handle an x87 floating point exception
+extern "x86-interrupt" fn handle_x87_floating_point(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "X87 FLOATING POINT", None);
+}
+
+/// Handle a misaligned access while alignment checking is enabled
+extern "x86-interrupt" fn handle_alignment_check(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: u64,
+) {
+    handle_exception(stack_frame, "ALIGNMENT CHECK", Some(error_code));
+}
+
+/// Handle a machine check, which reports a hardware error and is always fatal
+#[cfg_attr(feature = "cargo-clippy", allow(use_debug))]
+extern "x86-interrupt" fn handle_machine_check(stack_frame: &mut ExceptionStackFrame) {
+    emergency::panic(format_args!("\nException: MACHINE CHECK\n{:#?}\n", stack_frame));
+}
+
+/// Handle a SIMD floating point exception
+extern "x86-interrupt" fn handle_simd_floating_point(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "SIMD FLOATING POINT", None);
+}
+
+/// Handle a virtualization exception
+extern "x86-interrupt" fn handle_virtualization(stack_frame: &mut ExceptionStackFrame) {
+    handle_exception(stack_frame, "VIRTUALIZATION", None);
+}
+
+/// Handle a security exception
+extern "x86-interrupt" fn handle_security_exception(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: u64,
+) {
+    handle_exception(stack_frame, "SECURITY EXCEPTION", Some(error_code));
 }
 
 /// Handle a breakpoint exception
 #[allow(dead_code)]
 #[cfg_attr(feature = "cargo-clippy", allow(use_debug))]
 extern "x86-interrupt" fn handle_breakpoint(stack_frame: &mut ExceptionStackFrame) {
+    restore_gs_base(stack_frame);
     println!("\nException: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+/// Handle a page fault, backing lazily-allocated memory if possible
+#[allow(dead_code)]
+#[cfg_attr(feature = "cargo-clippy", allow(use_debug))]
+extern "x86-interrupt" fn handle_page_fault(
+    stack_frame: &mut ExceptionStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::instructions::interrupts;
+    use x86_64::registers::control_regs;
+
+    restore_gs_base(stack_frame);
+    let address = control_regs::cr2().0;
+    // If the faulting code could be preempted, so can servicing the fault. That way, a thread
+    // holding the memory controller gets to run and release it.
+    let can_wait = Flags::from_bits_truncate(stack_frame.cpu_flags).contains(Flags::IF);
+    if can_wait {
+        unsafe { interrupts::enable() };
+    }
+    let handled = memory::handle_page_fault(address, error_code, can_wait);
+    if can_wait {
+        unsafe { interrupts::disable() };
+    }
+    if handled {
+        return;
+    }
+    if error_code.contains(PageFaultErrorCode::USER_MODE) && process::current().is_some() {
+        println!(
+            "\nprocess killed by PAGE FAULT at {:#x}\n{:?}",
+            address, error_code
+        );
+        unsafe { interrupts::enable() };
+        process::exit(FAULT_EXIT_CODE);
+    }
+    if let Some(owner) = memory::stack_overflow_owner(address) {
+        emergency::panic(format_args!(
+            "\nException: STACK OVERFLOW on {} at {:#x}\n{:#?}\n",
+            owner, address, stack_frame
+        ));
+    }
+    emergency::panic(format_args!(
+        "\nException: PAGE FAULT at {:#x}\n{:?}\n{:#?}\n",
+        address, error_code, stack_frame
+    ));
+}
+
+/// Ignore a spurious interrupt from the local APIC, which must not be acknowledged
+extern "x86-interrupt" fn handle_spurious(_stack_frame: &mut ExceptionStackFrame) {}
+
+/// Invalidate the pages another processor asked to be shot down
+extern "x86-interrupt" fn handle_tlb_shootdown(stack_frame: &mut ExceptionStackFrame) {
+    restore_gs_base(stack_frame);
+    smp::tlb::handle_interrupt();
+}
+
+/// Handle a timer interrupt, preempting the running thread if its time slice is used up
+extern "x86-interrupt" fn handle_timer(stack_frame: &mut ExceptionStackFrame) {
+    restore_gs_base(stack_frame);
+    pit::tick();
+    task::timer::tick();
+    pic::end_of_interrupt(pit::IRQ);
+    scheduler::tick();
+}
+
+/// Handle a keyboard interrupt, waking a thread reading the console
+extern "x86-interrupt" fn handle_keyboard(stack_frame: &mut ExceptionStackFrame) {
+    restore_gs_base(stack_frame);
+    keyboard::handle_interrupt();
+    pic::end_of_interrupt(keyboard::IRQ);
+}
+
+/// Handle an interrupt of the first serial port, waking the tasks reading it
+extern "x86-interrupt" fn handle_serial(stack_frame: &mut ExceptionStackFrame) {
+    restore_gs_base(stack_frame);
+    serial::handle_interrupt();
+    pic::end_of_interrupt(serial::IRQ);
+}
+
 /// Handle a double fault
 #[allow(dead_code)]
 #[cfg_attr(feature = "cargo-clippy", allow(use_debug))]
-#[cfg_attr(feature = "cargo-clippy", allow(empty_loop))]
 extern "x86-interrupt" fn handle_double_fault(
     stack_frame: &mut ExceptionStackFrame,
     _error_code: u64,
 ) {
-    println!("\nException: DOUBLE FAULT\n{:#?}", stack_frame);
-    loop {}
+    use x86_64::registers::control_regs;
+
+    restore_gs_base(stack_frame);
+    // An overflowing stack pointer can't take the page fault, since pushing its frame faults again
+    let owner = memory::stack_overflow_owner(stack_frame.stack_pointer.0)
+        .or_else(|| memory::stack_overflow_owner(control_regs::cr2().0));
+    match owner {
+        Some(owner) => emergency::panic(format_args!(
+            "\nException: DOUBLE FAULT caused by stack overflow on {}\n{:#?}\n",
+            owner, stack_frame
+        )),
+        None => emergency::panic(format_args!("\nException: DOUBLE FAULT\n{:#?}\n", stack_frame)),
+    }
 }
@@ -0,0 +1,77 @@
+//! Legacy 8259 programmable interrupt controllers, remapped so that IRQs don't overlap CPU exceptions
+use x86_64::instructions::port::{inb, outb};
+
+/// Interrupt vector of IRQ 0
+pub const IRQ_OFFSET: u8 = 32;
+
+/// Command port of the primary PIC
+const PRIMARY_COMMAND: u16 = 0x20;
+/// Data port of the primary PIC
+const PRIMARY_DATA: u16 = 0x21;
+/// Command port of the secondary PIC
+const SECONDARY_COMMAND: u16 = 0xa0;
+/// Data port of the secondary PIC
+const SECONDARY_DATA: u16 = 0xa1;
+/// IRQ line of the primary PIC the secondary PIC is connected to
+const CASCADE_IRQ: u8 = 2;
+
+/// Initialization command word 1: start initialization, expect ICW4
+const ICW1_INIT: u8 = 0x11;
+/// Initialization command word 4: 8086 mode
+const ICW4_8086: u8 = 0x01;
+/// End of interrupt command
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// Remap both PICs to IRQ_OFFSET and mask every IRQ
+pub fn init() {
+    unsafe {
+        outb(PRIMARY_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(SECONDARY_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(PRIMARY_DATA, IRQ_OFFSET);
+        io_wait();
+        outb(SECONDARY_DATA, IRQ_OFFSET + 8);
+        io_wait();
+        outb(PRIMARY_DATA, 1 << CASCADE_IRQ);
+        io_wait();
+        outb(SECONDARY_DATA, CASCADE_IRQ);
+        io_wait();
+        outb(PRIMARY_DATA, ICW4_8086);
+        io_wait();
+        outb(SECONDARY_DATA, ICW4_8086);
+        io_wait();
+
+        // IRQs are unmasked one by one as handlers get installed
+        outb(PRIMARY_DATA, !(1 << CASCADE_IRQ));
+        outb(SECONDARY_DATA, 0xff);
+    }
+}
+
+/// Allow an IRQ to be raised
+pub fn unmask(irq: u8) {
+    let (port, line) = if irq < 8 {
+        (PRIMARY_DATA, irq)
+    } else {
+        (SECONDARY_DATA, irq - 8)
+    };
+    unsafe {
+        let mask = inb(port);
+        outb(port, mask & !(1 << line));
+    }
+}
+
+/// Acknowledge an IRQ, so that the PICs can raise it again
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(SECONDARY_COMMAND, END_OF_INTERRUPT);
+        }
+        outb(PRIMARY_COMMAND, END_OF_INTERRUPT);
+    }
+}
+
+/// Give the PICs time to process a command, by writing to an unused port
+fn io_wait() {
+    unsafe { outb(0x80, 0) };
+}
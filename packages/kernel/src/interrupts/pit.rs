@@ -0,0 +1,41 @@
+//! Programmable interval timer, used as the system tick
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use x86_64::instructions::port::outb;
+
+/// IRQ line of the PIT
+pub const IRQ: u8 = 0;
+/// Number of timer interrupts per second
+pub const TICKS_PER_SECOND: usize = 100;
+
+/// Frequency of the PIT's oscillator in Hz
+const BASE_FREQUENCY: usize = 1_193_182;
+/// Data port of channel 0
+const CHANNEL_0: u16 = 0x40;
+/// Mode/command port
+const COMMAND: u16 = 0x43;
+/// Channel 0, low byte then high byte, rate generator
+const RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Timer interrupts since the PIT was started
+static TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Program channel 0 to interrupt TICKS_PER_SECOND times per second
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn init() {
+    let divisor = BASE_FREQUENCY / TICKS_PER_SECOND;
+    unsafe {
+        outb(COMMAND, RATE_GENERATOR);
+        outb(CHANNEL_0, divisor as u8);
+        outb(CHANNEL_0, (divisor >> 8) as u8);
+    }
+}
+
+/// Count a timer interrupt
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Get the number of timer interrupts since the PIT was started
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,136 @@
+//! PS/2 keyboard, translating scancode set 1 on a US layout into the console's input
+//!
+//! Typed characters go to console reads and to tasks polling a Keys stream, whichever asks first.
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use interrupts::pic;
+use ring_buffer::RingBuffer;
+use sync::{Semaphore, SpinLock};
+use task::{Poll, Stream, Waker, WakerSet};
+use x86_64::instructions::port::inb;
+
+/// IRQ line of the keyboard
+pub const IRQ: u8 = 1;
+
+/// Port the keyboard controller hands out scancodes through
+const DATA_PORT: u16 = 0x60;
+/// Scancode bit set when a key is released
+const RELEASED: u8 = 0x80;
+/// Scancode prefix of extended keys, which are ignored
+const EXTENDED: u8 = 0xe0;
+/// Scancode of the left shift key
+const LEFT_SHIFT: u8 = 0x2a;
+/// Scancode of the right shift key
+const RIGHT_SHIFT: u8 = 0x36;
+/// Characters of the keys by scancode, with 0 for keys that don't type one
+const KEYS: &[u8] = b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+/// Characters of the keys by scancode while shift is held
+const SHIFTED_KEYS: &[u8] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// Characters typed but not read yet
+static INPUT: SpinLock<RingBuffer> = SpinLock::new("keyboard input", RingBuffer::new());
+/// Tasks waiting for a character
+static WAITERS: WakerSet = WakerSet::new();
+/// Whether a shift key is held
+static SHIFT: AtomicBool = ATOMIC_BOOL_INIT;
+/// Whether the last scancode was EXTENDED
+static EXTENDED_PENDING: AtomicBool = ATOMIC_BOOL_INIT;
+
+lazy_static! {
+    /// One permit per character in INPUT
+    static ref AVAILABLE: Semaphore = Semaphore::new(0);
+}
+
+/// Stream of typed characters
+pub struct Keys;
+
+/// Translate a scancode into the character it types, keeping track of the shift keys
+fn decode(scancode: u8) -> Option<u8> {
+    if scancode == EXTENDED {
+        EXTENDED_PENDING.store(true, Ordering::Relaxed);
+        return None;
+    }
+    if EXTENDED_PENDING.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    if scancode & RELEASED != 0 {
+        let key = scancode & !RELEASED;
+        if key == LEFT_SHIFT || key == RIGHT_SHIFT {
+            SHIFT.store(false, Ordering::Relaxed);
+        }
+        return None;
+    }
+    if scancode == LEFT_SHIFT || scancode == RIGHT_SHIFT {
+        SHIFT.store(true, Ordering::Relaxed);
+        return None;
+    }
+
+    let keys = if SHIFT.load(Ordering::Relaxed) {
+        SHIFTED_KEYS
+    } else {
+        KEYS
+    };
+    match keys.get(usize::from(scancode)) {
+        Some(&byte) if byte != 0 => Some(byte),
+        _ => None,
+    }
+}
+
+/// Get ready to take keyboard interrupts and unmask them
+pub fn init() {
+    // The semaphore allocates, which interrupt handlers must not be the first to do
+    ::lazy_static::initialize(&AVAILABLE);
+    pic::unmask(IRQ);
+}
+
+/// Handle a keyboard interrupt, queueing the typed character and waking a reader
+pub fn handle_interrupt() {
+    let scancode = unsafe { inb(DATA_PORT) };
+    if let Some(byte) = decode(scancode) {
+        // Characters typed while the buffer is full are dropped
+        if INPUT.lock().push(byte) {
+            AVAILABLE.release();
+            WAITERS.wake_all();
+        }
+    }
+}
+
+/// Read typed characters into buffer, sleeping until at least one is available. Returns the
+/// number of characters read.
+pub fn read(buffer: &mut [u8]) -> usize {
+    let mut count = 0;
+    while count < buffer.len() {
+        if count == 0 {
+            AVAILABLE.acquire();
+        } else if !AVAILABLE.try_acquire() {
+            break;
+        }
+        buffer[count] = take();
+        count += 1;
+    }
+    count
+}
+
+/// Take a character once its permit has been acquired
+fn take() -> u8 {
+    INPUT.lock().pop().expect("keyboard input and permits disagree")
+}
+
+/// Get a stream of typed characters
+pub fn keys() -> Keys {
+    Keys
+}
+
+impl Stream for Keys {
+    type Item = u8;
+
+    fn poll_next(&mut self, waker: &Waker) -> Poll<Option<u8>> {
+        // Registering first means a character typed right after the check still wakes the task
+        WAITERS.register(waker);
+        if AVAILABLE.try_acquire() {
+            Poll::Ready(Some(take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
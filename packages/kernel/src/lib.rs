@@ -9,6 +9,8 @@
 #![feature(unique)]
 #![feature(ptr_internals)]
 #![feature(abi_x86_interrupt)]
+#![feature(fnbox)]
+#![feature(asm)]
 #![cfg_attr(feature = "cargo-clippy", deny(clippy))]
 #![cfg_attr(feature = "cargo-clippy", deny(clippy_pedantic))]
 #![cfg_attr(feature = "cargo-clippy", allow(shadow_same))]
@@ -25,7 +27,7 @@ extern crate spin;
 extern crate volatile;
 extern crate x86_64;
 
-//#[macro_use]
+#[macro_use]
 extern crate alloc;
 #[macro_use]
 extern crate bitflags;
@@ -36,68 +38,279 @@ extern crate once;
 
 #[macro_use]
 mod vga_buffer;
+mod acpi;
+mod boot_modules;
+mod cpu;
+mod elf;
+mod emergency;
+mod fs;
+mod idle;
 mod memory;
 mod interrupts;
+mod keyboard;
+mod loader;
+mod pci;
+mod power;
+mod process;
+mod ring_buffer;
+mod scheduler;
+mod serial;
+mod smp;
+mod sync;
+mod syscall;
+mod task;
+mod usermode;
 
-use linked_list_allocator::LockedHeap;
+use alloc::arc::Arc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{mem, ptr};
+use interrupts::pit::TICKS_PER_SECOND;
+use memory::heap_allocator::LazyHeap;
+use memory::EntryFlags;
 //use alloc::boxed::Box;
 //use memory::heap_allocator::BumpAllocator;
 
-/// Start of heap space
-pub const HEAP_START: usize = 0o0_000_010_000_000_000;
-/// Size of heap space
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Size of heap space, which is only backed by frames as the heap grows into it
+pub const HEAP_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// Size of the part of the heap backed by frames at boot
+pub const HEAP_INITIAL_SIZE: usize = 1024 * 1024; // 1 MiB
+/// Start of the space stacks are allocated in
+pub const STACK_AREA_START: usize = 0o0_000_010_000_000_000;
+/// Size of the space stacks are allocated in
+pub const STACK_AREA_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// Number of pages of the lazily-backed region rust_main touches
+const LAZY_DEMO_PAGES: usize = 256;
 
 #[global_allocator]
 /// Global heap allocator
 //static HEAP_ALLOCATOR: BumpAllocator = BumpAllocator::new(HEAP_START, HEAP_START + HEAP_SIZE);
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: LazyHeap = LazyHeap::empty();
+
+/// Print what the ACPI tables say about processors, interrupt controllers and timers
+#[cfg_attr(feature = "cargo-clippy", allow(use_debug))]
+fn print_acpi_summary() {
+    if let Some(madt) = acpi::madt() {
+        let processors = madt
+            .entries()
+            .filter(|entry| match *entry {
+                acpi::MadtEntry::LocalApic { enabled, .. }
+                | acpi::MadtEntry::LocalX2Apic { enabled, .. } => enabled,
+                _ => false,
+            })
+            .count();
+        println!(
+            "acpi: {} processors, local APIC at {:#x}, legacy PICs: {}",
+            processors,
+            madt.local_apic_address(),
+            madt.has_legacy_pics()
+        );
+        for entry in madt.entries() {
+            match entry {
+                acpi::MadtEntry::LocalApic { .. } | acpi::MadtEntry::LocalX2Apic { .. } => {}
+                _ => println!("    {:?}", entry),
+            }
+        }
+    }
+    if let Some(fadt) = acpi::fadt() {
+        println!(
+            "acpi: SCI on IRQ {}, PM1a control at {:#x}, reset register {:?}",
+            fadt.sci_interrupt(),
+            fadt.pm1a_control_block(),
+            fadt.reset_register()
+        );
+    }
+    if let Some(hpet) = acpi::hpet() {
+        println!(
+            "acpi: HPET {} rev {} from vendor {:04x} at {:#x}, {} comparators, 64 bit: {}, \
+             legacy replacement: {}, minimum tick {}",
+            hpet.hpet_number(),
+            hpet.hardware_revision(),
+            hpet.vendor_id(),
+            hpet.base_address().address,
+            hpet.comparator_count(),
+            hpet.has_64_bit_counter(),
+            hpet.supports_legacy_replacement(),
+            hpet.minimum_tick()
+        );
+    }
+}
 
 #[no_mangle]
 /// The first Rust code that runs when we boot. On x86_64, it is called from long_start.asm.
-pub extern "C" fn rust_main(multiboot_information_address: usize) {
+pub extern "C" fn rust_main(multiboot_information_address: usize) -> ! {
     #![cfg_attr(feature = "cargo-clippy", allow(use_debug))]
     vga_buffer::clear_screen();
+    serial::init();
 
     let boot_info = unsafe { multiboot2::load(multiboot_information_address) };
-    enable_nxe_bit();
+    cpu::init();
+    enable_syscall_extensions();
     enable_write_protect_bit();
-    let mut memory_controller = memory::init(boot_info);
-    unsafe {
-        HEAP_ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    memory::init(boot_info);
+    boot_modules::init(boot_info);
+    if let Err(error) = acpi::init(boot_info) {
+        println!("acpi: {:?}, continuing without it", error);
+    }
+    print_acpi_summary();
+    power::init(boot_info);
+    interrupts::init(&mut memory::controller());
+    syscall::init();
+    scheduler::init();
+    unsafe { x86_64::instructions::interrupts::enable() };
+    smp::init();
+
+    pci::register_driver(&pci::bochs_display::DRIVER);
+    pci::init();
+    for (device, driver) in pci::devices() {
+        println!("pci {} ({})", device, driver.unwrap_or("no driver"));
+        let interrupts = device
+            .capability(pci::CAPABILITY_MSI_X)
+            .or_else(|| device.capability(pci::CAPABILITY_MSI));
+        if let Some(capability) = interrupts {
+            println!("    {:?}", capability);
+        }
     }
-    interrupts::init(&mut memory_controller);
 
     // Raise a breakpoint exception
     x86_64::instructions::interrupts::int3();
 
-    unsafe {
-        *(0xffff_ffff as *mut u64) = 42;
+    // Crash on purpose if the command line asks for it, such as with `crash=stack-overflow`
+    match power::command_line_option(boot_info, "crash") {
+        Some("page-fault") => unsafe { ptr::write_volatile(0xffff_ffff as *mut u64, 42) },
+        Some("stack-overflow") => stack_overflow(),
+        Some(name) => println!("unknown crash {}, expected page-fault or stack-overflow", name),
+        None => {}
     }
 
-    #[allow(unused_unsafe)]
+    // Outgrow the part of the heap backed at boot
+    let large = vec![0u8; 2 * HEAP_INITIAL_SIZE];
+    println!("Allocated {} KiB on the heap", large.len() / 1024);
+    drop(large);
+
+    // Touch the first and last page of a lazily-backed region, which get frames through the page
+    // fault handler while the pages in between stay unbacked
+    let region = memory::controller()
+        .alloc_lazy(LAZY_DEMO_PAGES, EntryFlags::WRITABLE, "lazy demo")
+        .expect("could not reserve a lazy region");
+    let (first, last) = (
+        region.start_address() as *mut usize,
+        (region.end_address() - mem::size_of::<usize>()) as *mut usize,
+    );
     unsafe {
-        /// Overflow the kernel stack
-        #[allow(unconditional_recursion)]
-        fn stack_overflow() {
-            stack_overflow();
-        }
-        stack_overflow()
+        ptr::write_volatile(first, 1);
+        ptr::write_volatile(last, 2);
+        println!(
+            "Lazy region at {:#x} holds {} and {}",
+            region.start_address(),
+            ptr::read_volatile(first),
+            ptr::read_volatile(last)
+        );
+    }
+    memory::controller().free_lazy(region);
+
+    let workers: Vec<_> = (0..3)
+        .map(|n| scheduler::spawn(move || println!("Hello from worker thread {}", n)))
+        .collect();
+    for worker in workers {
+        scheduler::join(worker);
+    }
+
+    // Hand a message to a thread through a mutex and condition variable, and wait for it to
+    // report back through an event
+    let mailbox = Arc::new((sync::Mutex::new(None), sync::Condvar::new()));
+    let delivered = Arc::new(sync::Event::new());
+    let receiver = {
+        let mailbox = Arc::clone(&mailbox);
+        let delivered = Arc::clone(&delivered);
+        scheduler::spawn(move || {
+            let (ref slot, ref arrived) = *mailbox;
+            let mut message = slot.lock();
+            while message.is_none() {
+                message = arrived.wait(message);
+            }
+            println!("Receiver thread got {:?}", *message);
+            delivered.set();
+        })
     };
+    *mailbox.0.lock() = Some("a message");
+    mailbox.1.notify_one();
+    delivered.wait();
+    scheduler::join(receiver);
+
+    // Run a tiny program in user mode, which greets through both system call entries
+    let user_demo = scheduler::spawn(|| usermode::run_flat_program(usermode::demo_program()));
+    scheduler::join(user_demo);
+
+    // Mount the initrd GRUB loaded as a boot module
+    match boot_modules::find("initrd") {
+        Some(module) => {
+            let initrd = fs::tar::TarFileSystem::new(module.data()).expect("initrd is corrupt");
+            fs::mount("/", Arc::new(initrd)).expect("could not mount initrd");
+        }
+        None => {
+            println!("no initrd module, boot modules: {:?}", boot_modules::all());
+            fs::mount("/", Arc::new(fs::tmp::TmpFileSystem::new())).expect("could not mount /");
+            fs::mkdir("/tmp").expect("could not create /tmp");
+        }
+    }
+    fs::mount("/tmp", Arc::new(fs::tmp::TmpFileSystem::new())).expect("could not mount /tmp");
+
+    let scratch = fs::create("/tmp/scratch")
+        .and_then(|mut scratch| scratch.write(b"Hello from tmpfs\n"))
+        .and_then(|_| fs::rename("/tmp/scratch", "/tmp/greeting"))
+        .and_then(|_| fs::open("/tmp/greeting"))
+        .and_then(|mut greeting| greeting.read_to_end());
+    match scratch {
+        Ok(contents) => print!("{}", String::from_utf8_lossy(&contents)),
+        Err(error) => println!("tmpfs error: {:?}", error),
+    }
+    if let Err(error) = fs::unlink("/tmp/greeting") {
+        println!("tmpfs error: {:?}", error);
+    }
+
+    // Run the first user program from the initrd as a process
+    let init = process::spawn("/bin/init")
+        .and_then(|pid| process::wait_for(pid).map(|code| (pid, code)));
+    match init {
+        Ok((pid, code)) => println!("process {} exited with code {}", pid.0, code),
+        Err(error) => println!("could not run /bin/init: {:?}", error),
+    }
 
     println!("Yay no crash!");
 
-    #[cfg_attr(feature = "cargo-clippy", allow(empty_loop))]
-    loop {}
+    // Echo the keyboard and the serial port and report the uptime from tasks, for as long as
+    // the kernel runs
+    let mut executor = task::Executor::new();
+    executor.spawn(keyboard::keys().for_each(|byte| print!("{}", byte as char)));
+    executor.spawn(serial::input().for_each(|byte| {
+        // Terminals send a carriage return for the enter key
+        let character = if byte == b'\r' { '\n' } else { byte as char };
+        print!("{}", character);
+    }));
+    executor.spawn(
+        task::timer::every(60 * TICKS_PER_SECOND)
+            .for_each(|ticks| println!("uptime: {} s", ticks / TICKS_PER_SECOND)),
+    );
+    executor.run()
+}
+
+/// Overflow the kernel stack. The frame is used after the recursive call, so that the
+/// recursion can't turn into a loop.
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    let frame = [0u8; 64];
+    stack_overflow();
+    unsafe { ptr::read_volatile(&frame) };
 }
 
-/// Enable the NXE bit in the extended feature register (EFER) allowing the NO_EXECUTE bit to be set on pages
-fn enable_nxe_bit() {
+/// Enable the SCE bit in the extended feature register (EFER) allowing the syscall and sysret instructions
+fn enable_syscall_extensions() {
     use x86_64::registers::msr::{rdmsr, wrmsr, IA32_EFER};
-    let nxe_bit = 1 << 11;
+    let sce_bit = 1;
     unsafe {
         let efer = rdmsr(IA32_EFER);
-        wrmsr(IA32_EFER, efer | nxe_bit);
+        wrmsr(IA32_EFER, efer | sce_bit);
     }
 }
 
@@ -113,10 +326,8 @@ extern "C" fn eh_personality() {}
 
 #[lang = "panic_fmt"]
 #[no_mangle]
-/// The Rust compiler requires this for panic handling. Currently just loops forever.
+/// The Rust compiler requires this for panic handling. Reports the panic, then halts, reboots or
+/// exits QEMU as the panic policy says.
 pub extern "C" fn panic_fmt(fmt: core::fmt::Arguments, file: &'static str, line: u32) -> ! {
-    println!("PANIC in {} at line {}:", file, line);
-    println!("\t{}", fmt);
-    #[cfg_attr(feature = "cargo-clippy", allow(empty_loop))]
-    loop {}
+    emergency::panic(format_args!("PANIC in {} at line {}:\n\t{}\n", file, line, fmt))
 }
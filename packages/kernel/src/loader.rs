@@ -0,0 +1,160 @@
+//! Load ELF executables into fresh user address spaces
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use elf::{self, ElfFile, PT_LOAD};
+use memory::{self, EntryFlags, InactivePageTable, Page, PAGE_SIZE};
+use usermode::{USER_SPACE_END, USER_SPACE_START, USER_STACK_TOP};
+
+/// Number of pages in the initial user stack
+const USER_STACK_PAGES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons a program can't be loaded
+pub enum Error {
+    /// The image is not a usable ELF executable
+    Elf(elf::Error),
+    /// A segment or the entry point lies outside user space
+    OutsideUserSpace,
+}
+
+/// A loadable segment of a program, checked to lie in user space
+struct Segment<'a> {
+    /// Start address of the first page of the segment
+    start: usize,
+    /// End address of the last page of the segment
+    end: usize,
+    /// Page flags the segment asks for
+    flags: EntryFlags,
+    /// Contents of the segment from the image. The rest of it, up to its memory size, stays
+    /// zeroed.
+    data: &'a [u8],
+    /// Virtual address of data
+    data_start: usize,
+}
+
+/// A program loaded into its own address space, ready to run
+pub struct Program {
+    /// Address space holding the program and its stack
+    pub address_space: InactivePageTable,
+    /// Virtual address execution starts at
+    pub entry_point: usize,
+    /// Initial user stack pointer
+    pub stack_top: usize,
+}
+
+impl From<elf::Error> for Error {
+    fn from(error: elf::Error) -> Self {
+        Error::Elf(error)
+    }
+}
+
+/// Get the pages covering size bytes at address, checking that they lie in user space
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+fn user_pages(address: u64, size: u64) -> Result<(Page, Page), Error> {
+    let start = address as usize;
+    let end = start
+        .checked_add(size as usize)
+        .ok_or(Error::OutsideUserSpace)?;
+    if start < USER_SPACE_START || end > USER_SPACE_END || size == 0 {
+        return Err(Error::OutsideUserSpace);
+    }
+    Ok((
+        Page::containing_address(start),
+        Page::containing_address(end - 1),
+    ))
+}
+
+/// Combine the flags of two segments sharing a page, which is writable or executable if either
+/// of them is
+fn union_flags(first: EntryFlags, second: EntryFlags) -> EntryFlags {
+    ((first | second) - EntryFlags::NO_EXECUTE) | (first & second & EntryFlags::NO_EXECUTE)
+}
+
+/// Load an ELF executable into a new address space. Segments are mapped with the permissions
+/// from their program headers, pages shared by segments with those of all of them, and a user
+/// stack is mapped below USER_STACK_TOP.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn load(image: &[u8]) -> Result<Program, Error> {
+    let file = ElfFile::parse(image)?;
+    let entry_point = file.entry_point() as usize;
+    if entry_point < USER_SPACE_START || entry_point >= USER_SPACE_END {
+        return Err(Error::OutsideUserSpace);
+    }
+
+    // Check every segment before mapping anything, so that a bad image leaves no mappings behind.
+    // Linkers may put segments into the same page, so pages are mapped in runs between the page
+    // boundaries of the segments, which are covered by the same segments throughout.
+    let mut segments = Vec::new();
+    let mut boundaries = Vec::new();
+    for header in file.program_headers()
+        .filter(|header| header.kind == PT_LOAD && header.memory_size > 0)
+    {
+        let (start, end) = user_pages(header.virtual_address, header.memory_size)?;
+        segments.push(Segment {
+            start: start.start_address(),
+            end: end.start_address() + PAGE_SIZE,
+            flags: EntryFlags::from_elf_program_flags(&header),
+            data: file.segment_data(&header),
+            data_start: header.virtual_address as usize,
+        });
+        boundaries.push(start.start_address());
+        boundaries.push(end.start_address() + PAGE_SIZE);
+    }
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut memory = memory::controller();
+    let mut address_space = memory.new_address_space();
+    for run in boundaries.windows(2) {
+        let (run_start, run_end) = (run[0], run[1]);
+        let flags = segments
+            .iter()
+            .filter(|segment| segment.start <= run_start && run_start < segment.end)
+            .fold(None, |combined, segment| {
+                Some(combined.map_or(segment.flags, |flags| union_flags(flags, segment.flags)))
+            });
+        let flags = match flags {
+            Some(flags) => flags | EntryFlags::USER_ACCESSIBLE,
+            None => continue,
+        };
+
+        memory.map_inactive(
+            &mut address_space,
+            Page::range_inclusive(
+                Page::containing_address(run_start),
+                Page::containing_address(run_end - 1),
+            ),
+            flags,
+            |page, contents| {
+                // Copy the parts of the segments' data that fall into this page
+                let page_start = page.start_address();
+                for segment in &segments {
+                    let data_end = segment.data_start + segment.data.len();
+                    let copy_start = max(page_start, segment.data_start);
+                    let copy_end = min(page_start + PAGE_SIZE, data_end);
+                    if copy_start < copy_end {
+                        contents[copy_start - page_start..copy_end - page_start].copy_from_slice(
+                            &segment.data
+                                [copy_start - segment.data_start..copy_end - segment.data_start],
+                        );
+                    }
+                }
+            },
+        );
+    }
+
+    let stack_end = Page::containing_address(USER_STACK_TOP - 1);
+    let stack_start = Page::containing_address(USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE);
+    memory.map_inactive(
+        &mut address_space,
+        Page::range_inclusive(stack_start, stack_end),
+        EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+        |_, _| {},
+    );
+
+    Ok(Program {
+        address_space: address_space,
+        entry_point: entry_point,
+        stack_top: USER_STACK_TOP,
+    })
+}
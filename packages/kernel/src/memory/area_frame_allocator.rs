@@ -1,8 +1,14 @@
-//! AreaFrameAllocator allocates page frames sequentially, without freeing them.
+//! AreaFrameAllocator allocates page frames sequentially, reusing frames once they are freed.
+use alloc::btree_map::BTreeMap;
+use alloc::vec::Vec;
 use memory::{Frame, FrameAllocator};
 use multiboot2::{MemoryArea, MemoryAreaIter};
 
-/// AreaFrameAllocator allocates page frames sequentially, avoiding kernel and multiboot info struct
+/// Maximum number of physical memory ranges that can be reserved
+const MAX_RESERVED_AREAS: usize = 16;
+
+/// AreaFrameAllocator allocates page frames sequentially, avoiding kernel, multiboot info struct
+/// and reserved areas such as boot modules
 pub struct AreaFrameAllocator {
     /// The next frame that can be allocated in the current memory area, set to none if there is no space left
     next_free_frame: Frame,
@@ -18,10 +24,23 @@ pub struct AreaFrameAllocator {
     multiboot_start: Frame,
     /// Frame where the end of the multiboot info structure is stored
     multiboot_end: Frame,
+    /// First and last frame numbers of reserved areas. This is an array rather than a Vec since
+    /// areas are reserved before the heap exists.
+    reserved_areas: [Option<(usize, usize)>; MAX_RESERVED_AREAS],
+    /// Frames that have been freed and can be handed out again
+    free_frames: Vec<Frame>,
+    /// Reference counts of frames mapped more than once (copy-on-write), by frame number. None
+    /// until a frame is first shared, since the allocator is created before the heap exists and
+    /// an empty BTreeMap already allocates.
+    shared_frames: Option<BTreeMap<usize, usize>>,
 }
 
 impl FrameAllocator for AreaFrameAllocator {
     fn allocate_frame(&mut self) -> Option<Frame> {
+        if let Some(frame) = self.free_frames.pop() {
+            return Some(frame);
+        }
+
         if let Some(area) = self.current_area {
             // "Clone" the frame to return it if it's free. Frame doesn't
             // implement Clone, but we can construct an identical frame.
@@ -49,6 +68,9 @@ impl FrameAllocator for AreaFrameAllocator {
                 self.next_free_frame = Frame {
                     number: self.multiboot_end.number + 1,
                 };
+            } else if let Some(last) = self.reserved_area_end(&frame) {
+                // `frame` is in a reserved area
+                self.next_free_frame = Frame { number: last + 1 };
             } else {
                 // frame is unused, increment `next_free_frame` and return it
                 self.next_free_frame.number += 1;
@@ -61,8 +83,29 @@ impl FrameAllocator for AreaFrameAllocator {
         }
     }
 
-    fn deallocate_frame(&mut self, _frame: Frame) {
-        unimplemented!()
+    fn deallocate_frame(&mut self, frame: Frame) {
+        // Drop one reference, only freeing the frame when the last one is gone
+        let shared_frames = match self.shared_frames {
+            Some(ref mut shared_frames) => shared_frames,
+            None => {
+                self.free_frames.push(frame);
+                return;
+            }
+        };
+        let remaining = match shared_frames.get_mut(&frame.number) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => 0,
+        };
+        match remaining {
+            0 => self.free_frames.push(frame),
+            1 => {
+                shared_frames.remove(&frame.number);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -83,11 +126,55 @@ impl AreaFrameAllocator {
             kernel_end: Frame::containing_address(kernel_end),
             multiboot_start: Frame::containing_address(multiboot_start),
             multiboot_end: Frame::containing_address(multiboot_end),
+            reserved_areas: [None; MAX_RESERVED_AREAS],
+            free_frames: Vec::new(),
+            shared_frames: None,
         };
         allocator.choose_next_area();
         allocator
     }
 
+    /// Never hand out the frames of the physical memory from start to end (exclusive). Must be
+    /// called before frames in the area are allocated.
+    pub fn reserve(&mut self, start: usize, end: usize) {
+        assert!(end > start, "reserved area is empty");
+        let area = (
+            Frame::containing_address(start).number,
+            Frame::containing_address(end - 1).number,
+        );
+        let slot = self.reserved_areas
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("too many reserved areas");
+        *slot = Some(area);
+    }
+
+    /// Get the last frame number of the reserved area containing frame
+    fn reserved_area_end(&self, frame: &Frame) -> Option<usize> {
+        self.reserved_areas
+            .iter()
+            .filter_map(|area| *area)
+            .find(|&(first, last)| frame.number >= first && frame.number <= last)
+            .map(|(_, last)| last)
+    }
+
+    /// Add a reference to an allocated frame, so that it is only freed once every reference is deallocated
+    pub fn share_frame(&mut self, frame: &Frame) {
+        *self.shared_frames
+            .get_or_insert_with(BTreeMap::new)
+            .entry(frame.number)
+            .or_insert(1) += 1;
+    }
+
+    /// Get the number of references to an allocated frame
+    pub fn reference_count(&self, frame: &Frame) -> usize {
+        self.shared_frames
+            .as_ref()
+            .and_then(|shared_frames| shared_frames.get(&frame.number))
+            .cloned()
+            .unwrap_or(1)
+    }
+
     /// Finds next area with free space for page frames
     #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
     fn choose_next_area(&mut self) {
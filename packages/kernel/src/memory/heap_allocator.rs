@@ -1,6 +1,15 @@
 //! Heap allocator allocates rust objects such as Box, Vec, BTreeMap, and types in the alloc crate
 use alloc::heap::{Alloc, AllocErr, Layout};
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use linked_list_allocator::Heap;
+use memory::{self, EntryFlags, Page, PAGE_SIZE};
+use spin::Mutex;
+
+/// Number of bytes the heap grows by at least
+const GROWTH_STEP: usize = 256 * 1024;
+/// Number of free bytes below which the heap grows ahead of time. Allocations made while the
+/// memory controller is locked can't grow the heap, so they need room left for them.
+const GROWTH_RESERVE: usize = 256 * 1024;
 
 /// A simple allocator that allocates memory linearly and ignores freed memory
 #[derive(Debug)]
@@ -53,6 +62,95 @@ unsafe impl<'a> Alloc for &'a BumpAllocator {
     }
 }
 
+/// Linked list heap in a lazily-backed region. Only the part of the region handed to the heap so
+/// far is backed by frames, and the heap grows into the rest once it runs low.
+pub struct LazyHeap {
+    /// The heap, spanning the backed part of the region
+    heap: Mutex<Heap>,
+    /// End of the region the heap grows into
+    end: AtomicUsize,
+    /// Number of bytes allocated and not freed yet
+    used: AtomicUsize,
+    /// Held while growing, so that no page is backed twice
+    growing: Mutex<()>,
+}
+
+impl LazyHeap {
+    /// Create a heap without any memory
+    pub const fn empty() -> Self {
+        Self {
+            heap: Mutex::new(Heap::empty()),
+            end: ATOMIC_USIZE_INIT,
+            used: ATOMIC_USIZE_INIT,
+            growing: Mutex::new(()),
+        }
+    }
+
+    /// Hand the heap the region of size bytes at start, of which the first initial_size bytes
+    /// are backed already. Unsafe since the region must not be used for anything else.
+    pub unsafe fn init(&self, start: usize, initial_size: usize, size: usize) {
+        self.heap.lock().init(start, initial_size);
+        self.end.store(start + size, Ordering::SeqCst);
+    }
+
+    /// Back by more bytes of the region, at least GROWTH_STEP or else the rest of the region, and
+    /// hand them to the heap.
+    /// Returns whether the heap grew, which it doesn't while the memory controller is locked
+    /// since the allocation asking for room may be made under that lock.
+    fn grow(&self, by: usize) -> bool {
+        let _growing = match self.growing.try_lock() {
+            Some(guard) => guard,
+            None => return false,
+        };
+        let top = self.heap.lock().top();
+        let remaining = self.end.load(Ordering::SeqCst) - top;
+        let by = align_up(by.max(GROWTH_STEP), PAGE_SIZE).min(remaining);
+        if by == 0 {
+            return false;
+        }
+        let mut controller = match memory::try_controller() {
+            Some(controller) => controller,
+            None => return false,
+        };
+
+        let pages = Page::range_inclusive(
+            Page::containing_address(top),
+            Page::containing_address(top + by - 1),
+        );
+        for page in pages {
+            controller.map_zeroed(page, EntryFlags::WRITABLE);
+        }
+        unsafe { self.heap.lock().extend(by) };
+        true
+    }
+}
+
+unsafe impl<'a> Alloc for &'a LazyHeap {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        loop {
+            let result = self.heap.lock().allocate_first_fit(layout.clone());
+            match result {
+                Ok(pointer) => {
+                    let used = self.used.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+                    let size = self.heap.lock().size();
+                    if size.saturating_sub(used) < GROWTH_RESERVE {
+                        self.grow(GROWTH_STEP);
+                    }
+                    return Ok(pointer);
+                }
+                Err(error) => if !self.grow(layout.size() + layout.align()) {
+                    return Err(error);
+                },
+            }
+        }
+    }
+
+    unsafe fn dealloc(&mut self, pointer: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(pointer, layout.clone());
+        self.used.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
 /// Align downwards. Returns the greatest x with alignment `align`
 /// so that x <= addr. The alignment must be a power of 2.
 fn align_down(addr: usize, align: usize) -> usize {
@@ -1,14 +1,27 @@
 //! Memory module: handles all kernel memory operations including allocating page frames and memory
 pub use self::area_frame_allocator::AreaFrameAllocator;
-pub use self::paging::EntryFlags;
-use self::paging::{Page, PhysicalAddress};
-use self::stack_allocator::Stack;
+pub use self::paging::{EntryFlags, InactivePageTable, MappedRange, Page, PageIter};
+pub use self::region::Region;
+pub use self::stack_allocator::{stack_overflow_owner, Stack, StackOwner};
+pub use self::paging::PhysicalAddress;
+use self::paging::{TemporaryPage, VirtualAddress};
+use alloc::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use multiboot2::BootInformation;
-use {HEAP_SIZE, HEAP_START};
+use spin::{Mutex, MutexGuard, Once};
+use x86_64::structures::idt::PageFaultErrorCode;
+use scheduler;
+use smp;
+use usermode::{USER_SPACE_END, USER_SPACE_START};
+use {HEAP_ALLOCATOR, HEAP_INITIAL_SIZE, HEAP_SIZE, STACK_AREA_SIZE, STACK_AREA_START};
 
 mod area_frame_allocator;
 pub mod heap_allocator;
 mod paging;
+mod region;
 mod stack_allocator;
 
 /// FrameAllocator allocates and deallocates page frames
@@ -45,11 +58,40 @@ pub struct MemoryController {
     frame_allocator: AreaFrameAllocator,
     /// Stack allocator
     stack_allocator: stack_allocator::StackAllocator,
+    /// Lazily-backed regions of kernel memory
+    region_manager: region::RegionManager,
+    /// Lazily-backed regions of user memory handed out by the mmap system call, by the physical
+    /// address of the P4 table of the address space they belong to
+    user_region_managers: BTreeMap<PhysicalAddress, region::RegionManager>,
+    /// Temporary page used to access inactive page tables
+    temporary_page: TemporaryPage,
 }
 
 /// Size of each page frame
 pub const PAGE_SIZE: usize = 0x1000;
 
+/// Start of the area lazily-backed regions are reserved in
+pub const LAZY_AREA_START: usize = 0o0_000_020_000_000_000;
+/// Size of the area lazily-backed regions are reserved in
+pub const LAZY_AREA_SIZE: usize = 0o0_000_010_000_000_000; // 1 GiB
+/// Start of the area lazily-backed user regions are reserved in
+pub const USER_LAZY_AREA_START: usize = 0o0_100_000_000_000_000;
+/// Size of the area lazily-backed user regions are reserved in
+pub const USER_LAZY_AREA_SIZE: usize = 0o0_100_000_000_000_000; // 32 TiB
+/// Start of the window PCI configuration space is mapped in, outside of any region
+pub const ECAM_WINDOW_START: usize = 0o0_000_030_000_000_000;
+/// Size of the window PCI configuration space is mapped in
+pub const ECAM_WINDOW_SIZE: usize = 0o0_000_040_000_000_000; // 4 GiB
+
+/// Address of the temporary page used by the memory controller, just below the stack area
+const TEMPORARY_PAGE_ADDRESS: usize = STACK_AREA_START - PAGE_SIZE;
+
+/// The memory controller, available once memory::init has been called
+static MEMORY_CONTROLLER: Once<Mutex<MemoryController>> = Once::new();
+/// Id plus one of the thread holding the memory controller, or 0 if it is free or was locked
+/// outside of a thread
+static CONTROLLER_OWNER: AtomicUsize = ATOMIC_USIZE_INIT;
+
 impl Frame {
     /// Set frame to correspond to physical address
     fn containing_address(address: usize) -> Self {
@@ -94,19 +136,554 @@ impl Iterator for FrameIter {
 }
 
 impl MemoryController {
-    /// Allocate a new stack
-    pub fn alloc_stack(&mut self, size_in_pages: usize) -> Option<Stack> {
+    /// Allocate a new stack, guarded by an unmapped page
+    pub fn alloc_stack(&mut self, size_in_pages: usize, owner: StackOwner) -> Option<Stack> {
         let &mut Self {
             ref mut active_table,
             ref mut frame_allocator,
             ref mut stack_allocator,
+            ..
         } = self;
-        stack_allocator.alloc_stack(active_table, frame_allocator, size_in_pages)
+        stack_allocator.alloc_stack(active_table, frame_allocator, size_in_pages, owner)
+    }
+
+    /// Free a stack, so that its memory can be used again
+    pub fn free_stack(&mut self, stack: Stack) {
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut stack_allocator,
+            ..
+        } = self;
+        stack_allocator.free_stack(stack, active_table, frame_allocator);
+    }
+
+    /// Reserve a region of kernel memory whose pages are only backed by frames once touched
+    pub fn alloc_lazy(
+        &mut self,
+        size_in_pages: usize,
+        flags: EntryFlags,
+        name: &'static str,
+    ) -> Option<Region> {
+        self.region_manager.reserve(size_in_pages, flags, name)
+    }
+
+    /// Reserve a region of user memory whose pages are only backed by frames once touched
+    pub fn alloc_user_lazy(
+        &mut self,
+        size_in_pages: usize,
+        flags: EntryFlags,
+        name: &'static str,
+    ) -> Option<Region> {
+        self.user_region_managers
+            .entry(active_address_space())
+            .or_insert_with(|| {
+                region::RegionManager::new(
+                    USER_LAZY_AREA_START,
+                    USER_LAZY_AREA_START + USER_LAZY_AREA_SIZE,
+                )
+            })
+            .reserve(size_in_pages, flags | EntryFlags::USER_ACCESSIBLE, name)
+    }
+
+    /// Find the lazily-backed user region of the active address space containing address
+    fn find_user_region(&self, address: VirtualAddress) -> Option<Region> {
+        self.user_region_managers
+            .get(&active_address_space())
+            .and_then(|regions| regions.find(address))
+    }
+
+    /// Check whether user mode may access the given range of memory, either because it is mapped
+    /// user accessible or because it belongs to a lazily-backed user region. Copy-on-write pages
+    /// count as writable.
+    pub fn check_user_range(&self, start: VirtualAddress, size: usize, write: bool) -> bool {
+        if size == 0 {
+            return true;
+        }
+        let end = match start.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+        if end > USER_SPACE_END {
+            return false;
+        }
+
+        let pages = Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(end - 1),
+        );
+        for page in pages {
+            let flags = match self.active_table.page_flags(page) {
+                Some(flags) => flags,
+                None => match self.find_user_region(page.start_address()) {
+                    Some(region) => region.flags(),
+                    None => return false,
+                },
+            };
+            let writable = flags.contains(EntryFlags::WRITABLE)
+                || flags.contains(EntryFlags::COPY_ON_WRITE);
+            if !flags.contains(EntryFlags::USER_ACCESSIBLE) || (write && !writable) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Release a lazily-backed region, unmapping every page that has been touched
+    pub fn free_lazy(&mut self, region: Region) {
+        let region = self.region_manager
+            .release(region.start_address())
+            .expect("region was not reserved");
+        for page in region.pages() {
+            if self.active_table.translate_page(page).is_some() {
+                let frame = self.active_table.unmap(page, &mut self.frame_allocator);
+                self.frame_allocator.deallocate_frame(frame);
+            }
+        }
+    }
+
+    /// Map physical memory outside of the frame allocator's control, such as device registers or
+    /// firmware tables, into a new region of kernel memory. Returns the virtual address of start.
+    pub fn map_physical(
+        &mut self,
+        start: PhysicalAddress,
+        size: usize,
+        flags: EntryFlags,
+        name: &'static str,
+    ) -> Option<VirtualAddress> {
+        if size == 0 {
+            return None;
+        }
+        let first_frame = Frame::containing_address(start);
+        let last_frame = Frame::containing_address(start + size - 1);
+        let region = self.region_manager.reserve(
+            last_frame.number - first_frame.number + 1,
+            flags,
+            name,
+        )?;
+        for (page, frame) in region
+            .pages()
+            .zip(Frame::range_inclusive(first_frame, last_frame))
+        {
+            self.active_table
+                .map_to(page, &frame, flags, &mut self.frame_allocator);
+        }
+        Some(region.start_address() + start % PAGE_SIZE)
+    }
+
+    /// Map physical memory outside of the frame allocator's control at a fixed address of a
+    /// window set aside for it, without reserving a region
+    pub fn map_physical_at(
+        &mut self,
+        address: VirtualAddress,
+        start: PhysicalAddress,
+        size: usize,
+        flags: EntryFlags,
+    ) {
+        let first_frame = Frame::containing_address(start);
+        let last_frame = Frame::containing_address(start + size - 1);
+        for (page, frame) in Page::range_inclusive(
+            Page::containing_address(address),
+            Page::containing_address(address + size - 1),
+        ).zip(Frame::range_inclusive(first_frame, last_frame))
+        {
+            self.active_table
+                .map_to(page, &frame, flags, &mut self.frame_allocator);
+        }
+    }
+
+    /// Identity map physical memory outside of the frame allocator's control, for code that runs
+    /// at the same address before and after enabling paging, such as the SMP trampoline
+    pub fn identity_map(&mut self, start: PhysicalAddress, size: usize, flags: EntryFlags) {
+        let first_frame = Frame::containing_address(start);
+        let last_frame = Frame::containing_address(start + size - 1);
+        for frame in Frame::range_inclusive(first_frame, last_frame) {
+            self.active_table
+                .identity_map(&frame, flags, &mut self.frame_allocator);
+        }
+    }
+
+    /// Remove a mapping made by identity_map, leaving the physical memory alone
+    pub fn unmap_identity(&mut self, start: PhysicalAddress, size: usize) {
+        let pages = Page::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(start + size - 1),
+        );
+        for page in pages {
+            self.active_table.unmap(page, &mut self.frame_allocator);
+        }
+    }
+
+    /// Remove a mapping made by map_physical, leaving the physical memory alone
+    pub fn unmap_physical(&mut self, address: VirtualAddress) {
+        let region = self.region_manager
+            .release(address - address % PAGE_SIZE)
+            .expect("physical memory was not mapped");
+        for page in region.pages() {
+            self.active_table.unmap(page, &mut self.frame_allocator);
+        }
+    }
+
+    /// Map a page of the active address space to a new, cleared frame
+    pub fn map_zeroed(&mut self, page: Page, flags: EntryFlags) {
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ..
+        } = self;
+        // Map the page writable first so that it can be cleared, then restrict it to the flags
+        active_table.map(page, flags | EntryFlags::WRITABLE, frame_allocator);
+        unsafe {
+            ::core::ptr::write_bytes(page.start_address() as *mut u8, 0, PAGE_SIZE);
+        }
+        active_table.set_flags(page, flags);
+    }
+
+    /// Change the flags of a mapped page of the active address space
+    pub fn set_page_flags(&mut self, page: Page, flags: EntryFlags) {
+        self.active_table.set_flags(page, flags);
+    }
+
+    /// Create an address space that shares the kernel's mappings with the active one
+    pub fn new_address_space(&mut self) -> InactivePageTable {
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut temporary_page,
+            ..
+        } = self;
+        // The kernel, its heap and its stacks all live in the first P4 entry
+        let kernel_frame = active_table.p4()[0]
+            .pointed_frame()
+            .expect("kernel is not mapped");
+        let kernel_flags = active_table.p4()[0].flags();
+
+        let frame = frame_allocator.allocate_frame().expect("out of memory");
+        let mut table = InactivePageTable::new(frame, active_table, temporary_page);
+        active_table.with(&mut table, temporary_page, |mapper| {
+            mapper.p4_mut()[0].set(&kernel_frame, kernel_flags);
+        });
+        table
+    }
+
+    /// Map pages of an inactive address space to new frames. Each frame starts out zeroed and is
+    /// then passed to fill along with the page it is mapped to.
+    pub fn map_inactive<F>(
+        &mut self,
+        table: &mut InactivePageTable,
+        pages: PageIter,
+        flags: EntryFlags,
+        mut fill: F,
+    ) where
+        F: FnMut(Page, &mut [u8]),
+    {
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut temporary_page,
+            ..
+        } = self;
+
+        let mut mapped = Vec::new();
+        for page in pages {
+            let frame = frame_allocator.allocate_frame().expect("out of memory");
+            {
+                let address = temporary_page.map(&frame, active_table);
+                let contents = unsafe { slice::from_raw_parts_mut(address as *mut u8, PAGE_SIZE) };
+                for byte in contents.iter_mut() {
+                    *byte = 0;
+                }
+                fill(page, contents);
+            }
+            temporary_page.unmap(active_table);
+            mapped.push((page, frame));
+        }
+
+        active_table.with(table, temporary_page, |mapper| {
+            for (page, frame) in mapped {
+                mapper.map_to(page, &frame, flags, frame_allocator);
+            }
+        });
+    }
+
+    /// Free an address space that is not active, along with all of its user memory
+    pub fn free_address_space(&mut self, mut table: InactivePageTable) {
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut temporary_page,
+            ref mut user_region_managers,
+            ..
+        } = self;
+        assert!(
+            table.p4_address() != active_address_space(),
+            "can't free the active address space"
+        );
+        active_table.with(&mut table, temporary_page, |mapper| {
+            mapper.free_user_space(frame_allocator);
+        });
+        user_region_managers.remove(&table.p4_address());
+        frame_allocator.deallocate_frame(Frame::containing_address(table.p4_address()));
+    }
+
+    /// Share the mapped pages in the range with an inactive address space. Writable pages become
+    /// read-only copy-on-write pages in both address spaces, and get copied on the first write.
+    pub fn share_copy_on_write(&mut self, pages: PageIter, table: &mut InactivePageTable) {
+        let mut shared = Vec::new();
+        for page in pages {
+            let flags = match self.active_table.page_flags(page) {
+                Some(flags) => flags,
+                None => continue,
+            };
+            let frame = self.active_table
+                .translate_page(page)
+                .expect("mapped page has no frame");
+
+            let flags = if flags.contains(EntryFlags::WRITABLE) {
+                let flags = (flags - EntryFlags::WRITABLE) | EntryFlags::COPY_ON_WRITE;
+                self.active_table.set_flags(page, flags);
+                flags
+            } else {
+                flags
+            };
+            self.frame_allocator.share_frame(&frame);
+            shared.push((page, frame, flags));
+        }
+
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut temporary_page,
+            ..
+        } = self;
+        active_table.with(table, temporary_page, |mapper| {
+            for (page, frame, flags) in shared {
+                mapper.map_to(page, &frame, flags, frame_allocator);
+            }
+        });
+    }
+
+    /// Create a copy of the user part of the active address space. Mapped pages are shared
+    /// copy-on-write, and lazily-backed user regions are reserved in the copy as well.
+    pub fn fork_address_space(&mut self) -> InactivePageTable {
+        let mut table = self.new_address_space();
+        let mut user_ranges = Vec::new();
+        self.walk_active_table(|range| {
+            if range.start() >= USER_SPACE_START && range.end() <= USER_SPACE_END {
+                user_ranges.push(range);
+            }
+        });
+        for range in user_ranges {
+            let pages = Page::range_inclusive(
+                Page::containing_address(range.start()),
+                Page::containing_address(range.end() - 1),
+            );
+            self.share_copy_on_write(pages, &mut table);
+        }
+
+        let regions = self.user_region_managers
+            .get(&active_address_space())
+            .cloned();
+        if let Some(regions) = regions {
+            self.user_region_managers.insert(table.p4_address(), regions);
+        }
+        table
+    }
+
+    /// Call f with every mapped range of the active address space
+    pub fn walk_active_table<F>(&self, f: F)
+    where
+        F: FnMut(MappedRange),
+    {
+        self.active_table.walk(f)
+    }
+
+    /// Call f with every mapped range of an inactive address space
+    pub fn walk_inactive_table<F>(&mut self, table: &mut InactivePageTable, f: F)
+    where
+        F: FnMut(MappedRange),
+    {
+        let &mut Self {
+            ref mut active_table,
+            ref mut temporary_page,
+            ..
+        } = self;
+        active_table.with(table, temporary_page, |mapper| mapper.walk(f));
+    }
+
+    /// Print the mappings of the active address space
+    pub fn dump_active_table(&self) {
+        self.active_table.dump();
+    }
+
+    /// Print the mappings of an inactive address space
+    pub fn dump_inactive_table(&mut self, table: &mut InactivePageTable) {
+        let &mut Self {
+            ref mut active_table,
+            ref mut temporary_page,
+            ..
+        } = self;
+        active_table.with(table, temporary_page, |mapper| mapper.dump());
+    }
+
+    /// Resolve a page fault, returning whether the faulting access can be retried
+    fn handle_page_fault(&mut self, address: VirtualAddress, error_code: PageFaultErrorCode) -> bool {
+        if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+                return self.copy_on_write(Page::containing_address(address));
+            }
+            return false;
+        }
+        self.back_lazy_page(address)
+    }
+
+    /// Give a copy-on-write page its own writable frame
+    fn copy_on_write(&mut self, page: Page) -> bool {
+        let flags = match self.active_table.page_flags(page) {
+            Some(flags) => flags,
+            None => return false,
+        };
+        if !flags.contains(EntryFlags::COPY_ON_WRITE) {
+            return false;
+        }
+        let writable_flags = (flags - EntryFlags::COPY_ON_WRITE) | EntryFlags::WRITABLE;
+
+        let &mut Self {
+            ref mut active_table,
+            ref mut frame_allocator,
+            ref mut temporary_page,
+            ..
+        } = self;
+        let shared_frame = active_table
+            .translate_page(page)
+            .expect("mapped page has no frame");
+        if frame_allocator.reference_count(&shared_frame) == 1 {
+            // Every other address space already has its own copy, so the frame can be taken over
+            active_table.set_flags(page, writable_flags);
+            return true;
+        }
+
+        // The copy goes through the temporary page, so that the fault needs no heap memory
+        let frame = frame_allocator.allocate_frame().expect("out of memory");
+        let copy = temporary_page.map(&frame, active_table);
+        unsafe {
+            ::core::ptr::copy_nonoverlapping(
+                page.start_address() as *const u8,
+                copy as *mut u8,
+                PAGE_SIZE,
+            );
+        }
+        temporary_page.unmap(active_table);
+
+        let shared_frame = active_table.unmap(page, frame_allocator);
+        frame_allocator.deallocate_frame(shared_frame);
+        active_table.map_to(page, &frame, writable_flags, frame_allocator);
+        true
+    }
+
+    /// Back the faulting page with a new frame if it belongs to a lazily-backed region
+    fn back_lazy_page(&mut self, address: VirtualAddress) -> bool {
+        let region = match self.region_manager.find(address) {
+            Some(region) => region,
+            None => match self.find_user_region(address) {
+                Some(region) => region,
+                None => return false,
+            },
+        };
+
+        self.map_zeroed(Page::containing_address(address), region.flags());
+        true
+    }
+}
+
+/// Get the physical address of the active P4 table, which identifies the active address space
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn active_address_space() -> PhysicalAddress {
+    ::x86_64::registers::control_regs::cr3().0 as usize
+}
+
+/// Lock on the memory controller, which shoots down the pages it changed on the other
+/// processors when released. The lock is held until they are done, so that no freed frame is
+/// reused while another processor may still reach it through a stale translation.
+pub struct ControllerGuard(MutexGuard<'static, MemoryController>);
+
+impl ControllerGuard {
+    /// Wrap the lock on the memory controller, recording the thread that holds it
+    fn new(guard: MutexGuard<'static, MemoryController>) -> Self {
+        if let Some(thread) = scheduler::try_current() {
+            CONTROLLER_OWNER.store(thread.0 + 1, Ordering::SeqCst);
+        }
+        ControllerGuard(guard)
+    }
+}
+
+impl Deref for ControllerGuard {
+    type Target = MemoryController;
+
+    fn deref(&self) -> &MemoryController {
+        &self.0
+    }
+}
+
+impl DerefMut for ControllerGuard {
+    fn deref_mut(&mut self) -> &mut MemoryController {
+        &mut self.0
+    }
+}
+
+impl Drop for ControllerGuard {
+    fn drop(&mut self) {
+        CONTROLLER_OWNER.store(0, Ordering::SeqCst);
+        smp::tlb::shootdown();
+    }
+}
+
+/// Get the memory controller. Panics if memory::init has not been called yet.
+pub fn controller() -> ControllerGuard {
+    ControllerGuard::new(
+        MEMORY_CONTROLLER
+            .try()
+            .expect("memory controller is not initialized")
+            .lock(),
+    )
+}
+
+/// Get the memory controller, unless it is locked or memory::init has not been called yet
+pub fn try_controller() -> Option<ControllerGuard> {
+    MEMORY_CONTROLLER.try()?.try_lock().map(ControllerGuard::new)
+}
+
+/// Check whether the running thread may be the one holding the memory controller. Before the
+/// scheduler runs, whatever holds it is the only flow of control.
+fn may_hold_controller() -> bool {
+    match scheduler::try_current() {
+        Some(thread) => CONTROLLER_OWNER.load(Ordering::SeqCst) == thread.0 + 1,
+        None => true,
     }
 }
 
+/// Try to resolve a page fault at the given address, returning whether the faulting access can be
+/// retried. Faults while the memory controller is locked are only resolved if can_wait is set and
+/// another thread holds it, since waiting for it would deadlock otherwise.
+pub fn handle_page_fault(
+    address: VirtualAddress,
+    error_code: PageFaultErrorCode,
+    can_wait: bool,
+) -> bool {
+    let controller = match MEMORY_CONTROLLER.try() {
+        Some(controller) => controller,
+        None => return false,
+    };
+    let guard = match controller.try_lock() {
+        Some(guard) => guard,
+        None if can_wait && !may_hold_controller() => controller.lock(),
+        None => return false,
+    };
+    ControllerGuard::new(guard).handle_page_fault(address, error_code)
+}
+
 /// Remap the kernel and initialize the page frame allocator from ELF memory sections
-pub fn init(boot_info: &BootInformation) -> MemoryController {
+pub fn init(boot_info: &BootInformation) {
     #![cfg_attr(feature = "cargo-clippy", allow(bool_comparison))]
     #![cfg_attr(feature = "cargo-clippy", allow(filter_map))]
     #![cfg_attr(feature = "cargo-clippy", allow(replace_consts))]
@@ -166,26 +743,63 @@ pub fn init(boot_info: &BootInformation) -> MemoryController {
         boot_info.end_address(),
         memory_map_tag.memory_areas(),
     );
+    for module in boot_info
+        .module_tags()
+        .filter(|module| module.end_address() > module.start_address())
+    {
+        println!(
+            "module {}: 0x{:x} - 0x{:x}",
+            module.name(),
+            module.start_address(),
+            module.end_address()
+        );
+        frame_allocator.reserve(module.start_address() as usize, module.end_address() as usize);
+    }
+    // Application processors start in real mode, so their trampoline has to stay in low memory
+    frame_allocator.reserve(
+        smp::TRAMPOLINE_ADDRESS,
+        smp::TRAMPOLINE_ADDRESS + smp::TRAMPOLINE_SIZE,
+    );
 
     let mut active_table = paging::remap_kernel(&mut frame_allocator, boot_info);
 
-    let heap_start_page = Page::containing_address(HEAP_START);
-    let heap_end_page = Page::containing_address(HEAP_START + HEAP_SIZE - 1);
-
-    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
-        active_table.map(page, EntryFlags::WRITABLE, &mut frame_allocator);
-    }
-
     let stack_allocator = {
-        let stack_alloc_start = heap_end_page + 1;
-        let stack_alloc_end = stack_alloc_start + 100;
+        let stack_alloc_start = Page::containing_address(STACK_AREA_START);
+        let stack_alloc_end = stack_alloc_start + (STACK_AREA_SIZE / PAGE_SIZE - 1);
         let stack_alloc_range = Page::range_inclusive(stack_alloc_start, stack_alloc_end);
         stack_allocator::StackAllocator::new(stack_alloc_range)
     };
 
-    MemoryController {
-        active_table: active_table,
-        frame_allocator: frame_allocator,
-        stack_allocator: stack_allocator,
+    let mut region_manager =
+        region::RegionManager::new(LAZY_AREA_START, LAZY_AREA_START + LAZY_AREA_SIZE);
+
+    // Only the start of the heap is backed now, it grows into the rest of its region as it fills
+    let heap = region_manager
+        .reserve(HEAP_SIZE / PAGE_SIZE, EntryFlags::WRITABLE, "heap")
+        .expect("no room for the heap");
+    let heap_start_page = Page::containing_address(heap.start_address());
+    let heap_end_page = Page::containing_address(heap.start_address() + HEAP_INITIAL_SIZE - 1);
+    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
+        active_table.map(page, EntryFlags::WRITABLE, &mut frame_allocator);
+    }
+    // The collections of the memory controller allocate, so the heap has to be ready first
+    unsafe {
+        HEAP_ALLOCATOR.init(heap.start_address(), HEAP_INITIAL_SIZE, HEAP_SIZE);
     }
+
+    let temporary_page = TemporaryPage::new(
+        Page::containing_address(TEMPORARY_PAGE_ADDRESS),
+        &mut frame_allocator,
+    );
+
+    MEMORY_CONTROLLER.call_once(|| {
+        Mutex::new(MemoryController {
+            active_table: active_table,
+            frame_allocator: frame_allocator,
+            stack_allocator: stack_allocator,
+            region_manager: region_manager,
+            user_region_managers: BTreeMap::new(),
+            temporary_page: temporary_page,
+        })
+    });
 }
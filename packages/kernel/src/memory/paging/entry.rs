@@ -1,4 +1,6 @@
 //! The entry module represents entries in the page table
+use cpu::{self, Features};
+use elf::{ProgramHeader, PF_R, PF_W, PF_X};
 use memory::Frame;
 use multiboot2::{ElfSection, ELF_SECTION_ALLOCATED, ELF_SECTION_EXECUTABLE, ELF_SECTION_WRITABLE};
 
@@ -27,6 +29,8 @@ bitflags! {
         /// Page isn't flushed from caches on address space switch (PGE bit of CR4 register must be set)
         const GLOBAL            = 1 << 8;
         // bits 9 - 11:     usable freely by OS
+        /// The page is shared read-only and must be copied before it is written to
+        const COPY_ON_WRITE     = 1 << 9;
         // bits 12 - 51:    physical address
         // bits 52 - 62:    usable freely by OS
         /// Disallow execution of code in this page (NXE bit in EFER register must be set)
@@ -62,9 +66,13 @@ impl Entry {
         }
     }
 
-    /// Set page frame and frame flags
-    pub fn set(&mut self, frame: &Frame, flags: EntryFlags) {
+    /// Set page frame and frame flags. NO_EXECUTE is left out on processors without NX, where
+    /// the bit is reserved.
+    pub fn set(&mut self, frame: &Frame, mut flags: EntryFlags) {
         assert_eq!(frame.start_address() & !0x000f_ffff_ffff_f000, 0);
+        if !cpu::has(Features::NX) {
+            flags.remove(EntryFlags::NO_EXECUTE);
+        }
         self.0 = (frame.start_address() as u64) | flags.bits();
     }
 }
@@ -86,4 +94,21 @@ impl EntryFlags {
 
         flags
     }
+
+    /// Initialize page table entry flags according to given ELF program header flags
+    pub fn from_elf_program_flags(header: &ProgramHeader) -> Self {
+        let mut flags = Self::empty();
+
+        if header.flags & (PF_R | PF_W | PF_X) != 0 {
+            flags |= Self::PRESENT;
+        }
+        if header.flags & PF_W != 0 {
+            flags |= Self::WRITABLE;
+        }
+        if header.flags & PF_X == 0 {
+            flags |= Self::NO_EXECUTE;
+        }
+
+        flags
+    }
 }
@@ -4,6 +4,7 @@ use super::entry::*;
 use super::table::{self, Level4, Table};
 use memory::{Frame, FrameAllocator, PAGE_SIZE};
 use core::ptr::Unique;
+use smp;
 
 /// Maps physical to virtual addresses using page tables
 pub struct Mapper {
@@ -76,15 +77,34 @@ impl Mapper {
             .or_else(huge_page)
     }
 
-    /// Map a Page to a Frame
+    /// Get the flags of a page mapped with a regular (not huge) page table entry
+    pub fn page_flags(&self, page: Page) -> Option<EntryFlags> {
+        self.p4()
+            .next_table(page.p4_index())
+            .and_then(|p3| p3.next_table(page.p3_index()))
+            .and_then(|p2| p2.next_table(page.p2_index()))
+            .and_then(|p1| {
+                let flags = p1[page.p1_index()].flags();
+                if flags.contains(EntryFlags::PRESENT) {
+                    Some(flags)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Map a Page to a Frame. The page has to be unmapped, and since processors don't cache
+    /// translations of unmapped pages, no TLB needs invalidating.
     pub fn map_to<A>(&mut self, page: Page, frame: &Frame, flags: EntryFlags, allocator: &mut A)
     where
         A: FrameAllocator,
     {
+        // The CPU only allows user mode access if every level of the table allows it
+        let table_flags = flags & EntryFlags::USER_ACCESSIBLE;
         let p4 = self.p4_mut();
-        let p3 = p4.next_table_create(page.p4_index(), allocator);
-        let p2 = p3.next_table_create(page.p3_index(), allocator);
-        let p1 = p2.next_table_create(page.p2_index(), allocator);
+        let p3 = p4.next_table_create(page.p4_index(), table_flags, allocator);
+        let p2 = p3.next_table_create(page.p3_index(), table_flags, allocator);
+        let p1 = p2.next_table_create(page.p2_index(), table_flags, allocator);
 
         assert!(p1[page.p1_index()].is_unused());
         p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
@@ -108,13 +128,61 @@ impl Mapper {
         self.map_to(page, frame, flags, allocator)
     }
 
-    /// Unmap a page
-    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A)
+    /// Unmap everything but the kernel's P4 entry and the recursive mapping, deallocating the
+    /// mapped frames and the page tables. Meant for inactive tables of exiting processes.
+    pub fn free_user_space<A>(&mut self, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        /// Deallocate the frame an entry points to and clear the entry
+        fn free_entry<A: FrameAllocator>(entry: &mut Entry, allocator: &mut A) {
+            if let Some(frame) = entry.pointed_frame() {
+                allocator.deallocate_frame(frame);
+            }
+            entry.set_unused();
+        }
+
+        for p4_index in 1..ENTRY_COUNT - 1 {
+            if let Some(p3) = self.p4_mut().next_table_mut(p4_index) {
+                for p3_index in 0..ENTRY_COUNT {
+                    if let Some(p2) = p3.next_table_mut(p3_index) {
+                        for p2_index in 0..ENTRY_COUNT {
+                            if let Some(p1) = p2.next_table_mut(p2_index) {
+                                for p1_index in 0..ENTRY_COUNT {
+                                    free_entry(&mut p1[p1_index], allocator);
+                                }
+                            }
+                            free_entry(&mut p2[p2_index], allocator);
+                        }
+                    }
+                    free_entry(&mut p3[p3_index], allocator);
+                }
+            }
+            free_entry(&mut self.p4_mut()[p4_index], allocator);
+        }
+    }
+
+    /// Change the flags of an already mapped page. Other processors see the change after the
+    /// next TLB shootdown.
+    pub fn set_flags(&mut self, page: Page, flags: EntryFlags) {
+        let p1 = self.p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))
+            .expect("mapping code does not support huge pages");
+        let frame = p1[page.p1_index()]
+            .pointed_frame()
+            .expect("page is not mapped");
+        p1[page.p1_index()].set(&frame, flags | EntryFlags::PRESENT);
+        smp::tlb::invalidate(page.start_address());
+    }
+
+    /// Unmap a page, returning the frame it was mapped to so the caller can decide whether to free it.
+    /// Other processors see the change after the next TLB shootdown.
+    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A) -> Frame
     where
         A: FrameAllocator,
     {
-        use x86_64::instructions::tlb;
-        use x86_64::VirtualAddress;
         assert!(self.translate(page.start_address()).is_some());
 
         let p1 = self.p4_mut()
@@ -123,13 +191,13 @@ impl Mapper {
             .and_then(|p2| p2.next_table_mut(page.p2_index()))
             .expect("mapping code does not support huge pages");
         //TODO check if the following expect message is correct
-        let _frame = p1[page.p1_index()]
+        let frame = p1[page.p1_index()]
             .pointed_frame()
             .expect("couldn't find page frame");
         p1[page.p1_index()].set_unused();
-        tlb::flush(VirtualAddress(page.start_address()));
+        smp::tlb::invalidate(page.start_address());
 
         // TODO free p(1,2,3) table if empty
-        // allocator.deallocate_frame(_frame);
+        frame
     }
 }
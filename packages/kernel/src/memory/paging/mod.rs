@@ -1,10 +1,12 @@
 //! The paging module manages the page table as well as remapping the kernel
 pub use self::entry::{Entry, EntryFlags};
 pub use self::mapper::Mapper;
-use self::temporary_page::TemporaryPage;
+pub use self::temporary_page::TemporaryPage;
+pub use self::walker::MappedRange;
 use memory::{Frame, FrameAllocator, PAGE_SIZE};
+use memory::stack_allocator::{self, StackOwner};
 use multiboot2::BootInformation;
-use x86_64::instructions::tlb;
+use smp;
 use x86_64::registers::control_regs;
 use core::ops::{Add, Deref, DerefMut};
 
@@ -12,6 +14,7 @@ mod entry;
 mod table;
 mod mapper;
 mod temporary_page;
+mod walker;
 
 /// Number of page table entries
 const ENTRY_COUNT: usize = 512;
@@ -21,7 +24,7 @@ pub type PhysicalAddress = usize;
 /// Virtual memory address
 pub type VirtualAddress = usize;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 /// A memory page
 pub struct Page {
     /// Page number
@@ -176,20 +179,28 @@ impl ActivePageTable {
                 &table.p4_frame.clone(),
                 EntryFlags::PRESENT | EntryFlags::WRITABLE,
             );
-            tlb::flush_all();
+            // The other processors run on the same P4 table, so their recursive mapping changes
+            // too. They only go through it with the memory controller locked, and the shootdown
+            // that releases the lock flushes what they cached of either table.
+            smp::tlb::invalidate_all();
 
             // Execute f in new context
             f(self);
 
             // Restore original active P4 table
             p4_table[511].set(&original_p4, EntryFlags::PRESENT | EntryFlags::WRITABLE);
-            tlb::flush_all();
+            smp::tlb::invalidate_all();
         }
         temporary_page.unmap(self);
     }
 }
 
 impl InactivePageTable {
+    /// Get the physical address of the P4 table, as loaded into CR3
+    pub fn p4_address(&self) -> PhysicalAddress {
+        self.p4_frame.start_address()
+    }
+
     /// InactivePageTable constructor
     pub fn new(
         frame: Frame,
@@ -270,6 +281,21 @@ where
         for frame in Frame::range_inclusive(multiboot_start, multiboot_end) {
             mapper.identity_map(&frame, EntryFlags::PRESENT, allocator)
         }
+
+        // Identity map boot modules read-only, skipping frames they share with the above
+        for module in boot_info
+            .module_tags()
+            .filter(|module| module.end_address() > module.start_address())
+        {
+            let module_start = Frame::containing_address(module.start_address() as usize);
+            let module_end = Frame::containing_address(module.end_address() as usize - 1);
+            for frame in Frame::range_inclusive(module_start, module_end) {
+                let page = Page::containing_address(frame.start_address());
+                if mapper.translate_page(page).is_none() {
+                    mapper.identity_map(&frame, EntryFlags::NO_EXECUTE, allocator);
+                }
+            }
+        }
     });
 
     // Unmap old original p4 page (created in boot.asm) and use as a guard page
@@ -278,6 +304,7 @@ where
 
     let old_p4_page = Page::containing_address(old_table.p4_frame.start_address());
     active_table.unmap(old_p4_page, allocator);
+    stack_allocator::register_guard_page(old_p4_page.start_address(), StackOwner::KernelBoot);
     println!("guard page created at {:#x}", old_p4_page.start_address());
 
     active_table
@@ -59,10 +59,12 @@ where
             .map(|address| unsafe { &mut *(address as *mut _) })
     }
 
-    /// Return the next table, or create a new one
+    /// Return the next table, or create a new one. The entry pointing to it gets the given
+    /// flags on top of PRESENT and WRITABLE, so that USER_ACCESSIBLE reaches the mapped page.
     pub fn next_table_create<A>(
         &mut self,
         index: usize,
+        flags: EntryFlags,
         allocator: &mut A,
     ) -> &mut Table<L::NextLevel>
     where
@@ -74,10 +76,16 @@ where
                 "mapping code does not support huge pages"
             );
             let frame = allocator.allocate_frame().expect("no frames available");
-            self.entries[index].set(&frame, EntryFlags::PRESENT | EntryFlags::WRITABLE);
+            self.entries[index].set(&frame, EntryFlags::PRESENT | EntryFlags::WRITABLE | flags);
             self.next_table_mut(index)
                 .expect("next table inexplicably does not exist")
                 .zero();
+        } else if !self.entries[index].flags().contains(flags) {
+            let frame = self.entries[index]
+                .pointed_frame()
+                .expect("next table inexplicably does not exist");
+            let existing = self.entries[index].flags();
+            self.entries[index].set(&frame, existing | flags);
         }
         self.next_table_mut(index)
             .expect("next table inexplicably does not exist")
@@ -0,0 +1,217 @@
+//! Walk the page table to find out which ranges of virtual memory are mapped, and print them
+use core::fmt;
+use memory::PAGE_SIZE;
+use super::{Mapper, PhysicalAddress, VirtualAddress, ENTRY_COUNT};
+use super::entry::{Entry, EntryFlags};
+
+#[derive(Debug, Clone, Copy)]
+/// Contiguous range of virtual memory, mapped to contiguous physical memory with the same flags
+pub struct MappedRange {
+    /// First virtual address of the range
+    start: VirtualAddress,
+    /// Virtual address right after the end of the range
+    end: VirtualAddress,
+    /// Physical address the start of the range is mapped to
+    physical_start: PhysicalAddress,
+    /// Effective flags of every page in the range
+    flags: EntryFlags,
+}
+
+/// Merges consecutive mappings into ranges before handing them out
+struct Coalescer<F: FnMut(MappedRange)> {
+    /// Range being built
+    current: Option<MappedRange>,
+    /// Function receiving finished ranges
+    output: F,
+}
+
+impl MappedRange {
+    /// Get the first virtual address of the range
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Get the virtual address right after the end of the range
+    pub fn end(&self) -> VirtualAddress {
+        self.end
+    }
+
+    /// Get the physical address the start of the range is mapped to
+    pub fn physical_start(&self) -> PhysicalAddress {
+        self.physical_start
+    }
+
+    /// Get the effective flags of the range
+    pub fn flags(&self) -> EntryFlags {
+        self.flags
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Pick the character for a permission
+        fn flag(set: bool, c: char) -> char {
+            if set {
+                c
+            } else {
+                '-'
+            }
+        }
+
+        write!(
+            f,
+            "{:016x}-{:016x} r{}{}{}{} {:016x} {:>8} KiB",
+            self.start,
+            self.end,
+            flag(self.flags.contains(EntryFlags::WRITABLE), 'w'),
+            flag(!self.flags.contains(EntryFlags::NO_EXECUTE), 'x'),
+            flag(self.flags.contains(EntryFlags::USER_ACCESSIBLE), 'u'),
+            flag(self.flags.contains(EntryFlags::COPY_ON_WRITE), 'c'),
+            self.physical_start,
+            (self.end - self.start) / 1024
+        )
+    }
+}
+
+impl<F: FnMut(MappedRange)> Coalescer<F> {
+    /// Add a mapping of size bytes, extending the current range if possible
+    fn add(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        physical_start: PhysicalAddress,
+        flags: EntryFlags,
+    ) {
+        // The CPU sets these bits on its own, so they should not split ranges
+        let flags = flags - EntryFlags::ACCESSED - EntryFlags::DIRTY;
+
+        if let Some(ref mut current) = self.current {
+            if current.end == start
+                && current.physical_start + (current.end - current.start) == physical_start
+                && current.flags == flags
+            {
+                current.end += size;
+                return;
+            }
+        }
+
+        let range = MappedRange {
+            start: start,
+            end: start + size,
+            physical_start: physical_start,
+            flags: flags,
+        };
+        if let Some(finished) = self.current.take() {
+            (self.output)(finished);
+        }
+        self.current = Some(range);
+    }
+
+    /// Hand out the last range
+    fn finish(mut self) {
+        if let Some(finished) = self.current.take() {
+            (self.output)(finished);
+        }
+    }
+}
+
+/// Build the canonical virtual address for the given table indices
+fn address(p4_index: usize, p3_index: usize, p2_index: usize, p1_index: usize) -> VirtualAddress {
+    let address = (p4_index << 39) | (p3_index << 30) | (p2_index << 21) | (p1_index << 12);
+    if p4_index >= ENTRY_COUNT / 2 {
+        // Sign extend bit 47
+        address | 0xffff_0000_0000_0000
+    } else {
+        address
+    }
+}
+
+/// Combine the flags of a table entry with those of the entries leading to it
+fn effective_flags(parent: EntryFlags, entry: &Entry) -> EntryFlags {
+    let mut flags = entry.flags();
+    if !parent.contains(EntryFlags::WRITABLE) {
+        flags.remove(EntryFlags::WRITABLE);
+    }
+    if !parent.contains(EntryFlags::USER_ACCESSIBLE) {
+        flags.remove(EntryFlags::USER_ACCESSIBLE);
+    }
+    if parent.contains(EntryFlags::NO_EXECUTE) {
+        flags.insert(EntryFlags::NO_EXECUTE);
+    }
+    flags
+}
+
+impl Mapper {
+    /// Call f with every coalesced range of mapped memory, in ascending order of virtual address
+    pub fn walk<F>(&self, f: F)
+    where
+        F: FnMut(MappedRange),
+    {
+        const P3_SIZE: usize = PAGE_SIZE * ENTRY_COUNT * ENTRY_COUNT;
+        const P2_SIZE: usize = PAGE_SIZE * ENTRY_COUNT;
+
+        let mut coalescer = Coalescer {
+            current: None,
+            output: f,
+        };
+        let p4 = self.p4();
+
+        // The last P4 entry is the recursive mapping, which would list the page tables themselves
+        for p4_index in 0..ENTRY_COUNT - 1 {
+            let p3 = match p4.next_table(p4_index) {
+                Some(p3) => p3,
+                None => continue,
+            };
+            let p4_flags = p4[p4_index].flags();
+
+            for p3_index in 0..ENTRY_COUNT {
+                let p3_flags = effective_flags(p4_flags, &p3[p3_index]);
+                if p3_flags.contains(EntryFlags::HUGE_PAGE) {
+                    if let Some(frame) = p3[p3_index].pointed_frame() {
+                        let start = address(p4_index, p3_index, 0, 0);
+                        coalescer.add(start, P3_SIZE, frame.start_address(), p3_flags);
+                    }
+                    continue;
+                }
+                let p2 = match p3.next_table(p3_index) {
+                    Some(p2) => p2,
+                    None => continue,
+                };
+
+                for p2_index in 0..ENTRY_COUNT {
+                    let p2_flags = effective_flags(p3_flags, &p2[p2_index]);
+                    if p2_flags.contains(EntryFlags::HUGE_PAGE) {
+                        if let Some(frame) = p2[p2_index].pointed_frame() {
+                            let start = address(p4_index, p3_index, p2_index, 0);
+                            coalescer.add(start, P2_SIZE, frame.start_address(), p2_flags);
+                        }
+                        continue;
+                    }
+                    let p1 = match p2.next_table(p2_index) {
+                        Some(p1) => p1,
+                        None => continue,
+                    };
+
+                    for p1_index in 0..ENTRY_COUNT {
+                        if let Some(frame) = p1[p1_index].pointed_frame() {
+                            let start = address(p4_index, p3_index, p2_index, p1_index);
+                            let flags = effective_flags(p2_flags, &p1[p1_index]);
+                            coalescer.add(start, PAGE_SIZE, frame.start_address(), flags);
+                        }
+                    }
+                }
+            }
+        }
+
+        coalescer.finish();
+    }
+
+    /// Print every mapped range, similar to /proc/self/maps
+    pub fn dump(&self) {
+        println!(
+            "{:<33} {:<5} {:<16} {:>12}",
+            "virtual", "perms", "physical", "size"
+        );
+        self.walk(|range| println!("{}", range));
+    }
+}
@@ -0,0 +1,172 @@
+//! Reserve ranges of virtual memory that are only backed by page frames when first accessed
+use memory::paging::{EntryFlags, Page, PageIter, VirtualAddress};
+use memory::PAGE_SIZE;
+
+/// Maximum number of regions a RegionManager can keep track of
+const MAX_REGIONS: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+/// A range of virtual memory whose pages get a frame on first access
+pub struct Region {
+    /// Address of the first page in the region
+    start: VirtualAddress,
+    /// Number of pages in the region
+    size_in_pages: usize,
+    /// Flags used when mapping pages of the region
+    flags: EntryFlags,
+    /// Name of the region, for debugging
+    name: &'static str,
+}
+
+/// Keeps track of lazily-backed regions within an area of virtual memory
+#[derive(Clone, Copy)]
+pub struct RegionManager {
+    /// Start address of the area regions are reserved in
+    area_start: VirtualAddress,
+    /// End address (exclusive) of the area regions are reserved in
+    area_end: VirtualAddress,
+    /// Reserved regions, in no particular order
+    regions: [Option<Region>; MAX_REGIONS],
+}
+
+impl Region {
+    /// Get the start address of the region
+    pub fn start_address(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Get the end address (exclusive) of the region
+    pub fn end_address(&self) -> VirtualAddress {
+        self.start + self.size_in_pages * PAGE_SIZE
+    }
+
+    /// Get the size of the region in pages
+    pub fn size_in_pages(&self) -> usize {
+        self.size_in_pages
+    }
+
+    /// Get the flags pages of the region are mapped with
+    pub fn flags(&self) -> EntryFlags {
+        self.flags
+    }
+
+    /// Get the name of the region
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Check if the region contains the given address
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        address >= self.start_address() && address < self.end_address()
+    }
+
+    /// Get an iterator over all pages in the region
+    pub fn pages(&self) -> PageIter {
+        Page::range_inclusive(
+            Page::containing_address(self.start_address()),
+            Page::containing_address(self.end_address() - 1),
+        )
+    }
+}
+
+impl RegionManager {
+    /// RegionManager constructor, managing the area from start to end (exclusive)
+    pub fn new(area_start: VirtualAddress, area_end: VirtualAddress) -> Self {
+        assert_eq!(area_start % PAGE_SIZE, 0, "region area must be page aligned");
+        assert_eq!(area_end % PAGE_SIZE, 0, "region area must be page aligned");
+        Self {
+            area_start: area_start,
+            area_end: area_end,
+            regions: [None; MAX_REGIONS],
+        }
+    }
+
+    /// Reserve a new region at the lowest free address of the area
+    pub fn reserve(
+        &mut self,
+        size_in_pages: usize,
+        flags: EntryFlags,
+        name: &'static str,
+    ) -> Option<Region> {
+        if size_in_pages == 0 {
+            return None;
+        }
+        let size = size_in_pages * PAGE_SIZE;
+
+        let mut start = self.area_start;
+        while let Some(overlap) = self.overlapping(start, start + size) {
+            start = overlap.end_address();
+        }
+        if start + size > self.area_end {
+            return None;
+        }
+
+        self.insert(Region {
+            start: start,
+            size_in_pages: size_in_pages,
+            flags: flags,
+            name: name,
+        })
+    }
+
+    /// Reserve a new region at a fixed address, failing if it overlaps an existing region
+    pub fn reserve_at(
+        &mut self,
+        start: VirtualAddress,
+        size_in_pages: usize,
+        flags: EntryFlags,
+        name: &'static str,
+    ) -> Option<Region> {
+        assert_eq!(start % PAGE_SIZE, 0, "regions must be page aligned");
+        let end = start + size_in_pages * PAGE_SIZE;
+        if size_in_pages == 0 || start < self.area_start || end > self.area_end
+            || self.overlapping(start, end).is_some()
+        {
+            return None;
+        }
+
+        self.insert(Region {
+            start: start,
+            size_in_pages: size_in_pages,
+            flags: flags,
+            name: name,
+        })
+    }
+
+    /// Release the region starting at the given address, returning it
+    pub fn release(&mut self, start: VirtualAddress) -> Option<Region> {
+        for slot in &mut self.regions {
+            let found = match *slot {
+                Some(region) => region.start_address() == start,
+                None => false,
+            };
+            if found {
+                return slot.take();
+            }
+        }
+        None
+    }
+
+    /// Find the region containing the given address
+    pub fn find(&self, address: VirtualAddress) -> Option<Region> {
+        self.regions
+            .iter()
+            .filter_map(|region| *region)
+            .find(|region| region.contains(address))
+    }
+
+    /// Find a region overlapping the range from start to end (exclusive)
+    fn overlapping(&self, start: VirtualAddress, end: VirtualAddress) -> Option<Region> {
+        self.regions
+            .iter()
+            .filter_map(|region| *region)
+            .find(|region| region.start_address() < end && start < region.end_address())
+    }
+
+    /// Store a region in the first free slot
+    fn insert(&mut self, region: Region) -> Option<Region> {
+        let slot = self.regions.iter_mut().find(|slot| slot.is_none())?;
+        *slot = Some(region);
+        Some(region)
+    }
+}
@@ -1,7 +1,39 @@
 //! Allocate stacks
+use alloc::vec::Vec;
+use core::fmt;
 use memory::{FrameAllocator, PAGE_SIZE};
 //use memory::paging::{PageIter, ActivePageTable};
-use memory::paging::{ActivePageTable, EntryFlags, Page, PageIter};
+use memory::paging::{ActivePageTable, EntryFlags, Page, PageIter, VirtualAddress};
+use sync::SpinLock;
+
+/// Maximum number of guard pages that can be registered
+const MAX_GUARD_PAGES: usize = 256;
+
+/// Guard pages of all allocated stacks, so that overflows can be attributed to a stack
+static GUARD_PAGES: SpinLock<[Option<GuardPage>; MAX_GUARD_PAGES]> =
+    SpinLock::new("guard pages", [None; MAX_GUARD_PAGES]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a stack is used for
+pub enum StackOwner {
+    /// The stack set up in boot.asm, which rust_main runs on
+    KernelBoot,
+    /// The Interrupt Stack Table stack used when handling double faults
+    DoubleFault,
+    /// A kernel thread, by thread id
+    Thread(usize),
+    /// The stack an application processor starts on, by processor index
+    Processor(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Unmapped page below a stack
+struct GuardPage {
+    /// Start address of the guard page
+    address: VirtualAddress,
+    /// Owner of the stack above the guard page
+    owner: StackOwner,
+}
 
 #[derive(Debug)]
 /// x86_64 general stack
@@ -14,8 +46,19 @@ pub struct Stack {
 
 /// Stack allocator
 pub struct StackAllocator {
-    /// Page range to allocate in
+    /// Page range that has never been allocated from
     range: PageIter,
+    /// Slots of freed stacks, sorted by address
+    free_slots: Vec<Slot>,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Pages that held a stack, starting with its guard page
+struct Slot {
+    /// First page of the slot, used as the guard page
+    start: Page,
+    /// Number of pages in the slot, including the guard page
+    size_in_pages: usize,
 }
 
 impl Stack {
@@ -39,10 +82,31 @@ impl Stack {
     }
 }
 
+impl fmt::Display for StackOwner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StackOwner::KernelBoot => write!(f, "kernel boot stack"),
+            StackOwner::DoubleFault => write!(f, "double fault IST"),
+            StackOwner::Thread(id) => write!(f, "thread {}", id),
+            StackOwner::Processor(id) => write!(f, "processor {} boot stack", id),
+        }
+    }
+}
+
+impl Slot {
+    /// Get the page right after the end of the slot
+    fn end(&self) -> Page {
+        self.start + self.size_in_pages
+    }
+}
+
 impl StackAllocator {
     /// StackAllocator constructor
     pub fn new(page_range: PageIter) -> Self {
-        Self { range: page_range }
+        Self {
+            range: page_range,
+            free_slots: Vec::new(),
+        }
     }
 
     /// Allocate a new stack
@@ -51,11 +115,51 @@ impl StackAllocator {
         active_table: &mut ActivePageTable,
         frame_allocator: &mut FA,
         size_in_pages: usize,
+        owner: StackOwner,
     ) -> Option<Stack> {
         if size_in_pages == 0 {
             return None;
         }
 
+        let start = match self.reuse_slot(size_in_pages) {
+            Some(start) => start,
+            None => self.take_from_range(size_in_pages)?,
+        };
+        let end = start + (size_in_pages - 1);
+
+        for page in Page::range_inclusive(start, end) {
+            active_table.map(page, EntryFlags::WRITABLE, frame_allocator);
+        }
+
+        let stack_top = end.start_address() + PAGE_SIZE;
+        register_guard_page(start.start_address() - PAGE_SIZE, owner);
+        Some(Stack::new(stack_top, start.start_address()))
+    }
+
+    /// Unmap a stack, returning its frames and making its pages available for new stacks
+    pub fn free_stack<FA: FrameAllocator>(
+        &mut self,
+        stack: Stack,
+        active_table: &mut ActivePageTable,
+        frame_allocator: &mut FA,
+    ) {
+        let start = Page::containing_address(stack.bottom());
+        let end = Page::containing_address(stack.top() - 1);
+        for page in Page::range_inclusive(start, end) {
+            let frame = active_table.unmap(page, frame_allocator);
+            frame_allocator.deallocate_frame(frame);
+        }
+
+        unregister_guard_page(stack.bottom() - PAGE_SIZE);
+        let size_in_pages = (stack.top() - stack.bottom()) / PAGE_SIZE;
+        self.release_slot(Slot {
+            start: Page::containing_address(stack.bottom() - PAGE_SIZE),
+            size_in_pages: size_in_pages + 1,
+        });
+    }
+
+    /// Take a guard page and stack pages from the unused range, returning the first stack page
+    fn take_from_range(&mut self, size_in_pages: usize) -> Option<Page> {
         let mut range = self.range.clone();
 
         let guard_page = range.next();
@@ -67,17 +171,91 @@ impl StackAllocator {
         };
 
         match (guard_page, stack_start, stack_end) {
-            (Some(_), Some(start), Some(end)) => {
+            (Some(_), Some(start), Some(_)) => {
                 self.range = range;
-
-                for page in Page::range_inclusive(start, end) {
-                    active_table.map(page, EntryFlags::WRITABLE, frame_allocator);
-                }
-
-                let stack_top = end.start_address() + PAGE_SIZE;
-                Some(Stack::new(stack_top, start.start_address()))
+                Some(start)
             }
             _ => None,
         }
     }
+
+    /// Take a guard page and stack pages from the first big enough freed slot, returning the first stack page
+    fn reuse_slot(&mut self, size_in_pages: usize) -> Option<Page> {
+        let needed = size_in_pages + 1;
+        let index = self.free_slots
+            .iter()
+            .position(|slot| slot.size_in_pages >= needed)?;
+
+        let slot = self.free_slots[index];
+        if slot.size_in_pages == needed {
+            self.free_slots.remove(index);
+        } else {
+            self.free_slots[index] = Slot {
+                start: slot.start + needed,
+                size_in_pages: slot.size_in_pages - needed,
+            };
+        }
+        Some(slot.start + 1)
+    }
+
+    /// Return a slot to the free list, merging it with adjacent free slots
+    fn release_slot(&mut self, slot: Slot) {
+        let index = self.free_slots
+            .iter()
+            .position(|other| other.start > slot.start)
+            .unwrap_or_else(|| self.free_slots.len());
+        self.free_slots.insert(index, slot);
+
+        if index + 1 < self.free_slots.len()
+            && self.free_slots[index].end() == self.free_slots[index + 1].start
+        {
+            let next = self.free_slots.remove(index + 1);
+            self.free_slots[index].size_in_pages += next.size_in_pages;
+        }
+        if index > 0 && self.free_slots[index - 1].end() == self.free_slots[index].start {
+            let slot = self.free_slots.remove(index);
+            self.free_slots[index - 1].size_in_pages += slot.size_in_pages;
+        }
+    }
+}
+
+/// Remember that the page at the given address guards the stack of owner
+pub fn register_guard_page(address: VirtualAddress, owner: StackOwner) {
+    let mut guard_pages = GUARD_PAGES.lock();
+    match guard_pages.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(GuardPage {
+                address: address,
+                owner: owner,
+            })
+        }
+        None => println!("too many guard pages, overflows of {} will not be reported", owner),
+    }
+}
+
+/// Forget about the guard page at the given address
+fn unregister_guard_page(address: VirtualAddress) {
+    let mut guard_pages = GUARD_PAGES.lock();
+    for slot in guard_pages.iter_mut() {
+        let found = match *slot {
+            Some(guard_page) => guard_page.address == address,
+            None => false,
+        };
+        if found {
+            *slot = None;
+        }
+    }
+}
+
+/// Find the stack whose guard page contains the given address
+pub fn stack_overflow_owner(address: VirtualAddress) -> Option<StackOwner> {
+    // This is called from fault handlers, which may have interrupted a registration
+    let guard_pages = GUARD_PAGES.try_lock()?;
+    guard_pages
+        .iter()
+        .filter_map(|slot| *slot)
+        .find(|guard_page| {
+            address >= guard_page.address && address < guard_page.address + PAGE_SIZE
+        })
+        .map(|guard_page| guard_page.owner)
 }
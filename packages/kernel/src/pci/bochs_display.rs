@@ -0,0 +1,25 @@
+//! Driver for the Bochs/QEMU standard VGA adapter, which only reports its framebuffer for now
+use super::{Bar, Device, DeviceMatch, Driver};
+
+/// The display adapter QEMU emulates with -vga std
+pub static DRIVER: Driver = Driver {
+    name: "bochs-display",
+    matches: &[DeviceMatch::id(0x1234, 0x1111)],
+    probe: probe,
+};
+
+/// Enable the adapter and report where its framebuffer is
+fn probe(device: &Device) -> bool {
+    match device.bars[0] {
+        Some(Bar::Memory { address, size, .. }) => {
+            device.enable();
+            println!(
+                "bochs-display: {} KiB framebuffer at {:#x}",
+                size / 1024,
+                address
+            );
+            true
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,218 @@
+//! Access to PCI configuration space, through the legacy 0xCF8/0xCFC ports or memory mapped
+//! through ECAM areas announced by the ACPI MCFG table
+use alloc::btree_set::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr;
+use memory::{self, EntryFlags, PhysicalAddress, ECAM_WINDOW_SIZE, ECAM_WINDOW_START};
+use spin::Mutex;
+use sync::TicketLock;
+use x86_64::instructions::port::{inb, inl, inw, outl, outw};
+
+/// Port selecting the configuration register accessed through CONFIG_DATA
+const CONFIG_ADDRESS: u16 = 0xcf8;
+/// Port the selected configuration register is read and written through
+const CONFIG_DATA: u16 = 0xcfc;
+/// Enable bit of CONFIG_ADDRESS
+const CONFIG_ENABLE: u32 = 1 << 31;
+/// Size of the configuration space of a function reachable through the ports
+const LEGACY_CONFIG_SIZE: u16 = 0x100;
+/// Size of the configuration space of a function reachable through ECAM
+const EXTENDED_CONFIG_SIZE: u16 = 0x1000;
+/// Size of the ECAM window of one bus
+const ECAM_BUS_SIZE: usize = 1 << 20;
+/// Size of the slot of the ECAM window set aside for each area, enough for all 256 buses
+const ECAM_SLOT_SIZE: usize = 256 * ECAM_BUS_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Location of a PCI function
+pub struct Address {
+    /// PCI segment group, only reachable beyond 0 through ECAM
+    pub segment: u16,
+    /// Bus number
+    pub bus: u8,
+    /// Device number, below 32
+    pub device: u8,
+    /// Function number, below 8
+    pub function: u8,
+}
+
+/// Memory mapped configuration space of a range of buses
+struct EcamArea {
+    /// Physical address of the configuration space of start_bus
+    base: PhysicalAddress,
+    /// PCI segment group the area belongs to
+    segment: u16,
+    /// First bus of the area
+    start_bus: u8,
+    /// Last bus (inclusive) of the area
+    end_bus: u8,
+    /// Virtual address of the slot of the ECAM window the buses are mapped in, at the offset of
+    /// their bus number
+    window: usize,
+    /// Buses mapped so far, which are mapped on first access
+    mapped_buses: BTreeSet<u8>,
+}
+
+lazy_static! {
+    /// ECAM areas, checked before falling back to the ports
+    static ref ECAM_AREAS: Mutex<Vec<EcamArea>> = Mutex::new(Vec::new());
+}
+
+/// Serializes the two-step port accesses
+static PORTS: TicketLock<()> = TicketLock::new("pci config ports", ());
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+impl EcamArea {
+    /// Get the virtual address of a configuration register, mapping its bus if needed
+    fn register(&mut self, address: Address, offset: u16) -> Option<usize> {
+        if address.segment != self.segment || address.bus < self.start_bus
+            || address.bus > self.end_bus
+        {
+            return None;
+        }
+        let bus_base = self.window + usize::from(address.bus) * ECAM_BUS_SIZE;
+        if !self.mapped_buses.contains(&address.bus) {
+            let bus_index = usize::from(address.bus - self.start_bus);
+            memory::controller().map_physical_at(
+                bus_base,
+                self.base + bus_index * ECAM_BUS_SIZE,
+                ECAM_BUS_SIZE,
+                EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE,
+            );
+            self.mapped_buses.insert(address.bus);
+        }
+        Some(
+            bus_base + (usize::from(address.device) << 15) + (usize::from(address.function) << 12)
+                + usize::from(offset),
+        )
+    }
+}
+
+/// Make the configuration space of buses start_bus to end_bus (inclusive) of a segment group
+/// accessible at the physical address base, as described by an MCFG entry. Areas beyond the
+/// room of the ECAM window are left to the ports.
+pub fn add_ecam_area(base: PhysicalAddress, segment: u16, start_bus: u8, end_bus: u8) {
+    let mut areas = ECAM_AREAS.lock();
+    if (areas.len() + 1) * ECAM_SLOT_SIZE > ECAM_WINDOW_SIZE {
+        println!(
+            "pci: no room to map the ECAM area of segment {:04x}, buses {:02x}-{:02x}",
+            segment, start_bus, end_bus
+        );
+        return;
+    }
+    let window = ECAM_WINDOW_START + areas.len() * ECAM_SLOT_SIZE;
+    areas.push(EcamArea {
+        base: base,
+        segment: segment,
+        start_bus: start_bus,
+        end_bus: end_bus,
+        window: window,
+        mapped_buses: BTreeSet::new(),
+    });
+}
+
+/// Get the segment group and first bus of every ECAM area
+pub fn ecam_segments() -> Vec<(u16, u8)> {
+    ECAM_AREAS
+        .lock()
+        .iter()
+        .map(|area| (area.segment, area.start_bus))
+        .collect()
+}
+
+/// Get the virtual address of a configuration register through ECAM, if an area covers it
+fn ecam_register(address: Address, offset: u16) -> Option<usize> {
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return None;
+    }
+    ECAM_AREAS
+        .lock()
+        .iter_mut()
+        .filter_map(|area| area.register(address, offset))
+        .next()
+}
+
+/// Select a configuration register through CONFIG_ADDRESS, returning false if the ports can't
+/// reach it
+fn select_port_register(address: Address, offset: u16) -> bool {
+    if address.segment != 0 || offset >= LEGACY_CONFIG_SIZE {
+        return false;
+    }
+    let value = CONFIG_ENABLE | (u32::from(address.bus) << 16) | (u32::from(address.device) << 11)
+        | (u32::from(address.function) << 8) | u32::from(offset & 0xfc);
+    unsafe { outl(CONFIG_ADDRESS, value) };
+    true
+}
+
+/// Read a 32 bit configuration register. Registers that can't be reached read as all ones,
+/// like those of absent functions.
+pub fn read_u32(address: Address, offset: u16) -> u32 {
+    if let Some(register) = ecam_register(address, offset & !3) {
+        return unsafe { ptr::read_volatile(register as *const u32) };
+    }
+    let _ports = PORTS.lock();
+    if select_port_register(address, offset) {
+        unsafe { inl(CONFIG_DATA) }
+    } else {
+        !0
+    }
+}
+
+/// Read a 16 bit configuration register
+pub fn read_u16(address: Address, offset: u16) -> u16 {
+    if let Some(register) = ecam_register(address, offset & !1) {
+        return unsafe { ptr::read_volatile(register as *const u16) };
+    }
+    let _ports = PORTS.lock();
+    if select_port_register(address, offset) {
+        unsafe { inw(CONFIG_DATA + (offset & 2)) }
+    } else {
+        !0
+    }
+}
+
+/// Read an 8 bit configuration register
+pub fn read_u8(address: Address, offset: u16) -> u8 {
+    if let Some(register) = ecam_register(address, offset) {
+        return unsafe { ptr::read_volatile(register as *const u8) };
+    }
+    let _ports = PORTS.lock();
+    if select_port_register(address, offset) {
+        unsafe { inb(CONFIG_DATA + (offset & 3)) }
+    } else {
+        !0
+    }
+}
+
+/// Write a 32 bit configuration register. Writes to registers that can't be reached are
+/// dropped.
+pub fn write_u32(address: Address, offset: u16, value: u32) {
+    if let Some(register) = ecam_register(address, offset & !3) {
+        return unsafe { ptr::write_volatile(register as *mut u32, value) };
+    }
+    let _ports = PORTS.lock();
+    if select_port_register(address, offset) {
+        unsafe { outl(CONFIG_DATA, value) };
+    }
+}
+
+/// Write a 16 bit configuration register
+pub fn write_u16(address: Address, offset: u16, value: u16) {
+    if let Some(register) = ecam_register(address, offset & !1) {
+        return unsafe { ptr::write_volatile(register as *mut u16, value) };
+    }
+    let _ports = PORTS.lock();
+    if select_port_register(address, offset) {
+        unsafe { outw(CONFIG_DATA + (offset & 2), value) };
+    }
+}
@@ -0,0 +1,545 @@
+//! PCI bus enumeration, and a registry matching the devices found to drivers
+pub use self::config::Address;
+use acpi;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+pub mod bochs_display;
+pub mod config;
+
+/// Configuration register: vendor id
+const VENDOR_ID: u16 = 0x00;
+/// Configuration register: device id
+const DEVICE_ID: u16 = 0x02;
+/// Configuration register: command
+const COMMAND: u16 = 0x04;
+/// Configuration register: status
+const STATUS: u16 = 0x06;
+/// Configuration register: revision id
+const REVISION_ID: u16 = 0x08;
+/// Configuration register: programming interface
+const PROG_IF: u16 = 0x09;
+/// Configuration register: subclass
+const SUBCLASS: u16 = 0x0a;
+/// Configuration register: class code
+const CLASS: u16 = 0x0b;
+/// Configuration register: header type
+const HEADER_TYPE: u16 = 0x0e;
+/// Configuration register: first base address register
+const BAR0: u16 = 0x10;
+/// Configuration register of bridges: secondary bus number
+const SECONDARY_BUS: u16 = 0x19;
+/// Configuration register: pointer to the first capability
+const CAPABILITIES_POINTER: u16 = 0x34;
+/// Configuration register: interrupt line
+const INTERRUPT_LINE: u16 = 0x3c;
+/// Configuration register: interrupt pin
+const INTERRUPT_PIN: u16 = 0x3d;
+
+/// Vendor id read from absent functions
+const NO_VENDOR: u16 = 0xffff;
+/// Header type bit set for devices with more than one function
+const MULTIFUNCTION: u8 = 0x80;
+/// Header type of ordinary devices
+const HEADER_GENERAL: u8 = 0x00;
+/// Header type of PCI-to-PCI bridges
+const HEADER_BRIDGE: u8 = 0x01;
+/// Command bit enabling I/O space decoding
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+/// Command bit enabling memory space decoding
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+/// Status bit set when the function has a capability list
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+/// Upper bound on capabilities followed, guarding against looping lists
+const MAX_CAPABILITIES: usize = 48;
+
+/// Capability id of MSI
+pub const CAPABILITY_MSI: u8 = 0x05;
+/// Capability id of MSI-X
+pub const CAPABILITY_MSI_X: u8 = 0x11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Decoded base address register
+pub enum Bar {
+    /// Memory mapped registers or memory
+    Memory {
+        /// Physical base address
+        address: u64,
+        /// Size in bytes
+        size: u64,
+        /// Whether reads have no side effects, so the range may be prefetched
+        prefetchable: bool,
+        /// Whether the BAR takes up two slots to hold a 64 bit address
+        is_64bit: bool,
+    },
+    /// I/O ports
+    Io {
+        /// First port
+        port: u16,
+        /// Number of ports
+        size: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Entry of a function's capability list
+pub enum Capability {
+    /// Message signalled interrupts
+    Msi {
+        /// Offset of the capability in configuration space
+        offset: u8,
+        /// Whether messages can go to 64 bit addresses
+        is_64bit: bool,
+        /// Number of vectors the function can request
+        vectors: u8,
+        /// Whether vectors can be masked individually
+        per_vector_masking: bool,
+    },
+    /// Message signalled interrupts with a table of vectors in a BAR
+    MsiX {
+        /// Offset of the capability in configuration space
+        offset: u8,
+        /// Number of entries in the vector table
+        table_size: u16,
+        /// Index of the BAR holding the vector table
+        table_bar: u8,
+        /// Offset of the vector table within its BAR
+        table_offset: u32,
+        /// Index of the BAR holding the pending bit array
+        pending_bar: u8,
+        /// Offset of the pending bit array within its BAR
+        pending_offset: u32,
+    },
+    /// Capability without further decoding
+    Other {
+        /// Capability id
+        id: u8,
+        /// Offset of the capability in configuration space
+        offset: u8,
+    },
+}
+
+#[derive(Debug, Clone)]
+/// A PCI function found during enumeration
+pub struct Device {
+    /// Location of the function
+    pub address: Address,
+    /// Vendor id
+    pub vendor_id: u16,
+    /// Device id
+    pub device_id: u16,
+    /// Class code
+    pub class: u8,
+    /// Subclass
+    pub subclass: u8,
+    /// Programming interface
+    pub prog_if: u8,
+    /// Revision id
+    pub revision: u8,
+    /// Header type, without the multifunction bit
+    pub header_type: u8,
+    /// Base address registers by index. The slot after a 64 bit BAR is None.
+    pub bars: [Option<Bar>; 6],
+    /// Legacy interrupt line routed by the firmware
+    pub interrupt_line: u8,
+    /// Legacy interrupt pin, 1 for INTA# to 4 for INTD#, or 0 for none
+    pub interrupt_pin: u8,
+    /// Capability list
+    pub capabilities: Vec<Capability>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Which devices a driver handles. Fields that are None match anything.
+pub struct DeviceMatch {
+    /// Vendor id
+    pub vendor_id: Option<u16>,
+    /// Device id
+    pub device_id: Option<u16>,
+    /// Class code
+    pub class: Option<u8>,
+    /// Subclass
+    pub subclass: Option<u8>,
+    /// Programming interface
+    pub prog_if: Option<u8>,
+}
+
+/// A driver for PCI devices
+pub struct Driver {
+    /// Name of the driver
+    pub name: &'static str,
+    /// Devices the driver may handle
+    pub matches: &'static [DeviceMatch],
+    /// Take over a matching device, returning false if the driver can't handle it after all
+    pub probe: fn(&Device) -> bool,
+}
+
+/// A device found during enumeration, and the driver that claimed it
+struct Slot {
+    /// The device
+    device: Device,
+    /// Name of the driver handling the device
+    driver: Option<&'static str>,
+}
+
+lazy_static! {
+    /// Devices found by init
+    static ref DEVICES: Mutex<Vec<Slot>> = Mutex::new(Vec::new());
+    /// Registered drivers
+    static ref DRIVERS: Mutex<Vec<&'static Driver>> = Mutex::new(Vec::new());
+}
+
+impl DeviceMatch {
+    /// Match a vendor and device id
+    pub const fn id(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class: None,
+            subclass: None,
+            prog_if: None,
+        }
+    }
+
+    /// Match a class and subclass
+    #[allow(dead_code)]
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self {
+            vendor_id: None,
+            device_id: None,
+            class: Some(class),
+            subclass: Some(subclass),
+            prog_if: None,
+        }
+    }
+
+    /// Check whether a device matches
+    pub fn matches(&self, device: &Device) -> bool {
+        /// Check one field, where None matches anything
+        fn field<T: PartialEq>(expected: Option<T>, actual: T) -> bool {
+            expected.map_or(true, |expected| expected == actual)
+        }
+        field(self.vendor_id, device.vendor_id) && field(self.device_id, device.device_id)
+            && field(self.class, device.class) && field(self.subclass, device.subclass)
+            && field(self.prog_if, device.prog_if)
+    }
+}
+
+impl Device {
+    /// Read the configuration header of a present function
+    fn read(address: Address) -> Self {
+        let header_type = config::read_u8(address, HEADER_TYPE) & !MULTIFUNCTION;
+        let bar_count = match header_type {
+            HEADER_GENERAL => 6,
+            HEADER_BRIDGE => 2,
+            _ => 0,
+        };
+        Self {
+            address: address,
+            vendor_id: config::read_u16(address, VENDOR_ID),
+            device_id: config::read_u16(address, DEVICE_ID),
+            class: config::read_u8(address, CLASS),
+            subclass: config::read_u8(address, SUBCLASS),
+            prog_if: config::read_u8(address, PROG_IF),
+            revision: config::read_u8(address, REVISION_ID),
+            header_type: header_type,
+            bars: read_bars(address, bar_count),
+            interrupt_line: config::read_u8(address, INTERRUPT_LINE),
+            interrupt_pin: config::read_u8(address, INTERRUPT_PIN),
+            capabilities: read_capabilities(address),
+        }
+    }
+
+    /// Find a capability by id
+    pub fn capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities
+            .iter()
+            .find(|capability| capability.id() == id)
+            .cloned()
+    }
+
+    /// Let the device decode its I/O and memory BARs and act as a bus master
+    pub fn enable(&self) {
+        /// Command bit letting the device initiate transfers
+        const COMMAND_BUS_MASTER: u16 = 1 << 2;
+        let command = config::read_u16(self.address, COMMAND);
+        config::write_u16(
+            self.address,
+            COMMAND,
+            command | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER,
+        );
+    }
+
+    /// Get a description of the device's class
+    pub fn class_name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "network controller",
+            (0x03, _) => "display controller",
+            (0x04, _) => "multimedia controller",
+            (0x05, _) => "memory controller",
+            (0x06, 0x00) => "host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI-to-PCI bridge",
+            (0x06, _) => "bridge",
+            (0x07, _) => "communication controller",
+            (0x08, _) => "system peripheral",
+            (0x0c, 0x03) => "USB controller",
+            (0x0c, 0x05) => "SMBus controller",
+            (0x0c, _) => "serial bus controller",
+            _ => "unknown device",
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04x}:{:04x} {}",
+            self.address,
+            self.vendor_id,
+            self.device_id,
+            self.class_name()
+        )
+    }
+}
+
+impl Capability {
+    /// Get the capability id
+    pub fn id(&self) -> u8 {
+        match *self {
+            Capability::Msi { .. } => CAPABILITY_MSI,
+            Capability::MsiX { .. } => CAPABILITY_MSI_X,
+            Capability::Other { id, .. } => id,
+        }
+    }
+}
+
+/// Write all ones to a BAR and read back which address bits stick, restoring it afterwards
+fn probe_bar(address: Address, offset: u16) -> u32 {
+    let original = config::read_u32(address, offset);
+    config::write_u32(address, offset, !0);
+    let mask = config::read_u32(address, offset);
+    config::write_u32(address, offset, original);
+    mask
+}
+
+/// Decode and size the BARs of a function
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+fn read_bars(address: Address, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    // Decoding must be off while the BARs hold all ones, or they could shadow other devices
+    let command = config::read_u16(address, COMMAND);
+    config::write_u16(
+        address,
+        COMMAND,
+        command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+    );
+
+    let mut index = 0;
+    while index < count {
+        let offset = BAR0 + 4 * index as u16;
+        let value = config::read_u32(address, offset);
+        let mask = probe_bar(address, offset);
+        if value & 1 == 1 {
+            let size = (!(mask & !0x3)).wrapping_add(1) & 0xffff;
+            if size != 0 {
+                bars[index] = Some(Bar::Io {
+                    port: (value & !0x3) as u16,
+                    size: size as u16,
+                });
+            }
+            index += 1;
+            continue;
+        }
+
+        let is_64bit = (value >> 1) & 0x3 == 0x2 && index + 1 < count;
+        let (base, mask) = if is_64bit {
+            let upper = config::read_u32(address, offset + 4);
+            let upper_mask = probe_bar(address, offset + 4);
+            (
+                (u64::from(upper) << 32) | u64::from(value & !0xf),
+                (u64::from(upper_mask) << 32) | u64::from(mask & !0xf),
+            )
+        } else {
+            (u64::from(value & !0xf), u64::from(mask & !0xf))
+        };
+        // BARs without writable address bits aren't implemented, while 64 bit BARs of 4 GiB or
+        // more have none in their lower half
+        if mask != 0 {
+            let mask = if is_64bit { mask } else { mask | (!0 << 32) };
+            bars[index] = Some(Bar::Memory {
+                address: base,
+                size: (!mask).wrapping_add(1),
+                prefetchable: value & (1 << 3) != 0,
+                is_64bit: is_64bit,
+            });
+        }
+        index += if is_64bit { 2 } else { 1 };
+    }
+
+    config::write_u16(address, COMMAND, command);
+    bars
+}
+
+/// Walk the capability list of a function
+fn read_capabilities(address: Address) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if config::read_u16(address, STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+
+    let mut offset = config::read_u8(address, CAPABILITIES_POINTER) & 0xfc;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let register = u16::from(offset);
+        let id = config::read_u8(address, register);
+        let capability = match id {
+            CAPABILITY_MSI => {
+                let control = config::read_u16(address, register + 2);
+                Capability::Msi {
+                    offset: offset,
+                    is_64bit: control & (1 << 7) != 0,
+                    vectors: 1 << ((control >> 1) & 0x7),
+                    per_vector_masking: control & (1 << 8) != 0,
+                }
+            }
+            CAPABILITY_MSI_X => {
+                let control = config::read_u16(address, register + 2);
+                let table = config::read_u32(address, register + 4);
+                let pending = config::read_u32(address, register + 8);
+                Capability::MsiX {
+                    offset: offset,
+                    table_size: (control & 0x7ff) + 1,
+                    table_bar: (table & 0x7) as u8,
+                    table_offset: table & !0x7,
+                    pending_bar: (pending & 0x7) as u8,
+                    pending_offset: pending & !0x7,
+                }
+            }
+            _ => Capability::Other {
+                id: id,
+                offset: offset,
+            },
+        };
+        capabilities.push(capability);
+        offset = config::read_u8(address, register + 1) & 0xfc;
+    }
+    capabilities
+}
+
+/// Record the functions of a bus, descending into the buses behind bridges
+fn scan_bus(segment: u16, bus: u8, devices: &mut Vec<Device>) {
+    for device in 0..32 {
+        let address = Address {
+            segment: segment,
+            bus: bus,
+            device: device,
+            function: 0,
+        };
+        if config::read_u16(address, VENDOR_ID) == NO_VENDOR {
+            continue;
+        }
+        let functions = if config::read_u8(address, HEADER_TYPE) & MULTIFUNCTION != 0 {
+            8
+        } else {
+            1
+        };
+        for function in 0..functions {
+            let address = Address {
+                function: function,
+                ..address
+            };
+            if config::read_u16(address, VENDOR_ID) == NO_VENDOR {
+                continue;
+            }
+            let found = Device::read(address);
+            let secondary_bus = if found.header_type == HEADER_BRIDGE {
+                Some(config::read_u8(address, SECONDARY_BUS))
+            } else {
+                None
+            };
+            devices.push(found);
+            // A secondary bus at or below the bridge's own would make the scan loop
+            match secondary_bus {
+                Some(secondary_bus) if secondary_bus > bus => {
+                    scan_bus(segment, secondary_bus, devices)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Offer a device to the registered drivers, returning the name of the one that claimed it
+fn probe(device: &Device, drivers: &[&'static Driver]) -> Option<&'static str> {
+    drivers
+        .iter()
+        .filter(|driver| driver.matches.iter().any(|pattern| pattern.matches(device)))
+        .find(|driver| (driver.probe)(device))
+        .map(|driver| driver.name)
+}
+
+/// Enumerate the PCI functions of every segment group and offer them to the registered drivers
+pub fn init() {
+    assert_has_not_been_called!("pci::init must be called only once");
+    if let Some(mcfg) = acpi::mcfg() {
+        for entry in mcfg.entries() {
+            // MCFG base addresses are those of bus 0, even for areas starting at a later bus
+            let base = entry.base_address as usize + (usize::from(entry.start_bus) << 20);
+            config::add_ecam_area(base, entry.segment, entry.start_bus, entry.end_bus);
+        }
+    }
+
+    let mut devices = Vec::new();
+    scan_bus(0, 0, &mut devices);
+    for (segment, start_bus) in config::ecam_segments() {
+        if segment != 0 {
+            scan_bus(segment, start_bus, &mut devices);
+        }
+    }
+
+    let drivers = DRIVERS.lock().clone();
+    let slots: Vec<Slot> = devices
+        .into_iter()
+        .map(|device| {
+            let driver = probe(&device, &drivers);
+            Slot {
+                device: device,
+                driver: driver,
+            }
+        })
+        .collect();
+    *DEVICES.lock() = slots;
+}
+
+/// Add a driver, offering it the devices no other driver has claimed yet
+pub fn register_driver(driver: &'static Driver) {
+    DRIVERS.lock().push(driver);
+    let unclaimed: Vec<(usize, Device)> = DEVICES
+        .lock()
+        .iter()
+        .enumerate()
+        .filter(|&(_, slot)| slot.driver.is_none())
+        .map(|(index, slot)| (index, slot.device.clone()))
+        .collect();
+    // Probing happens without the lock held, since drivers may look at other devices
+    for (index, device) in unclaimed {
+        if let Some(name) = probe(&device, &[driver]) {
+            DEVICES.lock()[index].driver = Some(name);
+        }
+    }
+}
+
+/// Get every device found, with the name of the driver handling it
+pub fn devices() -> Vec<(Device, Option<&'static str>)> {
+    DEVICES
+        .lock()
+        .iter()
+        .map(|slot| (slot.device.clone(), slot.driver))
+        .collect()
+}
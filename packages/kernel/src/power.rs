@@ -0,0 +1,297 @@
+//! Rebooting and powering off the machine, and what to do after a panic
+use acpi;
+use core::{slice, str};
+use emergency;
+use idle;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use multiboot2::BootInformation;
+use x86_64::instructions::port::{inb, inw, outb, outl, outw};
+
+/// Status port of the PS/2 keyboard controller
+const KEYBOARD_STATUS: u16 = 0x64;
+/// Keyboard controller status bit set while its input buffer is full
+const KEYBOARD_INPUT_FULL: u8 = 1 << 1;
+/// Keyboard controller command pulsing the CPU reset line
+const KEYBOARD_RESET: u8 = 0xfe;
+/// Number of status reads to wait for the keyboard controller, or for a reset to take effect
+const RESET_TIMEOUT: usize = 0x10000;
+/// Port of QEMU's isa-debug-exit device, when started with
+/// `-device isa-debug-exit,iobase=0xf4,iosize=0x04`
+const QEMU_EXIT_PORT: u16 = 0xf4;
+/// Power off ports of emulators that don't need the AML: QEMU's PIIX4 and Q35 power management,
+/// Bochs and older QEMU, and VirtualBox
+const EMULATOR_POWER_OFF: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+/// PM1 control bit set once the firmware has handed power management over to the OS
+const SCI_EN: u16 = 1 << 0;
+/// Shift of the PM1 control sleep type field
+const SLP_TYP_SHIFT: u16 = 10;
+/// PM1 control sleep type field
+const SLP_TYP_MASK: u16 = 0x7 << SLP_TYP_SHIFT;
+/// PM1 control bit entering the sleep state in the sleep type field
+const SLP_EN: u16 = 1 << 13;
+/// Generic address space of I/O ports
+const ADDRESS_SPACE_IO: u8 = 1;
+/// Multiboot tag holding the kernel command line
+const TAG_COMMAND_LINE: u32 = 1;
+/// Multiboot tag ending the tag list
+const TAG_END: u32 = 0;
+
+/// AML opcode defining a name
+const AML_NAME_OP: u8 = 0x08;
+/// AML prefix of names relative to the root of the namespace
+const AML_ROOT_PREFIX: u8 = 0x5c;
+/// AML opcode of a package
+const AML_PACKAGE_OP: u8 = 0x12;
+/// AML opcode of the constant 0
+const AML_ZERO_OP: u8 = 0x00;
+/// AML opcode of the constant 1
+const AML_ONE_OP: u8 = 0x01;
+/// AML prefix of a byte constant
+const AML_BYTE_PREFIX: u8 = 0x0a;
+/// AML prefix of a word constant
+const AML_WORD_PREFIX: u8 = 0x0b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What the kernel does once it has reported a panic or an unrecoverable exception
+pub enum PanicPolicy {
+    /// Stop the processor, leaving the message on screen
+    Halt,
+    /// Restart the machine
+    Reboot,
+    /// Exit QEMU through its isa-debug-exit device with a failure status, halting elsewhere
+    ExitQemu,
+}
+
+/// Current panic policy, as a PanicPolicy discriminant. Halt until configured otherwise.
+static PANIC_POLICY: AtomicUsize = ATOMIC_USIZE_INIT;
+
+impl PanicPolicy {
+    /// Parse a policy as given in the panic= option of the kernel command line
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(PanicPolicy::Halt),
+            "reboot" => Some(PanicPolicy::Reboot),
+            "exit" => Some(PanicPolicy::ExitQemu),
+            _ => None,
+        }
+    }
+
+    /// Turn a value stored in PANIC_POLICY back into a policy
+    fn from_usize(value: usize) -> Self {
+        match value {
+            1 => PanicPolicy::Reboot,
+            2 => PanicPolicy::ExitQemu,
+            _ => PanicPolicy::Halt,
+        }
+    }
+}
+
+/// Set what the kernel does after a panic
+pub fn set_panic_policy(policy: PanicPolicy) {
+    PANIC_POLICY.store(policy as usize, Ordering::SeqCst);
+}
+
+/// Get what the kernel does after a panic
+pub fn panic_policy() -> PanicPolicy {
+    PanicPolicy::from_usize(PANIC_POLICY.load(Ordering::SeqCst))
+}
+
+/// Get the kernel command line GRUB passes, empty if there is none
+#[cfg_attr(feature = "cargo-clippy", allow(cast_ptr_alignment))]
+fn command_line(boot_info: &BootInformation) -> &'static str {
+    let mut tag = boot_info.start_address() + 8;
+    let end = boot_info.end_address();
+    while tag + 8 <= end {
+        let (tag_type, size) = unsafe { (*(tag as *const u32), *((tag + 4) as *const u32)) };
+        if tag_type == TAG_END || size < 8 {
+            break;
+        }
+        if tag_type == TAG_COMMAND_LINE {
+            let bytes = unsafe { slice::from_raw_parts((tag + 8) as *const u8, size as usize - 8) };
+            // The string is null terminated
+            let length = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+            return str::from_utf8(&bytes[..length]).unwrap_or("");
+        }
+        tag += (size as usize + 7) & !7;
+    }
+    ""
+}
+
+/// Get the value of an option of the kernel command line, such as reboot for `panic=reboot`.
+/// The last one counts if the option is given more than once.
+pub fn command_line_option(boot_info: &BootInformation, name: &str) -> Option<&'static str> {
+    command_line(boot_info)
+        .split_whitespace()
+        .filter_map(|word| {
+            if word.starts_with(name) && word[name.len()..].starts_with('=') {
+                Some(&word[name.len() + 1..])
+            } else {
+                None
+            }
+        })
+        .last()
+}
+
+/// Apply the panic= option of the kernel command line, such as `panic=reboot`
+pub fn init(boot_info: &BootInformation) {
+    let option = command_line_option(boot_info, "panic");
+    if let Some(name) = option {
+        match PanicPolicy::parse(name) {
+            Some(policy) => set_panic_policy(policy),
+            None => println!("unknown panic policy {}, expected halt, reboot or exit", name),
+        }
+    }
+}
+
+/// Carry out the panic policy. Called once a panic or an unrecoverable exception is reported.
+pub fn on_panic() -> ! {
+    match panic_policy() {
+        PanicPolicy::Halt => idle::halt_forever(),
+        PanicPolicy::Reboot => reboot(),
+        PanicPolicy::ExitQemu => {
+            exit_qemu(1);
+            idle::halt_forever()
+        }
+    }
+}
+
+/// Exit QEMU with status (code << 1) | 1. Does nothing without the isa-debug-exit device.
+pub fn exit_qemu(code: u32) {
+    unsafe { outl(QEMU_EXIT_PORT, code) };
+}
+
+/// Give a reset request some time to take effect
+fn wait_for_reset() {
+    for _ in 0..RESET_TIMEOUT {
+        unsafe { inb(KEYBOARD_STATUS) };
+    }
+}
+
+/// Restart the machine through the keyboard controller, then the ACPI reset register, and
+/// finally by triple faulting
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn reboot() -> ! {
+    use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+
+    unsafe { ::x86_64::instructions::interrupts::disable() };
+
+    // The keyboard controller ignores commands until its input buffer is empty
+    for _ in 0..RESET_TIMEOUT {
+        if unsafe { inb(KEYBOARD_STATUS) } & KEYBOARD_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    unsafe { outb(KEYBOARD_STATUS, KEYBOARD_RESET) };
+    wait_for_reset();
+
+    // Only reset registers in I/O space are used, since mapping memory would need the memory
+    // controller, which a panicking thread may hold
+    if let Some((register, value)) = acpi::fadt().and_then(|fadt| fadt.reset_register()) {
+        if register.address_space == ADDRESS_SPACE_IO {
+            unsafe { outb(register.address as u16, value) };
+            wait_for_reset();
+        }
+    }
+
+    // With an empty IDT, the breakpoint exception can't be delivered, and neither can the double
+    // fault that follows, which resets the processor
+    let empty = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe {
+        lidt(&empty);
+        ::x86_64::instructions::interrupts::int3();
+    }
+    idle::halt_forever()
+}
+
+/// Power the machine off through ACPI, then through emulator specific ports. Halts if nothing
+/// works.
+pub fn shutdown() -> ! {
+    unsafe { ::x86_64::instructions::interrupts::disable() };
+    enter_s5();
+    for &(port, value) in &EMULATOR_POWER_OFF {
+        unsafe { outw(port, value) };
+    }
+    exit_qemu(0);
+    // The writer's lock may be held by code that can't run anymore with interrupts disabled
+    emergency::print(format_args!("Could not power off, halting\n"));
+    idle::halt_forever()
+}
+
+/// Enter the ACPI S5 (soft off) sleep state, using the sleep types of the \_S5 package of the
+/// DSDT. Returns if the tables don't describe how.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+fn enter_s5() {
+    let fadt = match acpi::fadt() {
+        Some(fadt) => fadt,
+        None => return,
+    };
+    let (sleep_type_a, sleep_type_b) = match acpi::dsdt().and_then(|dsdt| find_s5(dsdt.data())) {
+        Some(sleep_types) => sleep_types,
+        None => return,
+    };
+    let pm1a = fadt.pm1a_control_block() as u16;
+    let pm1b = fadt.pm1b_control_block() as u16;
+    if pm1a == 0 {
+        return;
+    }
+
+    // The firmware may still own power management, in which case it has to be asked for it
+    if unsafe { inw(pm1a) } & SCI_EN == 0 && fadt.smi_command() != 0 && fadt.acpi_enable() != 0 {
+        unsafe { outb(fadt.smi_command() as u16, fadt.acpi_enable()) };
+        for _ in 0..RESET_TIMEOUT {
+            if unsafe { inw(pm1a) } & SCI_EN != 0 {
+                break;
+            }
+        }
+    }
+
+    for &(port, sleep_type) in &[(pm1a, sleep_type_a), (pm1b, sleep_type_b)] {
+        if port != 0 {
+            let control = unsafe { inw(port) } & !SLP_TYP_MASK;
+            let sleep = (sleep_type << SLP_TYP_SHIFT) & SLP_TYP_MASK;
+            unsafe { outw(port, control | sleep | SLP_EN) };
+        }
+    }
+    wait_for_reset();
+}
+
+/// Find the sleep types for PM1a and PM1b in the definition of the \_S5 package in AML code.
+/// Only handles the plain `Name(_S5, Package() {a, b, ...})` firmware uses in practice.
+fn find_s5(aml: &[u8]) -> Option<(u16, u16)> {
+    (0..aml.len().saturating_sub(4))
+        .filter(|&position| &aml[position..position + 4] == b"_S5_")
+        .filter(|&position| {
+            let name_op = match aml[..position].last() {
+                Some(&AML_ROOT_PREFIX) if position >= 2 => aml[position - 2],
+                Some(&byte) => byte,
+                None => return false,
+            };
+            name_op == AML_NAME_OP && aml.get(position + 4) == Some(&AML_PACKAGE_OP)
+        })
+        .filter_map(|position| {
+            // The package length takes one to four bytes, as told by the top bits of its first
+            let length_offset = position + 5;
+            let length_size = usize::from(*aml.get(length_offset)? >> 6) + 1;
+            // Skip the element count
+            let first = length_offset + length_size + 1;
+            let (sleep_type_a, second) = aml_integer(aml, first)?;
+            let (sleep_type_b, _) = aml_integer(aml, second)?;
+            Some((sleep_type_a, sleep_type_b))
+        })
+        .next()
+}
+
+/// Decode a small AML integer constant at offset, returning it and the offset past it
+fn aml_integer(aml: &[u8], offset: usize) -> Option<(u16, usize)> {
+    match *aml.get(offset)? {
+        AML_ZERO_OP => Some((0, offset + 1)),
+        AML_ONE_OP => Some((1, offset + 1)),
+        AML_BYTE_PREFIX => Some((u16::from(*aml.get(offset + 1)?), offset + 2)),
+        AML_WORD_PREFIX => {
+            let low = u16::from(*aml.get(offset + 1)?);
+            let high = u16::from(*aml.get(offset + 2)?);
+            Some((low | (high << 8), offset + 3))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,426 @@
+//! User processes: programs running in their own address space, with open files and a parent
+//! that collects their exit code
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+use fs;
+use loader;
+use memory::{self, InactivePageTable};
+use scheduler::{self, ThreadId};
+use sync::{self, SpinLock};
+use syscall::SyscallFrame;
+use usermode;
+
+/// Number of file descriptors a process can have open at once
+const MAX_FILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Unique identifier of a process
+pub struct Pid(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Who collects the exit code of a process
+pub enum Parent {
+    /// A kernel thread, through wait_for
+    Kernel,
+    /// Another process, through the wait system call
+    Process(Pid),
+    /// Nobody, since the parent exited first. The process is released as soon as it exits.
+    Orphan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons a process operation can fail
+pub enum Error {
+    /// The program could not be read
+    Fs(fs::Error),
+    /// The program is not a loadable executable
+    Load(loader::Error),
+    /// There is no child process to wait for
+    NoChildren,
+}
+
+#[derive(Clone)]
+/// Something a file descriptor refers to
+pub enum Descriptor {
+    /// The console: writes are printed, reads wait for keyboard input
+    Console,
+    /// An open file, shared between descriptors duplicated from the same open
+    File(Arc<sync::Mutex<fs::File>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Lifecycle state of a process
+enum ProcessState {
+    /// At least one of its threads is still running
+    Running,
+    /// Exited with the given code, waiting for its parent to collect it
+    Zombie(usize),
+}
+
+/// A user process
+struct Process {
+    /// Collector of the exit code
+    parent: Parent,
+    /// Processes started by this one that have not been collected yet
+    children: Vec<Pid>,
+    /// Address space the process runs in, or None once it has been freed
+    address_space: Option<InactivePageTable>,
+    /// Open files by descriptor number
+    files: Vec<Option<Descriptor>>,
+    /// Lifecycle state
+    state: ProcessState,
+}
+
+/// All processes that have not been collected yet
+struct ProcessTable {
+    /// Processes by id
+    processes: BTreeMap<Pid, Process>,
+    /// Process each user thread belongs to
+    threads: BTreeMap<ThreadId, Pid>,
+    /// Threads waiting for a child of the given parent to exit
+    waiters: Vec<(Parent, ThreadId)>,
+    /// Id given to the next process
+    next_pid: usize,
+}
+
+lazy_static! {
+    /// The process table. Page faults look up the running process with interrupts disabled, so
+    /// the lock keeps them disabled too.
+    static ref PROCESSES: SpinLock<ProcessTable> = SpinLock::new("processes", ProcessTable {
+        processes: BTreeMap::new(),
+        threads: BTreeMap::new(),
+        waiters: Vec::new(),
+        next_pid: 1,
+    });
+}
+
+impl From<fs::Error> for Error {
+    fn from(error: fs::Error) -> Self {
+        Error::Fs(error)
+    }
+}
+
+impl From<loader::Error> for Error {
+    fn from(error: loader::Error) -> Self {
+        Error::Load(error)
+    }
+}
+
+impl ProcessTable {
+    /// Get a process by id
+    fn process_mut(&mut self, pid: Pid) -> &mut Process {
+        self.processes.get_mut(&pid).expect("no such process")
+    }
+
+    /// Get the process the running thread belongs to
+    fn current(&self) -> Option<Pid> {
+        self.threads.get(&scheduler::current()).cloned()
+    }
+
+    /// Hand out a new process id
+    fn allocate_pid(&mut self) -> Pid {
+        let pid = Pid(self.next_pid);
+        self.next_pid += 1;
+        pid
+    }
+
+    /// Wake up the threads waiting for a child of parent to exit
+    fn wake_waiters(&mut self, parent: Parent) {
+        let waiters = mem::replace(&mut self.waiters, Vec::new());
+        for (waiting_for, thread) in waiters {
+            if waiting_for == parent {
+                scheduler::unblock(thread);
+            } else {
+                self.waiters.push((waiting_for, thread));
+            }
+        }
+    }
+
+    /// Remove a zombie child of parent, matching pid if given, and return its id and exit code.
+    /// Returns Ok(None) if there are matching children but none has exited yet.
+    fn reap(&mut self, parent: Parent, pid: Option<Pid>) -> Result<Option<(Pid, usize)>, Error> {
+        let mut found = false;
+        let mut zombie = None;
+        for (&child_pid, child) in &self.processes {
+            if child.parent != parent || pid.map_or(false, |pid| pid != child_pid) {
+                continue;
+            }
+            found = true;
+            if let ProcessState::Zombie(code) = child.state {
+                zombie = Some((child_pid, code));
+                break;
+            }
+        }
+        if !found {
+            return Err(Error::NoChildren);
+        }
+
+        if let Some((child_pid, _)) = zombie {
+            self.processes.remove(&child_pid);
+            if let Parent::Process(parent_pid) = parent {
+                self.process_mut(parent_pid)
+                    .children
+                    .retain(|&pid| pid != child_pid);
+            }
+        }
+        Ok(zombie)
+    }
+}
+
+/// Start the program at an absolute path as a new process. Kernel threads collect its exit
+/// code with wait_for.
+pub fn spawn(path: &str) -> Result<Pid, Error> {
+    start(path, Parent::Kernel)
+}
+
+/// Start the program at an absolute path as a child of the running process
+pub fn spawn_child(path: &str) -> Result<Pid, Error> {
+    let pid = current().expect("spawn_child called from a kernel thread");
+    start(path, Parent::Process(pid))
+}
+
+/// Load the program at an absolute path into a new process and start its thread
+fn start(path: &str, parent: Parent) -> Result<Pid, Error> {
+    let image = fs::open(path)?.read_to_end()?;
+    let program = loader::load(&image)?;
+    let entry_point = program.entry_point;
+    let stack_top = program.stack_top;
+    let files = vec![
+        Some(Descriptor::Console),
+        Some(Descriptor::Console),
+        Some(Descriptor::Console),
+    ];
+    Ok(launch(parent, program.address_space, files, move || unsafe {
+        usermode::enter(entry_point, stack_top)
+    }))
+}
+
+/// Add a process to the table and start its thread running f in its address space
+fn launch<F>(
+    parent: Parent,
+    address_space: InactivePageTable,
+    files: Vec<Option<Descriptor>>,
+    f: F,
+) -> Pid
+where
+    F: FnOnce() + Send + 'static,
+{
+    let p4_address = address_space.p4_address();
+    let pid = {
+        let mut processes = PROCESSES.lock();
+        let pid = processes.allocate_pid();
+        processes.processes.insert(
+            pid,
+            Process {
+                parent: parent,
+                children: Vec::new(),
+                address_space: Some(address_space),
+                files: files,
+                state: ProcessState::Running,
+            },
+        );
+        if let Parent::Process(parent_pid) = parent {
+            processes.process_mut(parent_pid).children.push(pid);
+        }
+        pid
+    };
+    // Starting the thread needs the scheduler and the memory controller, so the table isn't
+    // locked meanwhile. The thread registers itself before its code runs, so its system calls
+    // find the process.
+    let thread = scheduler::spawn_in(p4_address, move || {
+        PROCESSES.lock().threads.insert(scheduler::current(), pid);
+        f()
+    });
+    scheduler::detach(thread);
+    pid
+}
+
+/// Duplicate the running process. The child gets a copy-on-write copy of the address space and
+/// shares the open files, then returns from the system call described by frame with 0.
+pub fn fork(frame: &SyscallFrame) -> Pid {
+    let parent = current().expect("fork called from a kernel thread");
+    let address_space = memory::controller().fork_address_space();
+    let files = PROCESSES.lock().process_mut(parent).files.clone();
+    let mut child_frame = frame.clone();
+    child_frame.rax = 0;
+    launch(
+        Parent::Process(parent),
+        address_space,
+        files,
+        move || unsafe { usermode::resume(&child_frame) },
+    )
+}
+
+/// Replace the program of the running process with the one at an absolute path, keeping its
+/// id and open files. Only returns if the program can't be loaded, leaving the process as it was.
+pub fn exec(path: &str) -> Error {
+    // The path may point into the address space that is about to be freed
+    let path = String::from(path);
+    let program = match fs::open(&path)
+        .and_then(|mut file| file.read_to_end())
+        .map_err(Error::from)
+        .and_then(|image| loader::load(&image).map_err(Error::from))
+    {
+        Ok(program) => program,
+        Err(error) => return error,
+    };
+
+    scheduler::set_address_space(program.address_space.p4_address());
+    let old_address_space = {
+        let mut processes = PROCESSES.lock();
+        let pid = processes.current().expect("exec called from a kernel thread");
+        mem::replace(
+            &mut processes.process_mut(pid).address_space,
+            Some(program.address_space),
+        )
+    };
+    if let Some(old_address_space) = old_address_space {
+        memory::controller().free_address_space(old_address_space);
+    }
+    unsafe { usermode::enter(program.entry_point, program.stack_top) }
+}
+
+/// Get the process the running thread belongs to, or None for kernel threads
+pub fn current() -> Option<Pid> {
+    PROCESSES.lock().current()
+}
+
+/// Get the id of the parent of the running process, or None if it has no parent process
+pub fn parent() -> Option<Pid> {
+    let processes = PROCESSES.lock();
+    let pid = processes.current()?;
+    match processes.processes.get(&pid).map(|process| process.parent) {
+        Some(Parent::Process(parent)) => Some(parent),
+        _ => None,
+    }
+}
+
+/// Finish the running process with an exit code, freeing its memory and files. Its children
+/// become orphans. Must be called from a process thread.
+pub fn exit(code: usize) -> ! {
+    // The address space can't be freed while it is loaded
+    scheduler::set_address_space(scheduler::kernel_address_space());
+
+    let (address_space, files) = {
+        let mut processes = PROCESSES.lock();
+        let pid = processes.current().expect("kernel thread exited as a process");
+        let thread = scheduler::current();
+        processes.threads.remove(&thread);
+
+        let (parent, children, address_space, files) = {
+            let process = processes.process_mut(pid);
+            process.state = ProcessState::Zombie(code);
+            (
+                process.parent,
+                mem::replace(&mut process.children, Vec::new()),
+                process.address_space.take(),
+                mem::replace(&mut process.files, Vec::new()),
+            )
+        };
+
+        for child in children {
+            let exited = {
+                let process = processes.process_mut(child);
+                process.parent = Parent::Orphan;
+                process.state != ProcessState::Running
+            };
+            if exited {
+                processes.processes.remove(&child);
+            }
+        }
+
+        if parent == Parent::Orphan {
+            processes.processes.remove(&pid);
+        } else {
+            processes.wake_waiters(parent);
+        }
+        (address_space, files)
+    };
+    // Closing the files may have to wait, which can't be done with the table locked
+    drop(files);
+
+    if let Some(address_space) = address_space {
+        memory::controller().free_address_space(address_space);
+    }
+    scheduler::exit()
+}
+
+/// Wait for a child of parent to exit, matching pid if given, and collect its id and exit code
+fn wait_child(parent: Parent, pid: Option<Pid>) -> Result<(Pid, usize), Error> {
+    loop {
+        {
+            let mut processes = PROCESSES.lock();
+            if let Some(zombie) = processes.reap(parent, pid)? {
+                return Ok(zombie);
+            }
+            processes.waiters.push((parent, scheduler::current()));
+        }
+        scheduler::block_current();
+    }
+}
+
+/// Wait for a child of the running process to exit, or for any child if pid is None, and
+/// collect its id and exit code
+pub fn wait(pid: Option<Pid>) -> Result<(Pid, usize), Error> {
+    let parent = current().expect("wait called from a kernel thread");
+    wait_child(Parent::Process(parent), pid)
+}
+
+/// Wait for a process spawned by a kernel thread to exit, returning its exit code
+pub fn wait_for(pid: Pid) -> Result<usize, Error> {
+    wait_child(Parent::Kernel, Some(pid)).map(|(_, code)| code)
+}
+
+/// Get what a file descriptor of the running process refers to. Kernel threads running user
+/// code have the console open as descriptors 0, 1 and 2.
+pub fn descriptor(fd: usize) -> Option<Descriptor> {
+    let processes = PROCESSES.lock();
+    match processes.current() {
+        Some(pid) => processes
+            .processes
+            .get(&pid)
+            .and_then(|process| process.files.get(fd).cloned())
+            .and_then(|descriptor| descriptor),
+        None if fd <= 2 => Some(Descriptor::Console),
+        None => None,
+    }
+}
+
+/// Add a descriptor to the running process under the lowest free number, returning the number
+/// or None if the table is full
+pub fn add_descriptor(descriptor: Descriptor) -> Option<usize> {
+    let mut processes = PROCESSES.lock();
+    let pid = processes.current()?;
+    let files = &mut processes.process_mut(pid).files;
+    match files.iter().position(|file| file.is_none()) {
+        Some(fd) => {
+            files[fd] = Some(descriptor);
+            Some(fd)
+        }
+        None if files.len() < MAX_FILES => {
+            files.push(Some(descriptor));
+            Some(files.len() - 1)
+        }
+        None => None,
+    }
+}
+
+/// Close a file descriptor of the running process, returning whether it was open
+pub fn close_descriptor(fd: usize) -> bool {
+    let mut processes = PROCESSES.lock();
+    let pid = match processes.current() {
+        Some(pid) => pid,
+        None => return false,
+    };
+    let file = processes
+        .process_mut(pid)
+        .files
+        .get_mut(fd)
+        .and_then(|file| file.take());
+    // Closing the file may have to wait, which can't be done with the table locked
+    drop(processes);
+    file.is_some()
+}
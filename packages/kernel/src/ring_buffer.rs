@@ -0,0 +1,46 @@
+//! Fixed-size byte queue, which interrupt handlers can fill without allocating
+/// Number of bytes a RingBuffer holds
+const CAPACITY: usize = 256;
+
+/// Queue of bytes in the order they were pushed
+pub struct RingBuffer {
+    /// Storage of the bytes
+    bytes: [u8; CAPACITY],
+    /// Index of the oldest byte
+    start: usize,
+    /// Number of bytes queued
+    length: usize,
+}
+
+impl RingBuffer {
+    /// Create an empty queue
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; CAPACITY],
+            start: 0,
+            length: 0,
+        }
+    }
+
+    /// Add a byte, returning false if the queue is full
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.length == CAPACITY {
+            return false;
+        }
+        self.bytes[(self.start + self.length) % CAPACITY] = byte;
+        self.length += 1;
+        true
+    }
+
+    /// Take the oldest byte
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.length == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % CAPACITY;
+        self.length -= 1;
+        Some(byte)
+    }
+}
@@ -0,0 +1,412 @@
+//! Kernel threads, scheduled round-robin and preempted by the timer interrupt
+pub use self::thread::{ThreadId, ThreadState};
+use self::thread::Thread;
+use alloc::boxed::Box;
+use alloc::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use alloc::vec_deque::VecDeque;
+use core::mem;
+use core::sync::atomic::Ordering;
+use idle;
+use interrupts::{self, without_interrupts};
+use memory::{self, PhysicalAddress, Stack, StackOwner};
+use smp::percpu::{self, NO_THREAD};
+use spin::{Mutex, MutexGuard, Once};
+use syscall;
+
+mod thread;
+
+/// Number of timer ticks a thread may run before it is preempted
+const TIME_SLICE: usize = 5;
+/// Size of kernel thread stacks
+const THREAD_STACK_PAGES: usize = 4;
+
+extern "C" {
+    /// Save the current context, storing its stack pointer in old_stack_pointer, and switch to
+    /// the context saved at new_stack_pointer. Defined in context_switch.asm.
+    fn switch_context(old_stack_pointer: *mut usize, new_stack_pointer: usize);
+}
+
+/// Thread table and run queue
+struct Scheduler {
+    /// All threads that have not been joined yet
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// Threads ready to run, in the order they will run
+    run_queue: VecDeque<ThreadId>,
+    /// Thread currently running
+    current: ThreadId,
+    /// Thread run when nothing else is ready
+    idle: ThreadId,
+    /// Id given to the next thread
+    next_id: usize,
+    /// Timer ticks left before the current thread is preempted
+    remaining_ticks: usize,
+    /// Address space of kernel threads, the one that was active when the scheduler started
+    kernel_address_space: PhysicalAddress,
+}
+
+/// Contexts to switch between
+struct Switch {
+    /// Thread switched to
+    thread: ThreadId,
+    /// Where to save the stack pointer of the current thread
+    old_stack_pointer: *mut usize,
+    /// Saved stack pointer of the next thread
+    new_stack_pointer: usize,
+    /// Top of the next thread's stack, used when it is interrupted in user mode
+    kernel_stack_top: Option<usize>,
+    /// Address space to load, or None if the next thread shares the current one
+    address_space: Option<PhysicalAddress>,
+}
+
+/// The scheduler, available once scheduler::init has been called
+static SCHEDULER: Once<Mutex<Scheduler>> = Once::new();
+
+impl Scheduler {
+    /// Get a thread by id
+    fn thread_mut(&mut self, id: ThreadId) -> &mut Thread {
+        self.threads.get_mut(&id).expect("no such thread")
+    }
+
+    /// Hand out a new thread id
+    fn allocate_id(&mut self) -> ThreadId {
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Make a blocked thread ready again, or make it skip its next block if it hasn't blocked yet
+    fn unblock(&mut self, id: ThreadId) {
+        let ready = match self.threads.get_mut(&id) {
+            Some(thread) => match thread.state {
+                ThreadState::Blocked => {
+                    thread.state = ThreadState::Ready;
+                    true
+                }
+                ThreadState::Ready | ThreadState::Running => {
+                    thread.wakeup_pending = true;
+                    false
+                }
+                ThreadState::Exited => false,
+            },
+            None => false,
+        };
+        if ready {
+            self.run_queue.push_back(id);
+        }
+    }
+
+    /// Take the stacks of exited threads other than the current one, and release exited
+    /// detached threads
+    fn take_dead_stacks(&mut self) -> Vec<Stack> {
+        let current = self.current;
+        let stacks = self.threads
+            .values_mut()
+            .filter(|thread| thread.state == ThreadState::Exited && thread.id() != current)
+            .filter_map(|thread| thread.stack.take())
+            .collect();
+        let released: Vec<ThreadId> = self.threads
+            .values()
+            .filter(|thread| {
+                thread.detached && thread.state == ThreadState::Exited && thread.id() != current
+            })
+            .map(|thread| thread.id())
+            .collect();
+        for id in released {
+            self.threads.remove(&id);
+        }
+        stacks
+    }
+
+    /// Pick the next thread to run, returning where to save the current context and which
+    /// context to load, or None if the current thread keeps running
+    fn next_switch(&mut self) -> Option<Switch> {
+        let current = self.current;
+        let idle = self.idle;
+        if self.thread_mut(current).state == ThreadState::Running {
+            self.thread_mut(current).state = ThreadState::Ready;
+            if current != idle {
+                self.run_queue.push_back(current);
+            }
+        }
+
+        let next = self.run_queue.pop_front().unwrap_or(idle);
+        self.thread_mut(next).state = ThreadState::Running;
+        self.remaining_ticks = TIME_SLICE;
+        if next == current {
+            return None;
+        }
+        self.current = next;
+
+        let old_address_space = self.thread_mut(current).address_space;
+        let old_stack_pointer = &mut self.thread_mut(current).stack_pointer as *mut usize;
+        let next = self.thread_mut(next);
+        Some(Switch {
+            thread: next.id(),
+            old_stack_pointer: old_stack_pointer,
+            new_stack_pointer: next.stack_pointer,
+            kernel_stack_top: next.stack.as_ref().map(|stack| stack.top()),
+            address_space: if next.address_space == old_address_space {
+                None
+            } else {
+                Some(next.address_space)
+            },
+        })
+    }
+}
+
+/// Lock the scheduler. Interrupts must be disabled while it is held, or the timer could deadlock.
+fn scheduler() -> MutexGuard<'static, Scheduler> {
+    SCHEDULER
+        .try()
+        .expect("scheduler is not initialized")
+        .lock()
+}
+
+/// Load an address space into CR3. The kernel and its stacks are mapped the same in every
+/// address space, so execution continues normally.
+fn load_address_space(address_space: PhysicalAddress) {
+    use x86_64::registers::control_regs;
+    unsafe { control_regs::cr3_write(::x86_64::PhysicalAddress(address_space as u64)) };
+}
+
+/// Switch to the next thread. Interrupts must be disabled.
+fn schedule() {
+    let switch = scheduler().next_switch();
+    if let Some(switch) = switch {
+        if let Some(stack_top) = switch.kernel_stack_top {
+            interrupts::set_kernel_stack(stack_top);
+            syscall::set_kernel_stack(stack_top);
+        }
+        if let Some(address_space) = switch.address_space {
+            load_address_space(address_space);
+        }
+        percpu::current()
+            .current_thread()
+            .store(switch.thread.0, Ordering::SeqCst);
+        unsafe { switch_context(switch.old_stack_pointer, switch.new_stack_pointer) };
+    }
+}
+
+/// First function run by every new thread
+extern "C" fn thread_entry() -> ! {
+    // Threads start out with interrupts disabled, since they are switched to from the scheduler
+    let id = current();
+    let entry = scheduler().thread_mut(id).entry.take();
+    unsafe { ::x86_64::instructions::interrupts::enable() };
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit()
+}
+
+/// Body of the idle thread, which runs while no other thread is runnable
+fn idle() {
+    loop {
+        idle::idle();
+    }
+}
+
+/// Turn the running flow of control into the first thread, and start the idle thread
+pub fn init() {
+    assert_has_not_been_called!("scheduler::init must be called only once");
+    let boot = ThreadId(0);
+    SCHEDULER.call_once(|| {
+        let kernel_address_space = memory::active_address_space();
+        let mut threads = BTreeMap::new();
+        threads.insert(
+            boot,
+            Box::new(Thread::current(boot, kernel_address_space)),
+        );
+        Mutex::new(Scheduler {
+            threads: threads,
+            run_queue: VecDeque::new(),
+            current: boot,
+            idle: boot,
+            next_id: 1,
+            remaining_ticks: TIME_SLICE,
+            kernel_address_space: kernel_address_space,
+        })
+    });
+    percpu::current()
+        .current_thread()
+        .store(boot.0, Ordering::SeqCst);
+
+    let idle = spawn(idle);
+    without_interrupts(|| {
+        let mut scheduler = scheduler();
+        scheduler.run_queue.retain(|&id| id != idle);
+        scheduler.idle = idle;
+    });
+}
+
+/// Start a new kernel thread running f
+pub fn spawn<F>(f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let kernel_address_space = without_interrupts(|| scheduler().kernel_address_space);
+    spawn_in(kernel_address_space, f)
+}
+
+/// Start a new thread running f in the address space whose P4 table is at address_space. The
+/// address space must share the kernel's mappings and outlive the thread.
+pub fn spawn_in<F>(address_space: PhysicalAddress, f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    // Threads can't free their own stack, so the stacks of exited threads are freed here
+    let dead_stacks = without_interrupts(|| scheduler().take_dead_stacks());
+    for stack in dead_stacks {
+        memory::controller().free_stack(stack);
+    }
+
+    let id = without_interrupts(|| scheduler().allocate_id());
+    let stack = memory::controller()
+        .alloc_stack(THREAD_STACK_PAGES, StackOwner::Thread(id.0))
+        .expect("could not allocate thread stack");
+    let thread = Thread::new(id, stack, Box::new(f), thread_entry, address_space);
+
+    without_interrupts(|| {
+        let mut scheduler = scheduler();
+        scheduler.threads.insert(id, Box::new(thread));
+        scheduler.run_queue.push_back(id);
+    });
+    id
+}
+
+/// Release a thread once it exits, without anyone joining it
+pub fn detach(id: ThreadId) {
+    without_interrupts(|| {
+        if let Some(thread) = scheduler().threads.get_mut(&id) {
+            thread.detached = true;
+        }
+    });
+}
+
+/// Switch the running thread to another address space, such as the kernel's before its own is
+/// freed. Kernel threads pass kernel_address_space().
+pub fn set_address_space(address_space: PhysicalAddress) {
+    without_interrupts(|| {
+        let mut scheduler = scheduler();
+        let current = scheduler.current;
+        scheduler.thread_mut(current).address_space = address_space;
+        load_address_space(address_space);
+    });
+}
+
+/// Get the address space kernel threads run in
+pub fn kernel_address_space() -> PhysicalAddress {
+    without_interrupts(|| scheduler().kernel_address_space)
+}
+
+/// Get the id of the running thread
+pub fn current() -> ThreadId {
+    without_interrupts(|| scheduler().current)
+}
+
+/// Get the id of the running thread without locking the scheduler, or None if the scheduler
+/// doesn't run on this processor yet
+pub fn try_current() -> Option<ThreadId> {
+    match percpu::try_current()?.current_thread().load(Ordering::SeqCst) {
+        NO_THREAD => None,
+        id => Some(ThreadId(id)),
+    }
+}
+
+/// Check whether threads other than the running one are ready to run
+pub fn has_ready_threads() -> bool {
+    without_interrupts(|| !scheduler().run_queue.is_empty())
+}
+
+/// Let other threads run before continuing
+pub fn yield_now() {
+    without_interrupts(schedule);
+}
+
+/// Stop running the current thread until unblock is called for it. Returns immediately if
+/// unblock was already called since the last time the thread blocked.
+pub fn block_current() {
+    without_interrupts(|| {
+        let blocked = {
+            let mut scheduler = scheduler();
+            let current = scheduler.current;
+            let thread = scheduler.thread_mut(current);
+            if thread.wakeup_pending {
+                thread.wakeup_pending = false;
+                false
+            } else {
+                thread.state = ThreadState::Blocked;
+                true
+            }
+        };
+        if blocked {
+            schedule();
+        }
+    });
+}
+
+/// Let a blocked thread run again
+pub fn unblock(id: ThreadId) {
+    without_interrupts(|| scheduler().unblock(id));
+}
+
+/// Finish the current thread, waking up threads joining it
+pub fn exit() -> ! {
+    unsafe { ::x86_64::instructions::interrupts::disable() };
+    {
+        let mut scheduler = scheduler();
+        let current = scheduler.current;
+        let joiners = mem::replace(&mut scheduler.thread_mut(current).joiners, Vec::new());
+        scheduler.thread_mut(current).state = ThreadState::Exited;
+        for joiner in joiners {
+            scheduler.unblock(joiner);
+        }
+    }
+    schedule();
+    unreachable!("exited thread was scheduled again");
+}
+
+/// Wait for a thread to exit and release it
+pub fn join(id: ThreadId) {
+    loop {
+        let exited = without_interrupts(|| {
+            let mut scheduler = scheduler();
+            let current = scheduler.current;
+            match scheduler.threads.get_mut(&id) {
+                Some(thread) => if thread.state == ThreadState::Exited {
+                    true
+                } else {
+                    thread.joiners.push(current);
+                    false
+                },
+                None => true,
+            }
+        });
+        if exited {
+            break;
+        }
+        block_current();
+    }
+
+    let thread = without_interrupts(|| scheduler().threads.remove(&id));
+    if let Some(stack) = thread.and_then(|thread| thread.stack) {
+        memory::controller().free_stack(stack);
+    }
+}
+
+/// Count down the current thread's time slice, preempting it once the slice is used up. Called
+/// from the timer interrupt handler.
+pub fn tick() {
+    let preempt = match SCHEDULER.try() {
+        Some(scheduler) => {
+            let mut scheduler = scheduler.lock();
+            scheduler.remaining_ticks = scheduler.remaining_ticks.saturating_sub(1);
+            scheduler.remaining_ticks == 0
+        }
+        None => false,
+    };
+    if preempt {
+        schedule();
+    }
+}
@@ -0,0 +1,114 @@
+//! Kernel threads and their saved contexts
+use alloc::boxed::{Box, FnBox};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+use memory::{PhysicalAddress, Stack};
+
+/// CPU flags a new thread starts with: interrupts disabled, since it is entered from the scheduler
+const INITIAL_FLAGS: usize = 0x2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Unique identifier of a thread
+pub struct ThreadId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Scheduling state of a thread
+pub enum ThreadState {
+    /// Waiting in the run queue
+    Ready,
+    /// Currently running
+    Running,
+    /// Waiting to be unblocked
+    Blocked,
+    /// Finished, waiting to be joined
+    Exited,
+}
+
+/// A kernel thread
+pub struct Thread {
+    /// Thread id
+    id: ThreadId,
+    /// Scheduling state
+    pub state: ThreadState,
+    /// Stack, or None for the boot thread which runs on the stack from boot.asm
+    pub stack: Option<Stack>,
+    /// Stack pointer saved when the thread was switched away from
+    pub stack_pointer: usize,
+    /// Function to run when the thread first starts
+    pub entry: Option<Box<FnBox() + Send>>,
+    /// Whether the thread was unblocked before it got to block
+    pub wakeup_pending: bool,
+    /// Threads waiting for this thread to exit
+    pub joiners: Vec<ThreadId>,
+    /// Address space the thread runs in, as the physical address of its P4 table
+    pub address_space: PhysicalAddress,
+    /// Whether the thread is released as soon as it exits instead of being joined
+    pub detached: bool,
+}
+
+impl Thread {
+    /// Create the thread representing the flow of control that is already running
+    pub fn current(id: ThreadId, address_space: PhysicalAddress) -> Self {
+        Self {
+            id: id,
+            state: ThreadState::Running,
+            stack: None,
+            stack_pointer: 0,
+            entry: None,
+            wakeup_pending: false,
+            joiners: Vec::new(),
+            address_space: address_space,
+            detached: false,
+        }
+    }
+
+    /// Create a new thread that starts by calling trampoline on the given stack
+    pub fn new(
+        id: ThreadId,
+        stack: Stack,
+        entry: Box<FnBox() + Send>,
+        trampoline: extern "C" fn() -> !,
+        address_space: PhysicalAddress,
+    ) -> Self {
+        // Registers popped by switch_context: r15, r14, r13, r12, rbx, rbp, flags, then the
+        // return address. The zero above it stands in for the trampoline's own return address,
+        // keeping the stack aligned as if the trampoline had been called.
+        let initial_frame = [
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            INITIAL_FLAGS,
+            trampoline as usize,
+            0,
+        ];
+        let stack_pointer = stack.top() - initial_frame.len() * size_of::<usize>();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                initial_frame.as_ptr(),
+                stack_pointer as *mut usize,
+                initial_frame.len(),
+            );
+        }
+
+        Self {
+            id: id,
+            state: ThreadState::Ready,
+            stack: Some(stack),
+            stack_pointer: stack_pointer,
+            entry: Some(entry),
+            wakeup_pending: false,
+            joiners: Vec::new(),
+            address_space: address_space,
+            detached: false,
+        }
+    }
+
+    /// Get the thread id
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+}
@@ -0,0 +1,159 @@
+//! 16550 UART serial ports. The emergency console copies its output to the first one, whose
+//! input tasks can poll as a stream.
+use core::fmt;
+use interrupts::pic;
+use ring_buffer::RingBuffer;
+use sync::SpinLock;
+use task::{Poll, Stream, Waker, WakerSet};
+use x86_64::instructions::port::{inb, outb};
+
+/// I/O port base of the first serial port
+pub const COM1: u16 = 0x3f8;
+/// IRQ line of the first serial port
+pub const IRQ: u8 = 4;
+
+/// Register offset of the data register, or the low divisor byte while DLAB is set
+const DATA: u16 = 0;
+/// Register offset of the interrupt enable register, or the high divisor byte while DLAB is set
+const INTERRUPT_ENABLE: u16 = 1;
+/// Register offset of the FIFO control register
+const FIFO_CONTROL: u16 = 2;
+/// Register offset of the line control register
+const LINE_CONTROL: u16 = 3;
+/// Register offset of the modem control register
+const MODEM_CONTROL: u16 = 4;
+/// Register offset of the line status register
+const LINE_STATUS: u16 = 5;
+
+/// Line control bit making the first two registers access the baud rate divisor
+const DIVISOR_LATCH: u8 = 1 << 7;
+/// Line control value for 8 data bits, no parity and one stop bit
+const EIGHT_N_ONE: u8 = 0b11;
+/// Baud rate divisor for 38400 baud
+const DIVISOR: u16 = 3;
+/// FIFO control value enabling and clearing the FIFOs, with a 14 byte interrupt threshold
+const FIFO_ENABLE_CLEAR: u8 = 0xc7;
+/// Modem control value setting DTR, RTS and OUT2
+const MODEM_READY: u8 = 0x0b;
+/// Interrupt enable bit raising an interrupt when a byte arrives
+const RECEIVE_INTERRUPT: u8 = 1;
+/// Line status bit set while a received byte waits to be read
+const DATA_READY: u8 = 1;
+/// Line status bit set while the transmitter can take another byte
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+/// Number of line status reads before a byte is sent regardless, so that a missing port can't
+/// hang the caller
+const TRANSMIT_TIMEOUT: usize = 100_000;
+
+/// Bytes received on the first serial port but not read yet
+static INPUT: SpinLock<RingBuffer> = SpinLock::new("serial input", RingBuffer::new());
+/// Tasks waiting for a byte from the first serial port
+static WAITERS: WakerSet = WakerSet::new();
+
+#[derive(Debug, Clone, Copy)]
+/// A serial port. Holds no state besides its address, so any number of copies may write to the
+/// same port.
+pub struct SerialPort {
+    /// First I/O port of the UART
+    base: u16,
+}
+
+impl SerialPort {
+    /// Refer to the serial port at the given I/O port base
+    pub const fn new(base: u16) -> Self {
+        Self { base: base }
+    }
+
+    /// Set the port up for 38400 baud, 8N1, interrupting when a byte arrives
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    pub fn init(&self) {
+        unsafe {
+            outb(self.base + INTERRUPT_ENABLE, 0);
+            outb(self.base + LINE_CONTROL, DIVISOR_LATCH);
+            outb(self.base + DATA, DIVISOR as u8);
+            outb(self.base + INTERRUPT_ENABLE, (DIVISOR >> 8) as u8);
+            outb(self.base + LINE_CONTROL, EIGHT_N_ONE);
+            outb(self.base + FIFO_CONTROL, FIFO_ENABLE_CLEAR);
+            outb(self.base + MODEM_CONTROL, MODEM_READY);
+            outb(self.base + INTERRUPT_ENABLE, RECEIVE_INTERRUPT);
+        }
+    }
+
+    /// Read a received byte, if there is one
+    pub fn read_byte(&self) -> Option<u8> {
+        if unsafe { inb(self.base + LINE_STATUS) } & DATA_READY == 0 {
+            None
+        } else {
+            Some(unsafe { inb(self.base + DATA) })
+        }
+    }
+
+    /// Send a byte, waiting until the transmitter can take it
+    pub fn write_byte(&self, byte: u8) {
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if unsafe { inb(self.base + LINE_STATUS) } & TRANSMIT_EMPTY != 0 {
+                break;
+            }
+        }
+        unsafe { outb(self.base + DATA, byte) };
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect a carriage return before each line feed
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Stream of bytes received on the first serial port
+pub struct Input;
+
+impl Stream for Input {
+    type Item = u8;
+
+    fn poll_next(&mut self, waker: &Waker) -> Poll<Option<u8>> {
+        // Registering first means a byte arriving right after the check still wakes the task
+        WAITERS.register(waker);
+        match INPUT.lock().pop() {
+            Some(byte) => Poll::Ready(Some(byte)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Set up the first serial port. Its interrupts stay masked until enable_interrupts.
+pub fn init() {
+    SerialPort::new(COM1).init();
+}
+
+/// Unmask the interrupts of the first serial port, once the interrupt controllers are set up
+pub fn enable_interrupts() {
+    pic::unmask(IRQ);
+}
+
+/// Handle an interrupt of the first serial port, queueing the received bytes and waking the
+/// tasks waiting for them
+pub fn handle_interrupt() {
+    let port = SerialPort::new(COM1);
+    {
+        let mut input = INPUT.lock();
+        // The interrupt stays raised until every received byte is read. Bytes that don't fit
+        // are dropped.
+        while let Some(byte) = port.read_byte() {
+            input.push(byte);
+        }
+    }
+    WAITERS.wake_all();
+}
+
+/// Get a stream of the bytes received on the first serial port
+pub fn input() -> Input {
+    Input
+}
@@ -0,0 +1,209 @@
+//! Symmetric multiprocessing: starting the application processors listed in the MADT
+//!
+//! Application processors get descriptor tables, a local APIC and per-CPU data of their own,
+//! then wait for interrupts. Threads only run on the bootstrap processor.
+use acpi::{self, MadtEntry};
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use idle;
+use interrupts::{apic, pit};
+use memory::{self, EntryFlags, StackOwner, PAGE_SIZE};
+use scheduler;
+use x86_64::registers::msr::{rdmsr, IA32_EFER};
+
+pub mod percpu;
+pub mod tlb;
+
+/// Physical address the trampoline is copied to. Has to match TRAMPOLINE in ap_trampoline.asm.
+pub const TRAMPOLINE_ADDRESS: usize = 0x8000;
+/// Size of the memory reserved for the trampoline
+pub const TRAMPOLINE_SIZE: usize = PAGE_SIZE;
+/// Number of pages of the stack each application processor starts on
+const AP_STACK_PAGES: usize = 4;
+/// Timer ticks to wait after the INIT interrupt and the first startup interrupt, which has to
+/// be at least 10 ms
+const INIT_DELAY_TICKS: usize = 2;
+/// Timer ticks to wait for a processor to come online after a startup interrupt
+const STARTUP_TIMEOUT_TICKS: usize = pit::TICKS_PER_SECOND;
+/// EFER bit reporting that long mode is active, which the processor sets itself
+const EFER_LONG_MODE_ACTIVE: u64 = 1 << 10;
+/// Startup state of a processor that hasn't reached ap_main yet
+const AP_STARTING: usize = 0;
+/// Startup state of a processor that reached ap_main, and is done with the trampoline
+const AP_ENTERED: usize = 1;
+/// Startup state of a processor that took too long and was parked with INIT
+const AP_ABANDONED: usize = 2;
+
+extern "C" {
+    /// Start of the trampoline. Defined in ap_trampoline.asm.
+    static ap_trampoline_start: u8;
+    /// End of the trampoline. Defined in ap_trampoline.asm.
+    static ap_trampoline_end: u8;
+    /// Trampoline field holding the physical address of the P4 table
+    static ap_trampoline_page_table: u8;
+    /// Trampoline field holding the stack top
+    static ap_trampoline_stack: u8;
+    /// Trampoline field holding the entry point
+    static ap_trampoline_entry: u8;
+    /// Trampoline field holding the argument of the entry point
+    static ap_trampoline_argument: u8;
+    /// Trampoline field holding the EFER bits to set
+    static ap_trampoline_efer: u8;
+}
+
+/// Number of processors that are running, counting the bootstrap processor
+static ONLINE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Startup state of the processor being started. The trampoline fields and the trampoline
+/// itself are only reused once it has left AP_STARTING.
+static AP_STATE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Get the number of processors that are running
+pub fn online_count() -> usize {
+    ONLINE.load(Ordering::SeqCst).max(1)
+}
+
+/// Write a trampoline field in the copy of the trampoline
+fn set_trampoline_field(field: &u8, value: u64) {
+    unsafe {
+        let offset = field as *const u8 as usize - &ap_trampoline_start as *const u8 as usize;
+        ptr::write_volatile((TRAMPOLINE_ADDRESS + offset) as *mut u64, value);
+    }
+}
+
+/// Wait until ticks timer ticks have passed or condition holds, returning whether it held
+fn wait_ticks<F>(ticks: usize, condition: F) -> bool
+where
+    F: Fn() -> bool,
+{
+    let start = pit::ticks();
+    while pit::ticks() - start < ticks {
+        if condition() {
+            return true;
+        }
+        scheduler::yield_now();
+    }
+    condition()
+}
+
+/// Start an application processor through INIT-SIPI-SIPI, returning whether it came online
+fn start(id: usize, apic_id: u32) -> bool {
+    let stack = memory::controller()
+        .alloc_stack(AP_STACK_PAGES, StackOwner::Processor(id))
+        .expect("could not allocate processor stack");
+    unsafe {
+        set_trampoline_field(&ap_trampoline_stack, stack.top() as u64);
+        set_trampoline_field(&ap_trampoline_argument, id as u64);
+    }
+
+    AP_STATE.store(AP_STARTING, Ordering::SeqCst);
+    let online = ONLINE.load(Ordering::SeqCst);
+    let has_entered = || AP_STATE.load(Ordering::SeqCst) == AP_ENTERED;
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    let page_number = (TRAMPOLINE_ADDRESS / PAGE_SIZE) as u8;
+    apic::send_init(apic_id);
+    wait_ticks(INIT_DELAY_TICKS, || false);
+    // A second startup interrupt is only needed if the first one got lost
+    apic::send_startup(apic_id, page_number);
+    if !wait_ticks(INIT_DELAY_TICKS, &has_entered) {
+        apic::send_startup(apic_id, page_number);
+        wait_ticks(STARTUP_TIMEOUT_TICKS, &has_entered);
+    }
+
+    // A processor that is late may still be reading the trampoline fields, so it is parked
+    // before they are written for the next one
+    if AP_STATE
+        .compare_exchange(AP_STARTING, AP_ABANDONED, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        apic::send_init(apic_id);
+        wait_ticks(INIT_DELAY_TICKS, || false);
+        memory::controller().free_stack(stack);
+        return false;
+    }
+    wait_ticks(STARTUP_TIMEOUT_TICKS, || ONLINE.load(Ordering::SeqCst) > online)
+}
+
+/// Start every enabled processor listed in the MADT. Needs interrupts, the scheduler and the
+/// local APIC of the bootstrap processor.
+pub fn init() {
+    assert_has_not_been_called!("smp::init must be called only once");
+    tlb::join(|| {
+        ONLINE.store(1, Ordering::SeqCst);
+    });
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None => return,
+    };
+    let own_apic_id = apic::id();
+    let apic_ids: Vec<u32> = madt
+        .entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::LocalApic {
+                apic_id,
+                enabled: true,
+                ..
+            } => Some(u32::from(apic_id)),
+            // x2APIC ids from 255 on can't be addressed without switching to x2APIC mode
+            MadtEntry::LocalX2Apic {
+                x2apic_id,
+                enabled: true,
+                ..
+            } if x2apic_id < 0xff => Some(x2apic_id),
+            _ => None,
+        })
+        .filter(|&apic_id| apic_id != own_apic_id)
+        .collect();
+    if apic_ids.is_empty() {
+        return;
+    }
+
+    // The trampoline runs at the same address in real mode and once paging is enabled
+    let (trampoline, trampoline_size) = unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let end = &ap_trampoline_end as *const u8;
+        (start, end as usize - start as usize)
+    };
+    assert!(trampoline_size <= TRAMPOLINE_SIZE, "trampoline is too big");
+    let page_table = scheduler::kernel_address_space();
+    assert!(page_table < 1 << 32, "kernel page table must be below 4 GiB");
+    memory::controller().identity_map(TRAMPOLINE_ADDRESS, TRAMPOLINE_SIZE, EntryFlags::WRITABLE);
+    unsafe {
+        ptr::copy_nonoverlapping(trampoline, TRAMPOLINE_ADDRESS as *mut u8, trampoline_size);
+        set_trampoline_field(&ap_trampoline_page_table, page_table as u64);
+        set_trampoline_field(&ap_trampoline_entry, ap_main as u64);
+        // The other processors get the EFER bits of this one, so NXE only if cpu::init set it
+        let efer = rdmsr(IA32_EFER) & !EFER_LONG_MODE_ACTIVE;
+        set_trampoline_field(&ap_trampoline_efer, efer);
+    }
+
+    for (index, &apic_id) in apic_ids.iter().enumerate() {
+        if !start(index + 1, apic_id) {
+            println!("smp: processor with APIC id {} did not start", apic_id);
+        }
+    }
+    memory::controller().unmap_identity(TRAMPOLINE_ADDRESS, TRAMPOLINE_SIZE);
+    println!("smp: {} processors online", online_count());
+}
+
+/// First Rust code of an application processor, called by the trampoline on its own stack
+extern "C" fn ap_main(id: usize) -> ! {
+    // The bootstrap processor may have given up on this one and handed its stack and id on
+    if AP_STATE
+        .compare_exchange(AP_STARTING, AP_ENTERED, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        idle::halt_forever();
+    }
+    ::interrupts::init_ap(&mut memory::controller(), id);
+    let cpu = percpu::current();
+    println!("smp: processor {} (APIC id {}) online", cpu.id(), cpu.apic_id());
+    tlb::join(|| {
+        ONLINE.fetch_add(1, Ordering::SeqCst);
+    });
+
+    loop {
+        idle::idle();
+    }
+}
@@ -0,0 +1,133 @@
+//! Data private to each processor, found through its GS base
+//!
+//! User programs may load GS, which changes its base. IA32_KERNEL_GS_BASE keeps a copy of the
+//! pointer out of their reach, and every entry from user mode restores the GS base from it. Only
+//! syscall_entry runs swapgs, to reach the syscall fields before it can restore the GS base, and
+//! it puts the copy back right after. User programs can't rely on their GS base surviving an
+//! interrupt or system call.
+use alloc::boxed::Box;
+use core::sync::atomic::AtomicUsize;
+use interrupts::CpuTables;
+use smp::tlb;
+use sync::lockdep::HeldLocks;
+
+/// Value of PerCpu::current_thread before the scheduler runs on the processor
+pub const NO_THREAD: usize = !0;
+/// Model specific register holding the GS base
+const IA32_GS_BASE: u32 = 0xc000_0101;
+/// Model specific register holding the GS base swapgs switches to, which user mode can't change
+const IA32_KERNEL_GS_BASE: u32 = 0xc000_0102;
+
+#[repr(C)]
+/// Data of a processor, which lives until the machine stops
+pub struct PerCpu {
+    /// Address of the structure itself, so that gs:0 leads to it
+    this: usize,
+    /// Kernel stack syscall_entry switches to, updated when switching threads. Its offset has to
+    /// match PERCPU_SYSCALL_KERNEL_STACK in syscall.asm.
+    syscall_kernel_stack: AtomicUsize,
+    /// Scratch space for the user stack pointer while syscall_entry switches stacks. Its offset
+    /// has to match PERCPU_SYSCALL_USER_STACK in syscall.asm.
+    syscall_user_stack: usize,
+    /// Index of the processor, 0 for the bootstrap processor
+    id: usize,
+    /// Id of the processor's local APIC
+    apic_id: u32,
+    /// Descriptor tables of the processor
+    tables: CpuTables,
+    /// Last TLB shootdown the processor handled, or tlb::NOT_JOINED before it is online
+    tlb_generation: AtomicUsize,
+    /// Locks the processor holds, tracked by lockdep
+    held_locks: HeldLocks,
+    /// Id of the thread the processor runs, or NO_THREAD before the scheduler runs on it
+    current_thread: AtomicUsize,
+}
+
+impl PerCpu {
+    /// Get the kernel stack system calls of the running thread run on
+    pub fn syscall_kernel_stack(&self) -> &AtomicUsize {
+        &self.syscall_kernel_stack
+    }
+
+    /// Get the index of the processor, 0 for the bootstrap processor
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the id of the processor's local APIC
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id
+    }
+
+    /// Get the descriptor tables of the processor
+    pub fn tables(&self) -> &CpuTables {
+        &self.tables
+    }
+
+    /// Get the last TLB shootdown the processor handled
+    pub fn tlb_generation(&self) -> &AtomicUsize {
+        &self.tlb_generation
+    }
+
+    /// Get the locks the processor holds
+    pub fn held_locks(&self) -> &HeldLocks {
+        &self.held_locks
+    }
+
+    /// Get the id of the thread the processor runs
+    pub fn current_thread(&self) -> &AtomicUsize {
+        &self.current_thread
+    }
+}
+
+/// Create the data of the running processor and point its GS base, and the copy kept in
+/// IA32_KERNEL_GS_BASE, to it
+pub fn init(id: usize, apic_id: u32, tables: CpuTables) -> &'static PerCpu {
+    use x86_64::registers::msr::wrmsr;
+
+    let cpu = Box::into_raw(Box::new(PerCpu {
+        this: 0,
+        syscall_kernel_stack: AtomicUsize::new(0),
+        syscall_user_stack: 0,
+        id: id,
+        apic_id: apic_id,
+        tables: tables,
+        tlb_generation: AtomicUsize::new(tlb::NOT_JOINED),
+        held_locks: HeldLocks::new(),
+        current_thread: AtomicUsize::new(NO_THREAD),
+    }));
+    unsafe {
+        (*cpu).this = cpu as usize;
+        wrmsr(IA32_GS_BASE, cpu as u64);
+        wrmsr(IA32_KERNEL_GS_BASE, cpu as u64);
+        &*cpu
+    }
+}
+
+/// Point the GS base back to the data of the running processor, after user mode may have
+/// changed it
+pub fn restore_gs_base() {
+    use x86_64::registers::msr::{rdmsr, wrmsr};
+
+    unsafe { wrmsr(IA32_GS_BASE, rdmsr(IA32_KERNEL_GS_BASE)) };
+}
+
+/// Get the data of the running processor
+pub fn current() -> &'static PerCpu {
+    let cpu: usize;
+    unsafe {
+        asm!("mov %gs:0, $0" : "=r"(cpu) ::: "volatile");
+        &*(cpu as *const PerCpu)
+    }
+}
+
+/// Get the data of the running processor, or None if init hasn't been called on it yet
+pub fn try_current() -> Option<&'static PerCpu> {
+    use x86_64::registers::msr::rdmsr;
+
+    if unsafe { rdmsr(IA32_GS_BASE) } == 0 {
+        None
+    } else {
+        Some(current())
+    }
+}
@@ -0,0 +1,176 @@
+//! TLB shootdown: invalidating stale translations on every processor once a mapping changes
+//!
+//! Mapper records the pages it unmaps or remaps through invalidate, which flushes them from the
+//! local TLB right away. Other processors learn about them in batches: shootdown sends every
+//! other processor an interrupt with the pages recorded so far, and waits until each of them
+//! has flushed them. Dropping the memory controller's lock calls it, so frames freed by an
+//! unmap can't be reused while a stale translation to them remains. While a single processor
+//! runs, nothing is recorded and shootdown returns right away.
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use interrupts::apic;
+use spin::{Mutex, MutexGuard};
+use smp::{self, percpu};
+use x86_64::instructions::tlb;
+use x86_64::VirtualAddress;
+
+/// Interrupt vector of shootdown requests
+pub const VECTOR: u8 = 0xfd;
+/// Shootdown generation of processors that aren't online yet, which neither handle shootdowns
+/// nor get waited for
+pub const NOT_JOINED: usize = !0;
+/// Number of pages a batch names before it turns into a flush of the whole TLB
+const BATCH_SIZE: usize = 32;
+
+#[derive(Clone, Copy)]
+/// Pages whose translations have to be invalidated
+struct Batch {
+    /// Start addresses of the pages
+    addresses: [usize; BATCH_SIZE],
+    /// Number of addresses used
+    count: usize,
+    /// Whether more pages changed than addresses holds, so the whole TLB has to be flushed
+    overflowed: bool,
+}
+
+/// Pages changed since the last shootdown
+static PENDING: Mutex<Batch> = Mutex::new(Batch::new());
+/// Pages of the shootdown in progress
+static CURRENT: Mutex<Batch> = Mutex::new(Batch::new());
+/// Held by the processor shooting down, so that there is one shootdown at a time
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+/// Number of shootdowns started so far. Each processor remembers the last one it handled.
+static GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Number of processors that have yet to handle the shootdown in progress
+static REMAINING: AtomicUsize = ATOMIC_USIZE_INIT;
+
+impl Batch {
+    /// Create an empty batch
+    const fn new() -> Self {
+        Self {
+            addresses: [0; BATCH_SIZE],
+            count: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Check whether the batch names no pages
+    fn is_empty(&self) -> bool {
+        self.count == 0 && !self.overflowed
+    }
+
+    /// Add a page to the batch
+    fn push(&mut self, address: usize) {
+        if self.count < BATCH_SIZE {
+            self.addresses[self.count] = address;
+            self.count += 1;
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    /// Invalidate the pages of the batch in the local TLB
+    fn flush(&self) {
+        if self.overflowed {
+            tlb::flush_all();
+        } else {
+            for &address in &self.addresses[..self.count] {
+                tlb::flush(VirtualAddress(address));
+            }
+        }
+    }
+}
+
+/// Invalidate the translation of the page at address in the local TLB, and record it for the
+/// next shootdown if other processors are running
+pub fn invalidate(address: usize) {
+    tlb::flush(VirtualAddress(address));
+    if smp::online_count() > 1 {
+        PENDING.lock().push(address);
+    }
+}
+
+/// Invalidate the whole local TLB, and record a flush of the whole TLB for the next shootdown
+/// if other processors are running
+pub fn invalidate_all() {
+    tlb::flush_all();
+    if smp::online_count() > 1 {
+        PENDING.lock().overflowed = true;
+    }
+}
+
+/// Handle the shootdown in progress if the running processor hasn't yet
+fn handle_current() {
+    let cpu = percpu::current();
+    let generation = GENERATION.load(Ordering::SeqCst);
+    let handled = cpu.tlb_generation().load(Ordering::SeqCst);
+    if handled != NOT_JOINED && handled != generation {
+        CURRENT.lock().flush();
+        cpu.tlb_generation().store(generation, Ordering::SeqCst);
+        REMAINING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Take the shootdown lock. Shootdowns started meanwhile are handled while waiting, since their
+/// processor may be waiting on this one with interrupts disabled.
+fn lock() -> MutexGuard<'static, ()> {
+    loop {
+        if let Some(guard) = SHOOTDOWN.try_lock() {
+            return guard;
+        }
+        handle_current();
+    }
+}
+
+/// Make every other processor invalidate the pages recorded since the last shootdown, waiting
+/// until they have
+pub fn shootdown() {
+    if smp::online_count() == 1 {
+        return;
+    }
+    let _shootdown = lock();
+    let batch = {
+        let mut pending = PENDING.lock();
+        let batch = *pending;
+        *pending = Batch::new();
+        batch
+    };
+    if batch.is_empty() {
+        return;
+    }
+
+    *CURRENT.lock() = batch;
+    let cpu = percpu::current();
+    let joined = cpu.tlb_generation().load(Ordering::SeqCst) != NOT_JOINED;
+    let others = if joined {
+        smp::online_count() - 1
+    } else {
+        smp::online_count()
+    };
+    REMAINING.store(others, Ordering::SeqCst);
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    // The pages are already flushed locally
+    if joined {
+        cpu.tlb_generation().store(generation, Ordering::SeqCst);
+    }
+    apic::broadcast_ipi(VECTOR);
+    while REMAINING.load(Ordering::SeqCst) != 0 {}
+}
+
+/// Handle a shootdown interrupt
+pub fn handle_interrupt() {
+    handle_current();
+    apic::end_of_interrupt();
+}
+
+/// Run f, which brings the running processor online, so that it neither misses nor gets
+/// counted for a shootdown in progress
+pub fn join<F>(f: F)
+where
+    F: FnOnce(),
+{
+    let _shootdown = lock();
+    percpu::current()
+        .tlb_generation()
+        .store(GENERATION.load(Ordering::SeqCst), Ordering::SeqCst);
+    f();
+}
@@ -0,0 +1,36 @@
+//! Condition variables, which threads holding a Mutex sleep on until notified
+use scheduler;
+use super::{MutexGuard, WaitQueue};
+
+/// Condition variable. Like any condition variable, waits may end without a notification, so
+/// callers check their condition again after each one.
+pub struct Condvar {
+    /// Threads waiting for a notification
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    /// Create a condition variable without waiters
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release the mutex guard locks and sleep until notified, then lock the mutex again
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // Registering while the mutex is held means no notification after the release is missed
+        self.waiters.register();
+        drop(guard);
+        scheduler::block_current();
+        self.waiters.deregister();
+        mutex.lock()
+    }
+
+    /// Wake the thread that has been waiting longest
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+}
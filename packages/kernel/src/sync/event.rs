@@ -0,0 +1,40 @@
+//! One-shot events
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::WaitQueue;
+
+/// Event that happens once. Threads wait for it, and threads or interrupt handlers set it,
+/// after which it stays set.
+pub struct Event {
+    /// Whether the event happened
+    set: AtomicBool,
+    /// Threads waiting for the event
+    waiters: WaitQueue,
+}
+
+impl Event {
+    /// Create an event that hasn't happened yet
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Sleep until the event is set. Returns right away if it already is.
+    pub fn wait(&self) {
+        self.waiters.wait_until(|| {
+            if self.set.load(Ordering::Acquire) {
+                Some(())
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Set the event, waking every waiting thread
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
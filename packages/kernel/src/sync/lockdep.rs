@@ -0,0 +1,138 @@
+//! Lock debugging, active in debug builds
+//!
+//! Each processor tracks the locks it holds. Taking a lock the processor already holds is
+//! reported, as is taking two locks in the opposite order of an earlier acquisition, which
+//! deadlocks once two processors do it at the same time. Only pairs of locks are compared, and
+//! orders are forgotten once MAX_ORDERS of them are known. The first report, a panic, turns the
+//! checks off so that the panic message can be printed. Processors are only tracked once they
+//! have per-CPU data.
+//!
+//! The functions here must be called with interrupts disabled.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use smp::percpu;
+use spin::Mutex;
+
+/// Number of locks tracked per processor. Locks taken beyond that aren't checked.
+const MAX_HELD: usize = 16;
+/// Number of lock orders remembered
+const MAX_ORDERS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Identity of a lock
+pub struct LockId {
+    /// Address of the lock
+    address: usize,
+    /// Name of the lock, for reports
+    name: &'static str,
+}
+
+#[derive(Clone, Copy)]
+/// Two locks that have been held at the same time
+struct LockOrder {
+    /// Lock taken first
+    first: LockId,
+    /// Lock taken while first was held
+    second: LockId,
+}
+
+/// Locks held by a processor. Only touched by that processor with interrupts disabled.
+pub struct HeldLocks {
+    /// The locks, in any order
+    locks: UnsafeCell<[Option<LockId>; MAX_HELD]>,
+}
+
+/// Orders locks have been taken in
+static ORDERS: Mutex<[Option<LockOrder>; MAX_ORDERS]> = Mutex::new([None; MAX_ORDERS]);
+/// Set once a problem has been reported
+static REPORTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+impl LockId {
+    /// Identify the lock at the given address
+    pub fn new<T>(lock: &T, name: &'static str) -> Self {
+        Self {
+            address: lock as *const T as usize,
+            name: name,
+        }
+    }
+}
+
+impl HeldLocks {
+    /// Create an empty set of locks
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        Self {
+            locks: UnsafeCell::new([None; MAX_HELD]),
+        }
+    }
+}
+
+/// Get the locks held by the running processor, if it is checked
+fn held_locks() -> Option<&'static mut [Option<LockId>; MAX_HELD]> {
+    if !cfg!(debug_assertions) || REPORTED.load(Ordering::SeqCst) {
+        return None;
+    }
+    let cpu = percpu::try_current()?;
+    Some(unsafe { &mut *cpu.held_locks().locks.get() })
+}
+
+/// Report a locking problem
+fn report(message: &str, lock: LockId, other: LockId) -> ! {
+    REPORTED.store(true, Ordering::SeqCst);
+    panic!(
+        "lockdep: {} {} ({:#x}) while holding {} ({:#x})",
+        message, lock.name, lock.address, other.name, other.address
+    );
+}
+
+/// Check that the running processor may wait for the lock, and remember the order it is taken
+/// in relative to the locks already held
+pub fn acquire(lock: LockId) {
+    let held = match held_locks() {
+        Some(held) => held,
+        None => return,
+    };
+    let mut orders = ORDERS.lock();
+    for &other in held.iter().filter_map(|other| other.as_ref()) {
+        if other == lock {
+            report("recursive acquisition of", lock, other);
+        }
+        if orders
+            .iter()
+            .filter_map(|order| order.as_ref())
+            .any(|order| order.first == lock && order.second == other)
+        {
+            report("lock order inversion: taking", lock, other);
+        }
+        let known = orders
+            .iter()
+            .filter_map(|order| order.as_ref())
+            .any(|order| order.first == other && order.second == lock);
+        if !known {
+            if let Some(slot) = orders.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(LockOrder {
+                    first: other,
+                    second: lock,
+                });
+            }
+        }
+    }
+}
+
+/// Remember that the running processor holds the lock
+pub fn acquired(lock: LockId) {
+    if let Some(held) = held_locks() {
+        if let Some(slot) = held.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(lock);
+        }
+    }
+}
+
+/// Remember that the running processor released the lock
+pub fn released(lock: LockId) {
+    if let Some(held) = held_locks() {
+        if let Some(slot) = held.iter_mut().find(|slot| **slot == Some(lock)) {
+            *slot = None;
+        }
+    }
+}
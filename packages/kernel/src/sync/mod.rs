@@ -0,0 +1,68 @@
+//! Synchronization: spinning locks for interrupt handlers and blocking primitives for threads
+//!
+//! Every spinning lock here disables interrupts on the running processor while it is held, so
+//! an interrupt handler can't spin forever on a lock the code it interrupted holds. The previous
+//! interrupt state comes back when the guard is dropped, so guards have to be dropped in the
+//! reverse order of locking. Debug builds check every acquisition with lockdep.
+//!
+//! The blocking primitives put waiting threads to sleep on a WaitQueue. Only threads can wait,
+//! but interrupt handlers can wake them through Semaphore::release, Event::set and the
+//! notifications of Condvar.
+use core::sync::atomic::spin_loop_hint;
+use x86_64::instructions::interrupts as cpu_interrupts;
+use x86_64::registers::flags::{flags, Flags};
+
+pub use self::condvar::Condvar;
+pub use self::event::Event;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::Semaphore;
+pub use self::spinlock::{SpinLock, SpinLockGuard};
+pub use self::ticket::{TicketLock, TicketLockGuard};
+pub use self::wait_queue::WaitQueue;
+
+mod condvar;
+mod event;
+pub mod lockdep;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod spinlock;
+mod ticket;
+mod wait_queue;
+
+/// Interrupt state of the running processor from before a lock disabled interrupts
+pub struct InterruptGuard {
+    /// Whether interrupts were enabled
+    enabled: bool,
+}
+
+impl InterruptGuard {
+    /// Disable interrupts, remembering whether they were enabled
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        let enabled = flags().contains(Flags::IF);
+        if enabled {
+            unsafe { cpu_interrupts::disable() };
+        }
+        Self { enabled: enabled }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.enabled {
+            unsafe { cpu_interrupts::enable() };
+        }
+    }
+}
+
+/// Spin until condition holds
+fn spin_until<F>(condition: F)
+where
+    F: Fn() -> bool,
+{
+    while !condition() {
+        spin_loop_hint();
+    }
+}
@@ -0,0 +1,80 @@
+//! Mutex that puts waiting threads to sleep instead of spinning
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::WaitQueue;
+
+/// Mutual exclusion lock for data used by threads only. Waiting threads sleep, so it may be
+/// held for long operations, but it can't be taken in interrupt handlers. Since its holder may
+/// be switched out, lockdep doesn't track it.
+pub struct Mutex<T> {
+    /// Whether a thread holds the lock
+    locked: AtomicBool,
+    /// Threads waiting for the lock
+    waiters: WaitQueue,
+    /// The protected data
+    data: UnsafeCell<T>,
+}
+
+/// Access to the data of a locked Mutex, which is released when this is dropped
+pub struct MutexGuard<'a, T: 'a> {
+    /// The mutex
+    mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Create a mutex around data
+    pub fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Lock, sleeping while another thread holds the lock
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.waiters.wait_until(|| {
+            if self.locked
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                Some(())
+            } else {
+                None
+            }
+        });
+        MutexGuard { mutex: self }
+    }
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Get the mutex this guard locks
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.wake_one();
+    }
+}
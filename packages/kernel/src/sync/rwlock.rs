@@ -0,0 +1,148 @@
+//! Reader-writer lock, held by any number of readers or a single writer
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
+use super::lockdep::{self, LockId};
+use super::InterruptGuard;
+
+/// State bit set while a writer holds the lock
+const WRITER: usize = 1;
+/// State bit set while a writer waits, which keeps new readers out
+const WRITER_WAITING: usize = 1 << 1;
+/// State increment of each reader
+const READER: usize = 1 << 2;
+
+/// Lock shared by readers or held by one writer. Waiting writers go before new readers, so a
+/// processor already reading must not take the read lock again.
+pub struct RwLock<T> {
+    /// Name of the lock, for lockdep reports
+    name: &'static str,
+    /// Number of readers times READER, plus the WRITER and WRITER_WAITING bits
+    state: AtomicUsize,
+    /// The protected data
+    data: UnsafeCell<T>,
+}
+
+/// Shared access to the data of a RwLock, which is released when this is dropped
+pub struct RwLockReadGuard<'a, T: 'a> {
+    /// The lock
+    lock: &'a RwLock<T>,
+    /// Interrupt state to restore after the lock is released
+    _interrupts: InterruptGuard,
+}
+
+/// Exclusive access to the data of a RwLock, which is released when this is dropped
+pub struct RwLockWriteGuard<'a, T: 'a> {
+    /// The lock
+    lock: &'a RwLock<T>,
+    /// Interrupt state to restore after the lock is released
+    _interrupts: InterruptGuard,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Create a lock around data, named for lockdep reports
+    pub const fn new(name: &'static str, data: T) -> Self {
+        Self {
+            name: name,
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Get the identity of the lock for lockdep
+    fn id(&self) -> LockId {
+        LockId::new(self, self.name)
+    }
+
+    /// Lock for reading, waiting while a writer holds or waits for the lock
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let interrupts = InterruptGuard::new();
+        lockdep::acquire(self.id());
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | WRITER_WAITING) == 0
+                && self.state
+                    .compare_exchange_weak(
+                        state,
+                        state + READER,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                break;
+            }
+            spin_loop_hint();
+        }
+        lockdep::acquired(self.id());
+        RwLockReadGuard {
+            lock: self,
+            _interrupts: interrupts,
+        }
+    }
+
+    /// Lock for writing, waiting until no reader or writer holds the lock
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let interrupts = InterruptGuard::new();
+        lockdep::acquire(self.id());
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WRITER_WAITING == 0 {
+                // Other waiting writers set WRITER_WAITING again on their next attempt
+                if self.state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            spin_loop_hint();
+        }
+        lockdep::acquired(self.id());
+        RwLockWriteGuard {
+            lock: self,
+            _interrupts: interrupts,
+        }
+    }
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::released(self.lock.id());
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::released(self.lock.id());
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
@@ -0,0 +1,56 @@
+//! Counting semaphores
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::WaitQueue;
+
+/// Counter of available permits. Threads taking a permit sleep until one is available, and
+/// interrupt handlers may return permits.
+pub struct Semaphore {
+    /// Number of available permits
+    permits: AtomicUsize,
+    /// Threads waiting for a permit
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    /// Create a semaphore with the given number of permits
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take a permit, sleeping until one is available
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| {
+            if self.try_acquire() {
+                Some(())
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Take a permit if one is available, returning whether it was
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    /// Return a permit, waking a waiting thread
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
@@ -0,0 +1,85 @@
+//! Spin lock that keeps interrupts disabled while held
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use super::lockdep::{self, LockId};
+use super::InterruptGuard;
+
+/// Mutual exclusion lock spinning until the lock is free
+pub struct SpinLock<T> {
+    /// Name of the lock, for lockdep reports
+    name: &'static str,
+    /// The lock itself
+    inner: Mutex<T>,
+}
+
+/// Access to the data of a locked SpinLock, which is released when this is dropped
+pub struct SpinLockGuard<'a, T: 'a> {
+    /// Identity of the lock
+    id: LockId,
+    /// Guard of the lock itself
+    guard: MutexGuard<'a, T>,
+    /// Interrupt state to restore after the lock is released
+    _interrupts: InterruptGuard,
+}
+
+impl<T> SpinLock<T> {
+    /// Create a lock around data, named for lockdep reports
+    pub const fn new(name: &'static str, data: T) -> Self {
+        Self {
+            name: name,
+            inner: Mutex::new(data),
+        }
+    }
+
+    /// Get the identity of the lock for lockdep
+    fn id(&self) -> LockId {
+        LockId::new(self, self.name)
+    }
+
+    /// Lock, spinning until the lock is free
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        let interrupts = InterruptGuard::new();
+        let id = self.id();
+        lockdep::acquire(id);
+        let guard = self.inner.lock();
+        lockdep::acquired(id);
+        SpinLockGuard {
+            id: id,
+            guard: guard,
+            _interrupts: interrupts,
+        }
+    }
+
+    /// Lock if the lock is free
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        let interrupts = InterruptGuard::new();
+        let id = self.id();
+        let guard = self.inner.try_lock()?;
+        lockdep::acquired(id);
+        Some(SpinLockGuard {
+            id: id,
+            guard: guard,
+            _interrupts: interrupts,
+        })
+    }
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, T> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::released(self.id);
+    }
+}
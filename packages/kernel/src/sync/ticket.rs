@@ -0,0 +1,81 @@
+//! Ticket lock, which is taken in the order processors started waiting for it
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::lockdep::{self, LockId};
+use super::{spin_until, InterruptGuard};
+
+/// Mutual exclusion lock handing itself to waiters first come, first served, so that no
+/// processor waits forever while others keep taking the lock
+pub struct TicketLock<T> {
+    /// Name of the lock, for lockdep reports
+    name: &'static str,
+    /// Ticket the next waiter draws
+    next_ticket: AtomicUsize,
+    /// Ticket of the holder
+    now_serving: AtomicUsize,
+    /// The protected data
+    data: UnsafeCell<T>,
+}
+
+/// Access to the data of a locked TicketLock, which is released when this is dropped
+pub struct TicketLockGuard<'a, T: 'a> {
+    /// The lock
+    lock: &'a TicketLock<T>,
+    /// Interrupt state to restore after the lock is released
+    _interrupts: InterruptGuard,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+unsafe impl<T: Send> Send for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    /// Create a lock around data, named for lockdep reports
+    pub const fn new(name: &'static str, data: T) -> Self {
+        Self {
+            name: name,
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Get the identity of the lock for lockdep
+    fn id(&self) -> LockId {
+        LockId::new(self, self.name)
+    }
+
+    /// Lock, waiting until every processor that started waiting earlier had its turn
+    pub fn lock(&self) -> TicketLockGuard<T> {
+        let interrupts = InterruptGuard::new();
+        lockdep::acquire(self.id());
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        spin_until(|| self.now_serving.load(Ordering::Acquire) == ticket);
+        lockdep::acquired(self.id());
+        TicketLockGuard {
+            lock: self,
+            _interrupts: interrupts,
+        }
+    }
+}
+
+impl<'a, T> Deref for TicketLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for TicketLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for TicketLockGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::released(self.lock.id());
+        self.lock.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
@@ -0,0 +1,83 @@
+//! Wait queues, which threads sleep on until another thread or an interrupt handler wakes them
+use alloc::vec_deque::VecDeque;
+use scheduler::{self, ThreadId};
+use super::SpinLock;
+
+/// Threads sleeping until something happens. Waking is allowed from interrupt handlers; waiting
+/// only from threads.
+pub struct WaitQueue {
+    /// Sleeping threads, in the order they started waiting
+    waiters: SpinLock<VecDeque<ThreadId>>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        Self {
+            waiters: SpinLock::new("wait queue", VecDeque::new()),
+        }
+    }
+
+    /// Add the running thread to the queue. It has to call scheduler::block_current next, or
+    /// deregister if it doesn't sleep after all.
+    pub fn register(&self) {
+        let current = scheduler::current();
+        self.waiters.lock().push_back(current);
+    }
+
+    /// Remove the running thread from the queue, returning whether it was still there, that is
+    /// whether it hasn't been woken
+    pub fn deregister(&self) -> bool {
+        let current = scheduler::current();
+        let mut waiters = self.waiters.lock();
+        match waiters.iter().position(|&id| id == current) {
+            Some(index) => {
+                waiters.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sleep until condition returns a value, checking it again each time the thread is woken
+    pub fn wait_until<F, R>(&self, mut condition: F) -> R
+    where
+        F: FnMut() -> Option<R>,
+    {
+        loop {
+            if let Some(result) = condition() {
+                return result;
+            }
+            // Registering before checking again means no wakeup between the check and the
+            // block can be missed, since unblock makes the block return right away
+            self.register();
+            if let Some(result) = condition() {
+                // A wakeup meant for a waiting thread must not be swallowed
+                if !self.deregister() {
+                    self.wake_one();
+                }
+                return result;
+            }
+            scheduler::block_current();
+            self.deregister();
+        }
+    }
+
+    /// Wake the thread that has been waiting longest, returning whether there was one
+    pub fn wake_one(&self) -> bool {
+        let next = self.waiters.lock().pop_front();
+        match next {
+            Some(id) => {
+                scheduler::unblock(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake every waiting thread
+    pub fn wake_all(&self) {
+        while self.wake_one() {}
+    }
+}
@@ -0,0 +1,427 @@
+//! System calls, entered from user mode with the syscall instruction or int 0x80
+//!
+//! The call number goes in rax and up to six arguments in rdi, rsi, rdx, r10, r8 and r9. The
+//! result comes back in rax, with errors returned as negative numbers.
+use alloc::arc::Arc;
+use core::{mem, ptr, slice, str};
+use core::sync::atomic::Ordering;
+use fs;
+use interrupts::{self, pit};
+use keyboard;
+use memory::{self, EntryFlags, PAGE_SIZE};
+use power;
+use process::{self, Descriptor, Pid};
+use scheduler;
+use smp;
+use sync::Mutex;
+
+/// Interrupt vector of the int 0x80 system call fallback
+pub const INTERRUPT_VECTOR: u8 = 0x80;
+
+/// CPU flags cleared on syscall: interrupts, trap and direction
+const SYSCALL_FLAG_MASK: u64 = 0x700;
+/// Process allowed to reboot the machine: init, the first process started
+const INIT_PID: Pid = Pid(1);
+
+extern "C" {
+    /// Target of the syscall instruction. Defined in syscall.asm.
+    fn syscall_entry();
+    /// Handler of int 0x80. Defined in syscall.asm.
+    pub fn syscall_interrupt_entry();
+}
+
+#[derive(Debug, Clone)]
+#[repr(C)]
+/// User registers saved on entry to a system call, in the order syscall.asm pushes them
+pub struct SyscallFrame {
+    /// Call number, and the result on return
+    pub rax: usize,
+    /// First argument
+    pub rdi: usize,
+    /// Second argument
+    pub rsi: usize,
+    /// Third argument
+    pub rdx: usize,
+    /// Fourth argument
+    pub r10: usize,
+    /// Fifth argument
+    pub r8: usize,
+    /// Sixth argument
+    pub r9: usize,
+    /// Callee-saved register
+    pub rbx: usize,
+    /// Callee-saved register
+    pub rbp: usize,
+    /// Callee-saved register
+    pub r12: usize,
+    /// Callee-saved register
+    pub r13: usize,
+    /// Callee-saved register
+    pub r14: usize,
+    /// Callee-saved register
+    pub r15: usize,
+    /// User CPU flags
+    pub rflags: usize,
+    /// User instruction pointer to return to
+    pub rip: usize,
+    /// User stack pointer
+    pub rsp: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons a system call can fail
+pub enum Error {
+    /// There is no system call with the given number
+    NoSuchCall,
+    /// A pointer argument does not point to accessible user memory
+    BadAddress,
+    /// A file descriptor argument is not open
+    BadFileDescriptor,
+    /// Not enough memory to complete the call
+    OutOfMemory,
+    /// An argument is out of range
+    InvalidArgument,
+    /// The calling process has no children to wait for
+    NoChildren,
+    /// A program could not be loaded
+    NotExecutable,
+    /// The calling process may not make the call
+    PermissionDenied,
+    /// A filesystem operation failed
+    Fs(fs::Error),
+}
+
+/// Result of a system call
+pub type Result = ::core::result::Result<usize, Error>;
+
+/// Signature of system call implementations
+type Handler = fn(&mut SyscallFrame) -> Result;
+
+/// System calls, indexed by call number
+static SYSCALLS: [Handler; 16] = [
+    sys_exit,    // 0
+    sys_write,   // 1
+    sys_yield,   // 2
+    sys_mmap,    // 3
+    sys_time,    // 4
+    sys_getpid,  // 5
+    sys_getppid, // 6
+    sys_open,    // 7
+    sys_close,   // 8
+    sys_read,    // 9
+    sys_wait,    // 10
+    sys_spawn,   // 11
+    sys_fork,    // 12
+    sys_exec,    // 13
+    sys_waitpid, // 14
+    sys_reboot,  // 15
+];
+
+/// mmap protection flag: pages may be written
+pub const PROT_WRITE: usize = 1 << 0;
+/// mmap protection flag: pages may be executed
+pub const PROT_EXEC: usize = 1 << 1;
+
+/// open flag: create the file if it doesn't exist, and truncate it if it does
+pub const O_CREATE: usize = 1 << 0;
+
+/// reboot command: restart the machine
+pub const REBOOT_RESTART: usize = 1;
+/// reboot command: power the machine off
+pub const REBOOT_POWER_OFF: usize = 2;
+
+impl Error {
+    /// Get the negative number returned to user mode for the error
+    fn code(self) -> isize {
+        match self {
+            Error::NoSuchCall => -38,
+            Error::BadAddress => -14,
+            Error::BadFileDescriptor => -9,
+            Error::OutOfMemory => -12,
+            Error::InvalidArgument => -22,
+            Error::NoChildren => -10,
+            Error::NotExecutable => -8,
+            Error::PermissionDenied => -1,
+            Error::Fs(error) => match error {
+                fs::Error::NotFound => -2,
+                fs::Error::NotADirectory => -20,
+                fs::Error::IsADirectory => -21,
+                fs::Error::ReadOnly => -30,
+                fs::Error::AlreadyExists => -17,
+                fs::Error::NotEmpty => -39,
+                fs::Error::CrossDevice => -18,
+                fs::Error::NoSpace => -28,
+                fs::Error::InvalidPath => -22,
+                fs::Error::Corrupt => -5,
+            },
+        }
+    }
+}
+
+impl From<fs::Error> for Error {
+    fn from(error: fs::Error) -> Self {
+        Error::Fs(error)
+    }
+}
+
+impl From<process::Error> for Error {
+    fn from(error: process::Error) -> Self {
+        match error {
+            process::Error::Fs(error) => Error::Fs(error),
+            process::Error::Load(_) => Error::NotExecutable,
+            process::Error::NoChildren => Error::NoChildren,
+        }
+    }
+}
+
+/// Point the syscall instruction at syscall_entry. Requires the GDT from interrupts::init.
+pub fn init() {
+    use x86_64::registers::msr::{wrmsr, IA32_FMASK, IA32_LSTAR, IA32_STAR};
+
+    let selectors = interrupts::selectors();
+    // syscall loads cs from the kernel base and ss from the entry after it. sysret loads ss from
+    // the entry after the user base and cs from the one after that.
+    let kernel_base = u64::from(selectors.kernel_code.0);
+    let user_base = u64::from(selectors.user_data.0) - 8;
+    unsafe {
+        wrmsr(IA32_STAR, (user_base << 48) | (kernel_base << 32));
+        wrmsr(IA32_LSTAR, syscall_entry as u64);
+        wrmsr(IA32_FMASK, SYSCALL_FLAG_MASK);
+    }
+}
+
+/// Set the stack system calls of the running thread run on
+pub fn set_kernel_stack(stack_top: usize) {
+    smp::percpu::current()
+        .syscall_kernel_stack()
+        .store(stack_top, Ordering::SeqCst);
+}
+
+/// Run the system call described by frame, storing the result in its rax. Called from
+/// syscall.asm with interrupts disabled.
+#[no_mangle]
+#[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+pub extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    use x86_64::instructions::interrupts as cpu_interrupts;
+
+    // System calls only come from user mode, which may have changed the GS base. syscall_entry
+    // restores it itself, but int 0x80 doesn't go through it.
+    smp::percpu::restore_gs_base();
+    // The user registers are safe on the thread's stack now, so the call may be preempted
+    unsafe { cpu_interrupts::enable() };
+    let result = match SYSCALLS.get(frame.rax) {
+        Some(handler) => handler(frame),
+        None => Err(Error::NoSuchCall),
+    };
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(error) => error.code() as usize,
+    };
+    unsafe { cpu_interrupts::disable() };
+}
+
+/// Get a user buffer as a slice, checking that user mode may access it
+fn user_slice<'a>(address: usize, length: usize) -> ::core::result::Result<&'a [u8], Error> {
+    if !memory::controller().check_user_range(address, length, false) {
+        return Err(Error::BadAddress);
+    }
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length) })
+}
+
+/// Get a user buffer as a mutable slice, checking that user mode may write it
+fn user_slice_mut<'a>(
+    address: usize,
+    length: usize,
+) -> ::core::result::Result<&'a mut [u8], Error> {
+    if !memory::controller().check_user_range(address, length, true) {
+        return Err(Error::BadAddress);
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, length) })
+}
+
+/// Get a UTF-8 string passed by user mode as address and length
+fn user_str<'a>(address: usize, length: usize) -> ::core::result::Result<&'a str, Error> {
+    str::from_utf8(user_slice(address, length)?).map_err(|_| Error::InvalidArgument)
+}
+
+/// Get what a file descriptor of the calling process refers to
+fn descriptor(fd: usize) -> ::core::result::Result<Descriptor, Error> {
+    process::descriptor(fd).ok_or(Error::BadFileDescriptor)
+}
+
+/// exit(code): finish the calling process, or the calling thread if it has no process
+fn sys_exit(frame: &mut SyscallFrame) -> Result {
+    if process::current().is_some() {
+        process::exit(frame.rdi)
+    }
+    println!("thread {} exited with code {}", scheduler::current().0, frame.rdi);
+    scheduler::exit()
+}
+
+/// write(fd, buffer, length): write to an open file, or UTF-8 text to the console
+fn sys_write(frame: &mut SyscallFrame) -> Result {
+    let buffer = user_slice(frame.rsi, frame.rdx)?;
+    match descriptor(frame.rdi)? {
+        Descriptor::Console => {
+            let text = str::from_utf8(buffer).map_err(|_| Error::InvalidArgument)?;
+            print!("{}", text);
+            Ok(buffer.len())
+        }
+        Descriptor::File(file) => Ok(file.lock().write(buffer)?),
+    }
+}
+
+/// read(fd, buffer, length): read from an open file, returning 0 at its end. Reading the
+/// console waits until a key is typed.
+fn sys_read(frame: &mut SyscallFrame) -> Result {
+    let buffer = user_slice_mut(frame.rsi, frame.rdx)?;
+    match descriptor(frame.rdi)? {
+        Descriptor::Console => Ok(keyboard::read(buffer)),
+        Descriptor::File(file) => Ok(file.lock().read(buffer)?),
+    }
+}
+
+/// open(path, path_length, flags): open the file at an absolute path, returning the lowest free
+/// file descriptor. With O_CREATE the file is created or truncated.
+fn sys_open(frame: &mut SyscallFrame) -> Result {
+    let path = user_str(frame.rdi, frame.rsi)?;
+    let flags = frame.rdx;
+    if flags & !O_CREATE != 0 || process::current().is_none() {
+        return Err(Error::InvalidArgument);
+    }
+    let file = if flags & O_CREATE == 0 {
+        fs::open(path)?
+    } else {
+        fs::create(path)?
+    };
+    process::add_descriptor(Descriptor::File(Arc::new(Mutex::new(file))))
+        .ok_or(Error::OutOfMemory)
+}
+
+/// close(fd): close a file descriptor
+fn sys_close(frame: &mut SyscallFrame) -> Result {
+    if process::close_descriptor(frame.rdi) {
+        Ok(0)
+    } else {
+        Err(Error::BadFileDescriptor)
+    }
+}
+
+/// getpid(): id of the calling process, or 0 for kernel threads
+fn sys_getpid(_frame: &mut SyscallFrame) -> Result {
+    Ok(process::current().map_or(0, |pid| pid.0))
+}
+
+/// getppid(): id of the parent of the calling process, or 0 if it has none
+fn sys_getppid(_frame: &mut SyscallFrame) -> Result {
+    Ok(process::parent().map_or(0, |pid| pid.0))
+}
+
+/// spawn(path, path_length): start the program at an absolute path as a child process,
+/// returning its id
+fn sys_spawn(frame: &mut SyscallFrame) -> Result {
+    let path = user_str(frame.rdi, frame.rsi)?;
+    if process::current().is_none() {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(process::spawn_child(path)?.0)
+}
+
+/// Wait for a child process to exit, or any child if pid is None, storing its exit code at the
+/// user address status unless it is 0, and returning its id
+fn wait_child(pid: Option<Pid>, status: usize) -> Result {
+    if process::current().is_none() {
+        return Err(Error::NoChildren);
+    }
+    if status != 0 {
+        user_slice_mut(status, mem::size_of::<usize>())?;
+    }
+    let (pid, code) = process::wait(pid)?;
+    if status != 0 {
+        unsafe { ptr::write_unaligned(status as *mut usize, code) };
+    }
+    Ok(pid.0)
+}
+
+/// wait(status): wait for any child process to exit, storing its exit code at status unless it
+/// is 0, and returning its id
+fn sys_wait(frame: &mut SyscallFrame) -> Result {
+    wait_child(None, frame.rdi)
+}
+
+/// waitpid(pid, status): wait for the child process pid to exit, or any child if pid is 0,
+/// storing its exit code at status unless it is 0, and returning its id
+fn sys_waitpid(frame: &mut SyscallFrame) -> Result {
+    let pid = match frame.rdi {
+        0 => None,
+        pid => Some(Pid(pid)),
+    };
+    wait_child(pid, frame.rsi)
+}
+
+/// fork(): duplicate the calling process, returning the child's id in the parent and 0 in the
+/// child
+fn sys_fork(frame: &mut SyscallFrame) -> Result {
+    if process::current().is_none() {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(process::fork(frame).0)
+}
+
+/// exec(path, path_length): replace the program of the calling process with the one at an
+/// absolute path. Only returns on failure.
+fn sys_exec(frame: &mut SyscallFrame) -> Result {
+    let path = user_str(frame.rdi, frame.rsi)?;
+    if process::current().is_none() {
+        return Err(Error::InvalidArgument);
+    }
+    Err(process::exec(path).into())
+}
+
+/// reboot(command): restart or power off the machine. Only init may call it. Only returns on
+/// failure.
+fn sys_reboot(frame: &mut SyscallFrame) -> Result {
+    if process::current() != Some(INIT_PID) {
+        return Err(Error::PermissionDenied);
+    }
+    match frame.rdi {
+        REBOOT_RESTART => power::reboot(),
+        REBOOT_POWER_OFF => power::shutdown(),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// yield(): let other threads run
+fn sys_yield(_frame: &mut SyscallFrame) -> Result {
+    scheduler::yield_now();
+    Ok(0)
+}
+
+/// mmap(length, protection): reserve zeroed memory, backed on first access
+fn sys_mmap(frame: &mut SyscallFrame) -> Result {
+    let length = frame.rdi;
+    let protection = frame.rsi;
+    if length == 0 || protection & !(PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let size_in_pages = length
+        .checked_add(PAGE_SIZE - 1)
+        .ok_or(Error::InvalidArgument)? / PAGE_SIZE;
+
+    let mut flags = EntryFlags::empty();
+    if protection & PROT_WRITE != 0 {
+        flags |= EntryFlags::WRITABLE;
+    }
+    if protection & PROT_EXEC == 0 {
+        flags |= EntryFlags::NO_EXECUTE;
+    }
+    memory::controller()
+        .alloc_user_lazy(size_in_pages, flags, "mmap")
+        .map(|region| region.start_address())
+        .ok_or(Error::OutOfMemory)
+}
+
+/// time(): milliseconds since boot
+fn sys_time(_frame: &mut SyscallFrame) -> Result {
+    Ok(pit::ticks() * 1000 / pit::TICKS_PER_SECOND)
+}
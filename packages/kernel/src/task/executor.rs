@@ -0,0 +1,76 @@
+//! Executor polling tasks once they are woken, and halting while none is
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use idle;
+use scheduler;
+use super::{Future, Poll, Waker, READY};
+
+/// Number of tasks that can exist at once, one per bit of READY
+const MAX_TASKS: usize = 64;
+
+/// A future run as a task
+type Task = Box<Future<Output = ()>>;
+
+/// Runs tasks on the thread that calls run. There is a single executor, since wakers only name
+/// a task slot.
+pub struct Executor {
+    /// Task slots, None once their task finished
+    tasks: Vec<Option<Task>>,
+}
+
+impl Executor {
+    /// Create the executor
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub fn new() -> Self {
+        assert_has_not_been_called!("there must be only one executor");
+        Self { tasks: Vec::new() }
+    }
+
+    /// Add a task, which is polled for the first time once run is called
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let task: Task = Box::new(future);
+        let slot = match self.tasks.iter().position(|slot| slot.is_none()) {
+            Some(slot) => {
+                self.tasks[slot] = Some(task);
+                slot
+            }
+            None => {
+                assert!(self.tasks.len() < MAX_TASKS, "too many tasks");
+                self.tasks.push(Some(task));
+                self.tasks.len() - 1
+            }
+        };
+        Waker { task: slot }.wake();
+    }
+
+    /// Poll woken tasks forever, letting other threads run while none is woken and halting the
+    /// processor if there are none either
+    pub fn run(&mut self) -> ! {
+        loop {
+            let ready = READY.swap(0, Ordering::SeqCst);
+            if ready == 0 {
+                scheduler::yield_now();
+                if READY.load(Ordering::SeqCst) == 0 && !scheduler::has_ready_threads() {
+                    idle::idle_until(&READY);
+                }
+                continue;
+            }
+            for (slot, task) in self.tasks.iter_mut().enumerate() {
+                if ready & (1 << slot) == 0 {
+                    continue;
+                }
+                let finished = match *task {
+                    Some(ref mut future) => future.poll(&Waker { task: slot }) == Poll::Ready(()),
+                    None => false,
+                };
+                if finished {
+                    *task = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Cooperative kernel tasks: futures polled by an executor instead of running on threads
+//!
+//! The toolchain predates core::future and async functions, so this module brings its own
+//! Future and Stream traits, and tasks are types implementing them or built from combinators
+//! such as Stream::for_each. A Waker names a task by its slot in the executor, and waking sets
+//! the slot's bit in a mask, which takes neither locks nor allocations and so works from
+//! interrupt handlers.
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+pub use self::executor::Executor;
+
+mod executor;
+pub mod timer;
+
+/// Tasks that have been woken since the executor last polled them, one bit per task slot
+static READY: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Progress of a future
+pub enum Poll<T> {
+    /// The future finished with a value
+    Ready(T),
+    /// The future waits and has arranged to be woken
+    Pending,
+}
+
+/// Computation that finishes at some point. Polls that return Pending must have arranged for
+/// waker to be woken once polling again makes progress.
+pub trait Future {
+    /// Value the future finishes with
+    type Output;
+
+    /// Make as much progress as possible
+    fn poll(&mut self, waker: &Waker) -> Poll<Self::Output>;
+}
+
+/// Sequence of values that become available over time, the asynchronous counterpart of an
+/// iterator
+pub trait Stream {
+    /// Type of the values
+    type Item;
+
+    /// Get the next value, Ready(None) once the stream ended, or Pending after arranging for
+    /// waker to be woken
+    fn poll_next(&mut self, waker: &Waker) -> Poll<Option<Self::Item>>;
+
+    /// Turn the stream into a future calling f with each value, which finishes with the stream
+    fn for_each<F>(self, f: F) -> ForEach<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(Self::Item),
+    {
+        ForEach { stream: self, f: f }
+    }
+}
+
+/// Future calling a function with each value of a stream, created by Stream::for_each
+pub struct ForEach<S, F> {
+    /// The stream
+    stream: S,
+    /// The function
+    f: F,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Handle to wake a task, so that the executor polls it again
+pub struct Waker {
+    /// Slot of the task in the executor
+    task: usize,
+}
+
+/// Tasks to wake together once something happens, such as an interrupt. Registering and waking
+/// take neither locks nor allocations.
+pub struct WakerSet {
+    /// Slots of the registered tasks, one bit each
+    tasks: AtomicUsize,
+}
+
+impl<S, F> Future for ForEach<S, F>
+where
+    S: Stream,
+    F: FnMut(S::Item),
+{
+    type Output = ();
+
+    fn poll(&mut self, waker: &Waker) -> Poll<()> {
+        loop {
+            match self.stream.poll_next(waker) {
+                Poll::Ready(Some(item)) => (self.f)(item),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Waker {
+    /// Make the executor poll the task again
+    pub fn wake(&self) {
+        READY.fetch_or(1 << self.task, Ordering::SeqCst);
+    }
+}
+
+impl WakerSet {
+    /// Create an empty set
+    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default))]
+    pub const fn new() -> Self {
+        Self {
+            tasks: ATOMIC_USIZE_INIT,
+        }
+    }
+
+    /// Add the task of waker to the set
+    pub fn register(&self, waker: &Waker) {
+        self.tasks.fetch_or(1 << waker.task, Ordering::SeqCst);
+    }
+
+    /// Wake every task in the set and empty it
+    pub fn wake_all(&self) {
+        let tasks = self.tasks.swap(0, Ordering::SeqCst);
+        if tasks != 0 {
+            READY.fetch_or(tasks, Ordering::SeqCst);
+        }
+    }
+}
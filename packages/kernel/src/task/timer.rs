@@ -0,0 +1,43 @@
+//! Timer streams, woken by the timer interrupt
+use interrupts::pit;
+use super::{Poll, Stream, WakerSet, Waker};
+
+/// Tasks waiting for a timer to expire
+static WAITERS: WakerSet = WakerSet::new();
+
+/// Stream yielding the tick count every time a period of timer ticks has passed
+pub struct Ticks {
+    /// Number of ticks between values
+    period: usize,
+    /// Tick count at which the next value is due
+    next: usize,
+}
+
+/// Create a stream yielding the tick count every period ticks, starting a period from now
+pub fn every(period: usize) -> Ticks {
+    Ticks {
+        period: period,
+        next: pit::ticks() + period,
+    }
+}
+
+impl Stream for Ticks {
+    type Item = usize;
+
+    fn poll_next(&mut self, waker: &Waker) -> Poll<Option<usize>> {
+        // Registering first means a tick right after the check still wakes the task
+        WAITERS.register(waker);
+        let now = pit::ticks();
+        if now < self.next {
+            return Poll::Pending;
+        }
+        self.next = now + self.period;
+        Poll::Ready(Some(now))
+    }
+}
+
+/// Wake the tasks waiting for a timer, so they can check whether it expired. Called from the
+/// timer interrupt handler.
+pub fn tick() {
+    WAITERS.wake_all();
+}
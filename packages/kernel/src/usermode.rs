@@ -0,0 +1,95 @@
+//! Running code in user mode (ring 3)
+use core::{ptr, slice};
+use interrupts;
+use memory::{self, EntryFlags, Page, PAGE_SIZE};
+use syscall::SyscallFrame;
+
+/// Start of the user part of the address space, right after the kernel's P4 entry
+pub const USER_SPACE_START: usize = 0o0_001_000_000_000_000;
+/// End (exclusive) of the user part of the address space, where the higher half starts
+pub const USER_SPACE_END: usize = 0o0_400_000_000_000_000;
+/// Address flat user programs are loaded at
+const FLAT_PROGRAM_ADDRESS: usize = USER_SPACE_START;
+/// Initial user stack pointer, leaving an unmapped page at the end of user space
+pub const USER_STACK_TOP: usize = USER_SPACE_END - PAGE_SIZE;
+/// CPU flags user mode always runs with: interrupts enabled, plus the reserved bit
+const USER_FLAGS: usize = 0x202;
+
+extern "C" {
+    /// Start of the demo user program. Defined in user_demo.asm.
+    static user_demo_start: u8;
+    /// End of the demo user program. Defined in user_demo.asm.
+    static user_demo_end: u8;
+
+    /// Switch to ring 3 at entry with the given stack. Defined in usermode.asm.
+    fn enter_user_mode(
+        entry: usize,
+        stack_pointer: usize,
+        code_selector: u64,
+        data_selector: u64,
+    ) -> !;
+
+    /// Switch to ring 3 with the registers in frame. Defined in usermode.asm.
+    fn return_to_user_mode(
+        frame: *const SyscallFrame,
+        code_selector: u64,
+        data_selector: u64,
+    ) -> !;
+}
+
+/// Switch the current thread to user mode, continuing at entry with the given stack pointer.
+/// Interrupts and system calls from user mode run on the thread's kernel stack.
+pub unsafe fn enter(entry: usize, stack_pointer: usize) -> ! {
+    assert!(entry >= USER_SPACE_START && entry < USER_SPACE_END);
+    let selectors = interrupts::selectors();
+    enter_user_mode(
+        entry,
+        stack_pointer,
+        u64::from(selectors.user_code.0),
+        u64::from(selectors.user_data.0),
+    )
+}
+
+/// Switch the current thread to user mode with the registers saved in frame, as if the system
+/// call it describes was returning. Used to start forked processes.
+pub unsafe fn resume(frame: &SyscallFrame) -> ! {
+    assert!(frame.rip >= USER_SPACE_START && frame.rip < USER_SPACE_END);
+    let mut frame = frame.clone();
+    frame.rflags |= USER_FLAGS;
+    let selectors = interrupts::selectors();
+    return_to_user_mode(
+        &frame,
+        u64::from(selectors.user_code.0),
+        u64::from(selectors.user_data.0),
+    )
+}
+
+/// Get the machine code of the demo user program
+pub fn demo_program() -> &'static [u8] {
+    unsafe {
+        let start = &user_demo_start as *const u8;
+        let end = &user_demo_end as *const u8;
+        slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+/// Load a position independent flat binary of at most one page into user space and run it in
+/// user mode on the current thread, which must be a spawned thread
+pub fn run_flat_program(code: &[u8]) -> ! {
+    assert!(code.len() <= PAGE_SIZE, "flat programs must fit in a page");
+    let code_page = Page::containing_address(FLAT_PROGRAM_ADDRESS);
+    let stack_page = Page::containing_address(USER_STACK_TOP - PAGE_SIZE);
+    {
+        let mut memory = memory::controller();
+        memory.map_zeroed(code_page, EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE);
+        unsafe {
+            ptr::copy_nonoverlapping(code.as_ptr(), FLAT_PROGRAM_ADDRESS as *mut u8, code.len());
+        }
+        memory.set_page_flags(code_page, EntryFlags::USER_ACCESSIBLE);
+        memory.map_zeroed(
+            stack_page,
+            EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+        );
+    }
+    unsafe { enter(FLAT_PROGRAM_ADDRESS, USER_STACK_TOP) }
+}
@@ -4,6 +4,7 @@ extern crate spin;
 extern crate volatile;
 
 use volatile::Volatile;
+use sync::SpinLock;
 use core::ptr::Unique;
 use core::fmt;
 use core::fmt::Write;
@@ -169,14 +170,28 @@ impl fmt::Write for Writer {
     }
 }
 
-/// The global VGA text mode writer. Must be wrapped in a spin lock because
-/// things will go badly if we try and write from more than one thread at once,
-/// and we don't have a better locking mechanism yet.
-pub static WRITER: spin::Mutex<Writer> = spin::Mutex::new(Writer {
-    column_position: 0,
-    color_code: ColorCode::new(Color::LightGray, Color::Black),
-    buffer: unsafe { Unique::new_unchecked(0xb_8000 as *mut _) },
-});
+/// The global VGA text mode writer. Its lock keeps interrupts disabled, so that interrupt
+/// handlers can print while the code they interrupted does.
+pub static WRITER: SpinLock<Writer> = SpinLock::new(
+    "vga writer",
+    Writer {
+        column_position: 0,
+        color_code: ColorCode::new(Color::LightGray, Color::Black),
+        buffer: unsafe { Unique::new_unchecked(0xb_8000 as *mut _) },
+    },
+);
+
+/// Create a writer that draws on a new line in white on red, without taking WRITER's lock.
+/// Meant for the emergency console only, since its output mixes with WRITER's.
+pub fn emergency_writer() -> Writer {
+    let mut writer = Writer {
+        column_position: 0,
+        color_code: ColorCode::new(Color::White, Color::Red),
+        buffer: unsafe { Unique::new_unchecked(0xb_8000 as *mut _) },
+    };
+    writer.new_line();
+    writer
+}
 
 macro_rules! print {
     ($($arg:tt)*) => ({
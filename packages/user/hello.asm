@@ -0,0 +1,64 @@
+global _start
+
+; First user program, loaded from the initrd. Uses the system call numbers from
+; packages/kernel/src/syscall.rs.
+section .text
+bits 64
+_start:
+	; write(1, message, message_length)
+	mov eax, 1
+	mov edi, 1
+	lea rsi, [rel message]
+	mov edx, message_length
+	syscall
+
+	; open(motd_path, motd_path_length, 0)
+	mov eax, 7
+	lea rdi, [rel motd_path]
+	mov esi, motd_path_length
+	xor edx, edx
+	syscall
+	test rax, rax
+	js .exit
+	mov rbx, rax
+
+	; Copy the file to the console through a buffer on the stack
+	sub rsp, 256
+.copy:
+	; read(fd, buffer, 256)
+	mov eax, 9
+	mov rdi, rbx
+	mov rsi, rsp
+	mov edx, 256
+	syscall
+	test rax, rax
+	jle .close
+
+	; write(1, buffer, read)
+	mov rdx, rax
+	mov eax, 1
+	mov edi, 1
+	mov rsi, rsp
+	syscall
+	jmp .copy
+
+.close:
+	; close(fd)
+	mov eax, 8
+	mov rdi, rbx
+	syscall
+
+.exit:
+	; exit(getpid())
+	mov eax, 5
+	syscall
+	mov rdi, rax
+	mov eax, 0
+	syscall
+
+; The strings live in a data segment, which the linker puts into the page the code ends in
+section .data
+message: db "Hello from an ELF program", 10
+message_length: equ $ - message
+motd_path: db "/etc/motd"
+motd_path_length: equ $ - motd_path
@@ -0,0 +1,54 @@
+global _start
+
+; First process: starts /bin/hello with fork and exec, then waits for it. Uses the system call
+; numbers from packages/kernel/src/syscall.rs.
+section .text
+bits 64
+_start:
+	; fork()
+	mov eax, 12
+	syscall
+	test rax, rax
+	js .failed
+	jz .child
+	mov rbx, rax
+
+	; waitpid(child, status)
+	sub rsp, 16
+	mov eax, 14
+	mov rdi, rbx
+	mov rsi, rsp
+	syscall
+	cmp rax, rbx
+	jne .failed
+
+	; write(1, message, message_length)
+	mov eax, 1
+	mov edi, 1
+	lea rsi, [rel message]
+	mov edx, message_length
+	syscall
+
+	; exit(0)
+	mov eax, 0
+	xor edi, edi
+	syscall
+
+.child:
+	; exec(hello_path, hello_path_length)
+	mov eax, 13
+	lea rdi, [rel hello_path]
+	mov esi, hello_path_length
+	syscall
+	; Only reached if exec failed
+
+.failed:
+	; exit(1)
+	mov eax, 0
+	mov edi, 1
+	syscall
+
+message: db "init: child exited", 10
+message_length: equ $ - message
+hello_path: db "/bin/hello"
+hello_path_length: equ $ - hello_path
@@ -0,0 +1,16 @@
+global _start
+
+; Power the machine off, which only init may do. Uses the system call numbers from
+; packages/kernel/src/syscall.rs.
+section .text
+bits 64
+_start:
+	; reboot(REBOOT_POWER_OFF)
+	mov eax, 15
+	mov edi, 2
+	syscall
+
+	; exit(1), only reached if that failed
+	mov eax, 0
+	mov edi, 1
+	syscall